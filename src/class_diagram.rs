@@ -2,11 +2,20 @@
 //!
 //! Supports PlantUML class diagram syntax including:
 //! - Classes, interfaces, abstract classes, enums
+//! - Packages and namespaces, including nested ones and dotted names (`net.core.Socket`)
+//! - Generic type parameters with bounds (`class HashMap<K: Hash, V>`)
 //! - Fields and methods with visibility modifiers
 //! - Relationships: inheritance, realization, composition, aggregation, association
 
-use crate::common::{escape_xml, DiagramStyle, SvgBuilder};
-use std::collections::HashMap;
+use crate::class_lexer::{arrow_infix, column, tokenize, ParseError, Tok, Token};
+use crate::common::{escape_xml, DiagramStyle, LayoutDirection, Rect, SvgBuilder};
+use crate::edge_router::{route_edges, Axis, RouteRequest};
+use crate::json::{self, JsonValue};
+use crate::label_placement::{place_labels, LabelRequest};
+use crate::layout_engine::{arrange, LayeredLayout, LayoutEdge, LayoutEngine, LayoutGraph};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 // ============================================================================
 // Data Types
@@ -32,6 +41,18 @@ impl Visibility {
         }
     }
 
+    /// Strip a Rust visibility: `pub` is public, `pub(crate)`, `pub(super)`
+    /// and `pub(in path)` are package-level
+    fn strip_rust(text: &str) -> Option<(Self, &str)> {
+        let rest = text.strip_prefix("pub")?;
+        if let Some(restricted) = rest.strip_prefix('(') {
+            let close = restricted.find(')')?;
+            return Some((Self::Package, restricted[close + 1..].trim_start()));
+        }
+        rest.starts_with(char::is_whitespace)
+            .then(|| (Self::Public, rest.trim_start()))
+    }
+
     fn symbol(&self) -> &'static str {
         match self {
             Self::Public => "+",
@@ -62,6 +83,73 @@ pub struct Method {
     pub is_abstract: bool,
 }
 
+/// A generic type parameter, e.g. `T: Clone`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeParam {
+    pub name: String,
+    pub bounds: Option<String>,
+}
+
+impl TypeParam {
+    fn label(&self) -> String {
+        match &self.bounds {
+            Some(b) => format!("{}: {}", self.name, b),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Data carried by an enum variant
+#[derive(Debug, Clone, PartialEq)]
+pub enum VariantData {
+    /// `RED`
+    Unit,
+    /// `Some(T)`, holding the text between the parentheses
+    Tuple(String),
+    /// `Move { x: i32 }`, holding the text between the braces
+    Struct(String),
+}
+
+/// An enum literal (variant)
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumLiteral {
+    pub name: String,
+    pub data: VariantData,
+}
+
+impl EnumLiteral {
+    /// Parse `RED`, `Some(T)`, `RED("r")` or `Move { x: i32 }`
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (name, data) = if let Some(open) = text.find('(') {
+            let inner = text[open + 1..].trim_end().strip_suffix(')')?;
+            (&text[..open], VariantData::Tuple(inner.trim().to_string()))
+        } else if let Some(open) = text.find('{') {
+            let inner = text[open + 1..].trim_end().strip_suffix('}')?;
+            (&text[..open], VariantData::Struct(inner.trim().to_string()))
+        } else {
+            (text, VariantData::Unit)
+        };
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            data,
+        })
+    }
+
+    /// Payload as displayed after the name
+    fn data_label(&self) -> String {
+        match &self.data {
+            VariantData::Unit => String::new(),
+            VariantData::Tuple(inner) => format!("({})", inner),
+            VariantData::Struct(inner) => format!(" {{ {} }}", inner),
+        }
+    }
+}
+
 /// Type of class-like element
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ClassType {
@@ -69,6 +157,67 @@ pub enum ClassType {
     Interface,
     Abstract,
     Enum,
+    Struct,
+    Trait,
+    Union,
+    TypeAlias,
+}
+
+impl ClassType {
+    /// Kind named by a declaration keyword
+    fn from_keyword(word: &str) -> Option<Self> {
+        match word {
+            "class" => Some(Self::Class),
+            "interface" => Some(Self::Interface),
+            "abstract" => Some(Self::Abstract),
+            "enum" => Some(Self::Enum),
+            "struct" => Some(Self::Struct),
+            "trait" => Some(Self::Trait),
+            "union" => Some(Self::Union),
+            "type" => Some(Self::TypeAlias),
+            _ => None,
+        }
+    }
+
+    /// Kind of a declaration line, skipping a Rust visibility such as `pub(crate)`
+    fn from_decl(line: &str) -> Option<Self> {
+        let line = Visibility::strip_rust(line).map_or(line, |(_, rest)| rest);
+        let (word, rest) = line.split_once(char::is_whitespace)?;
+        let kind = Self::from_keyword(word)?;
+        let rest = rest.trim();
+        match kind {
+            // These words also start ordinary sentences, such as a note line
+            // reading "type your password", so they need a real declaration
+            Self::Struct | Self::Trait | Self::Union | Self::TypeAlias => declares_name(rest),
+            _ => !rest.is_empty(),
+        }
+        .then_some(kind)
+    }
+
+    /// CSS class of the box, also used to name its shadow filter
+    fn css_class(self) -> &'static str {
+        match self {
+            Self::Class => "class",
+            Self::Interface => "interface",
+            Self::Abstract => "abstract-class",
+            Self::Enum => "enum",
+            Self::Struct => "struct",
+            Self::Trait => "trait",
+            Self::Union => "union",
+            Self::TypeAlias => "type-alias",
+        }
+    }
+
+    /// Letter drawn in the circled spot before the name, for the Rust kinds
+    fn spot(self) -> Option<char> {
+        match self {
+            Self::Struct => Some('S'),
+            Self::Trait => Some('T'),
+            Self::Union => Some('U'),
+            Self::TypeAlias => Some('A'),
+            _ => None,
+        }
+    }
 }
 
 /// A class definition
@@ -78,13 +227,92 @@ pub struct ClassDef {
     pub class_type: ClassType,
     pub fields: Vec<Field>,
     pub methods: Vec<Method>,
+    /// Enum literals, drawn in their own compartment
+    pub literals: Vec<EnumLiteral>,
+    /// Target of a `type Foo = Bar;` alias
+    pub aliased: Option<String>,
     pub stereotype: Option<String>,
+    /// Generic type parameters, drawn in the template box
+    pub generics: Vec<TypeParam>,
+    /// Full dotted path of the enclosing package
+    pub package: Option<String>,
+    /// `$tag` names, used to select the class in `hide`/`show`/`remove`
+    pub tags: Vec<String>,
+    /// Laid out but not drawn (`hide Foo`)
+    pub hidden: bool,
+    /// Leave out the kind letter (`hide circle`)
+    pub hide_spot: bool,
+    /// Shrink the box to its header when it has no members (`hide empty members`)
+    pub hide_empty_members: bool,
+    /// Fixed top-left corner (`class Foo @ 120,40` or a layout file), relative
+    /// to the content area of the enclosing package if there is one
+    pub pinned: Option<(f32, f32)>,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ClassDef {
+    /// The class box as laid out
+    fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.width, self.height)
+    }
+
+    /// Circled kind letter, unless hidden
+    pub fn spot(&self) -> Option<char> {
+        if self.hide_spot {
+            None
+        } else {
+            self.class_type.spot()
+        }
+    }
+
+    /// Name shown in the class header, without the package prefix of dotted names
+    pub fn display_name(&self) -> &str {
+        match (&self.package, self.name.rsplit_once('.')) {
+            (Some(_), Some((_, short))) => short,
+            _ => &self.name,
+        }
+    }
+
+    /// Text of the UML template box (`K, V: Clone`), if the class is generic
+    pub fn template_label(&self) -> Option<String> {
+        if self.generics.is_empty() {
+            return None;
+        }
+        let params: Vec<String> = self.generics.iter().map(TypeParam::label).collect();
+        Some(params.join(", "))
+    }
+}
+
+/// A package or namespace grouping classes
+#[derive(Debug, Clone)]
+pub struct Package {
+    /// Full dotted path, e.g. `net.core`
+    pub name: String,
     pub x: f32,
     pub y: f32,
     pub width: f32,
     pub height: f32,
 }
 
+impl Package {
+    /// Full path of the enclosing package
+    pub fn parent(&self) -> Option<&str> {
+        self.name.rsplit_once('.').map(|(parent, _)| parent)
+    }
+
+    /// Name shown on the folder tab (the last path segment)
+    pub fn display_name(&self) -> &str {
+        self.name.rsplit_once('.').map_or(&self.name, |(_, n)| n)
+    }
+
+    fn depth(&self) -> usize {
+        self.name.matches('.').count()
+    }
+}
+
 /// Relationship type
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RelationType {
@@ -95,6 +323,174 @@ pub enum RelationType {
     Association,   // --
     Dependency,    // ..>
     DirectedAssoc, // -->
+    Nested,        // +--
+    Link,          // ..
+    Bidirectional, // <-->, <|--|>
+    Entity,        // }o--||
+}
+
+impl RelationType {
+    /// Markers drawn at the `from` and `to` ends when the arrow gives none
+    pub fn markers(self) -> (EndMarker, EndMarker) {
+        match self {
+            Self::Inheritance | Self::Realization => (EndMarker::None, EndMarker::Triangle),
+            Self::Composition => (EndMarker::DiamondFilled, EndMarker::None),
+            Self::Aggregation => (EndMarker::DiamondEmpty, EndMarker::None),
+            Self::Dependency | Self::DirectedAssoc => (EndMarker::None, EndMarker::Arrow),
+            Self::Nested => (EndMarker::Plus, EndMarker::None),
+            Self::Bidirectional => (EndMarker::Arrow, EndMarker::Arrow),
+            Self::Entity => (EndMarker::ExactlyOne, EndMarker::ExactlyOne),
+            Self::Association | Self::Link => (EndMarker::None, EndMarker::None),
+        }
+    }
+
+    pub fn is_dashed(self) -> bool {
+        matches!(self, Self::Realization | Self::Dependency | Self::Link)
+    }
+}
+
+/// Head drawn at one end of a relationship line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndMarker {
+    None,
+    Triangle,      // <|
+    Arrow,         // <
+    DiamondFilled, // *
+    DiamondEmpty,  // o
+    Plus,          // +
+    Square,        // #
+    Cross,         // x
+    ZeroOrOne,     // |o
+    ExactlyOne,    // ||
+    ZeroOrMany,    // }o
+    OneOrMany,     // }|
+}
+
+impl EndMarker {
+    /// Parse the head on the left of an arrow (`<|`, `*`, `}o`, ...)
+    fn from_left(head: &str) -> Option<Self> {
+        Some(match head {
+            "" => Self::None,
+            "<|" => Self::Triangle,
+            "<" => Self::Arrow,
+            "}o" => Self::ZeroOrMany,
+            "}|" => Self::OneOrMany,
+            "|o" => Self::ZeroOrOne,
+            "||" => Self::ExactlyOne,
+            _ => return Self::symmetric(head),
+        })
+    }
+
+    /// Parse the head on the right of an arrow (`|>`, `*`, `o{`, ...)
+    fn from_right(head: &str) -> Option<Self> {
+        Some(match head {
+            "" => Self::None,
+            "|>" => Self::Triangle,
+            ">" => Self::Arrow,
+            "o{" => Self::ZeroOrMany,
+            "|{" => Self::OneOrMany,
+            "o|" => Self::ZeroOrOne,
+            "||" => Self::ExactlyOne,
+            _ => return Self::symmetric(head),
+        })
+    }
+
+    fn symmetric(head: &str) -> Option<Self> {
+        Some(match head {
+            "*" => Self::DiamondFilled,
+            "o" => Self::DiamondEmpty,
+            "+" => Self::Plus,
+            "#" => Self::Square,
+            "x" => Self::Cross,
+            _ => return None,
+        })
+    }
+
+    fn is_crows_foot(self) -> bool {
+        matches!(
+            self,
+            Self::ZeroOrOne | Self::ExactlyOne | Self::ZeroOrMany | Self::OneOrMany
+        )
+    }
+
+    /// Id of the `<marker>` in the diagram's `<defs>`
+    fn marker_id(self) -> Option<&'static str> {
+        Some(match self {
+            Self::None => return None,
+            Self::Triangle => "cls-triangle",
+            Self::Arrow => "cls-arrow",
+            Self::DiamondFilled => "cls-diamond-filled",
+            Self::DiamondEmpty => "cls-diamond-empty",
+            Self::Plus => "cls-plus",
+            Self::Square => "cls-square",
+            Self::Cross => "cls-cross",
+            Self::ZeroOrOne => "cls-zero-or-one",
+            Self::ExactlyOne => "cls-exactly-one",
+            Self::ZeroOrMany => "cls-zero-or-many",
+            Self::OneOrMany => "cls-one-or-many",
+        })
+    }
+}
+
+/// Multiplicity and role shown at one end of a relationship
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelationEnd {
+    /// Cardinality such as `1`, `0..*` or `many`
+    pub multiplicity: Option<String>,
+    /// Role name of the class at this end
+    pub role: Option<String>,
+    /// Text anchor of the multiplicity, placed by the layout
+    pub multiplicity_at: Option<(f32, f32)>,
+    /// Text anchor of the role, placed by the layout
+    pub role_at: Option<(f32, f32)>,
+}
+
+impl RelationEnd {
+    /// Split quoted end text: `"0..*"` is a multiplicity, `"items 0..*"` a role
+    /// followed by a multiplicity, anything else a role
+    fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if text.is_empty() {
+            return Self::default();
+        }
+        if is_multiplicity(text) {
+            return Self {
+                multiplicity: Some(text.to_string()),
+                ..Self::default()
+            };
+        }
+        match text.rsplit_once(char::is_whitespace) {
+            Some((role, mult)) if is_multiplicity(mult) => Self {
+                multiplicity: Some(mult.to_string()),
+                role: Some(role.trim().to_string()),
+                ..Self::default()
+            },
+            _ => Self {
+                role: Some(text.to_string()),
+                ..Self::default()
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.multiplicity.is_none() && self.role.is_none()
+    }
+}
+
+fn is_multiplicity(text: &str) -> bool {
+    matches!(text, "many" | "n" | "m")
+        || text
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '*' | '.' | ','))
+}
+
+/// Which way the middle label reads, relative to `from` -> `to`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelDirection {
+    /// `label >` read from `from` towards `to`
+    Forward,
+    /// `label <` read from `to` towards `from`
+    Backward,
 }
 
 /// A relationship between classes
@@ -104,6 +500,134 @@ pub struct Relationship {
     pub to: String,
     pub rel_type: RelationType,
     pub label: Option<String>,
+    /// Reading-direction arrow drawn beside the label
+    pub label_direction: Option<LabelDirection>,
+    pub from_end: RelationEnd,
+    pub to_end: RelationEnd,
+    pub from_marker: EndMarker,
+    pub to_marker: EndMarker,
+    pub dashed: bool,
+    /// Field, method or literal row the line leaves from (`Order::customer_id`)
+    pub from_member: Option<String>,
+    /// Row the line arrives at
+    pub to_member: Option<String>,
+    /// Where the layout should put `to` relative to `from` (`-up->`)
+    pub direction: Option<Direction>,
+    /// Only used to space the layout (`-[hidden]-`), never drawn
+    pub hidden: bool,
+    /// Points of the drawn line, filled in by the layout
+    pub path: Vec<(f32, f32)>,
+    /// Text anchor of the label, placed by the layout
+    pub label_at: Option<(f32, f32)>,
+}
+
+/// Layout direction hint written inside an arrow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn reversed(self) -> Self {
+        match self {
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+
+    /// The same hint with the axes swapped, for a left-to-right layout
+    fn transposed(self) -> Self {
+        match self {
+            Self::Up => Self::Left,
+            Self::Down => Self::Right,
+            Self::Left => Self::Up,
+            Self::Right => Self::Down,
+        }
+    }
+}
+
+impl Relationship {
+    fn new(from: String, to: String, rel_type: RelationType) -> Self {
+        let (from_marker, to_marker) = rel_type.markers();
+        Self {
+            from,
+            to,
+            rel_type,
+            label: None,
+            label_direction: None,
+            from_end: RelationEnd::default(),
+            to_end: RelationEnd::default(),
+            from_marker,
+            to_marker,
+            dashed: rel_type.is_dashed(),
+            from_member: None,
+            to_member: None,
+            direction: None,
+            hidden: false,
+            path: Vec::new(),
+            label_at: None,
+        }
+    }
+}
+
+/// Side of a class a note is attached to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteSide {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// What a note is attached to
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteTarget {
+    /// `note left of Foo`
+    Class { name: String, side: NoteSide },
+    /// `note on link`, by index into `ClassDiagram::relationships`
+    Link(usize),
+    /// `note "text" as N1`, connected through `links`
+    Free,
+}
+
+/// A note box with a folded corner
+#[derive(Debug, Clone)]
+pub struct Note {
+    /// Alias from `as N1`, used to connect free notes
+    pub alias: Option<String>,
+    /// Note text; lines are separated by `\n`
+    pub text: String,
+    pub target: NoteTarget,
+    /// Classes connected to the note with `N1 .. Foo`
+    pub links: Vec<String>,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Note {
+    fn new(alias: Option<String>, text: String, target: NoteTarget) -> Self {
+        Self {
+            alias,
+            text,
+            target,
+            links: Vec::new(),
+            x: 0.0,
+            y: 0.0,
+            width: 0.0,
+            height: 0.0,
+        }
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &str> {
+        self.text.lines()
+    }
 }
 
 /// Parsed class diagram
@@ -111,6 +635,211 @@ pub struct Relationship {
 pub struct ClassDiagram {
     pub classes: Vec<ClassDef>,
     pub relationships: Vec<Relationship>,
+    pub packages: Vec<Package>,
+    pub notes: Vec<Note>,
+    /// Classes the layout keeps next to each other (`together { A B }`)
+    pub together: Vec<Vec<String>>,
+    /// `left to right direction` / `top to bottom direction`; overrides the
+    /// style's direction when set
+    pub direction: Option<LayoutDirection>,
+}
+
+impl ClassDiagram {
+    /// Parse class diagram source, failing with every syntax error found.
+    ///
+    /// Rendering is lenient and skips statements it cannot parse; use this to
+    /// validate a diagram first.
+    pub fn parse(source: &str) -> Result<Self, Vec<ParseError>> {
+        let (diagram, errors) = Parser::new().parse_checked(source);
+        if errors.is_empty() {
+            Ok(diagram)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Pin classes to the positions in a layout file: a JSON object mapping
+    /// class names to `{"x": .., "y": ..}`, as written by [`Self::layout_json`].
+    ///
+    /// Names that are not in the diagram are skipped, so a file keeps working
+    /// while the model changes.
+    pub fn apply_layout(&mut self, json: &str) -> Result<(), LayoutFileError> {
+        let error = |message: String| LayoutFileError { message };
+        let value = json::parse(json).map_err(|e| error(e.to_string()))?;
+        let JsonValue::Object(entries) = value else {
+            return Err(error("expected an object of class positions".to_string()));
+        };
+        // Check the whole file before pinning anything
+        let mut pins = Vec::with_capacity(entries.len());
+        for (name, at) in &entries {
+            let coordinate = |key| at.get(key).and_then(JsonValue::as_f64);
+            let (Some(x), Some(y)) = (coordinate("x"), coordinate("y")) else {
+                return Err(error(format!("`{}` needs numeric \"x\" and \"y\"", name)));
+            };
+            if x < 0.0 || y < 0.0 {
+                return Err(error(format!("`{}` has a negative position", name)));
+            }
+            pins.push((name, (x as f32, y as f32)));
+        }
+        for (name, at) in pins {
+            if let Some(class) = self.classes.iter_mut().find(|c| &c.name == name) {
+                class.pinned = Some(at);
+            }
+        }
+        Ok(())
+    }
+
+    /// Positions of all classes after [`Self::layout`], as a layout file that
+    /// [`Self::apply_layout`] reads back
+    pub fn layout_json(&self) -> String {
+        let entries = self
+            .classes
+            .iter()
+            .map(|class| {
+                let (ox, oy) = self.content_origin(class.package.as_deref());
+                let round = |v: f32| JsonValue::from((f64::from(v) * 100.0).round() / 100.0);
+                let at = JsonValue::Object(vec![
+                    ("x".to_string(), round(class.x - ox)),
+                    ("y".to_string(), round(class.y - oy)),
+                ]);
+                (class.name.clone(), at)
+            })
+            .collect();
+        json::to_string_pretty(&JsonValue::Object(entries))
+    }
+
+    /// Top-left corner of a package's content area, which pins inside it
+    /// are relative to; the diagram's own origin for `None`
+    fn content_origin(&self, package: Option<&str>) -> (f32, f32) {
+        package
+            .and_then(|name| self.packages.iter().find(|p| p.name == name))
+            .map_or((0.0, 0.0), |p| {
+                (
+                    p.x + PACKAGE_PADDING,
+                    p.y + PACKAGE_TAB_HEIGHT + PACKAGE_PADDING,
+                )
+            })
+    }
+
+    /// Class with the given name, unless it is hidden
+    fn visible_class(&self, name: &str) -> Option<&ClassDef> {
+        self.classes.iter().find(|c| c.name == name && !c.hidden)
+    }
+}
+
+/// Error raised while reading a layout file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutFileError {
+    pub message: String,
+}
+
+impl fmt::Display for LayoutFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "layout file: {}", self.message)
+    }
+}
+
+impl std::error::Error for LayoutFileError {}
+
+/// `hide`, `show` or `remove`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DisplayAction {
+    Hide,
+    Show,
+    Remove,
+}
+
+/// Classes a display command applies to
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    /// `hide methods`: every class
+    All,
+    /// `hide Foo`
+    Class(String),
+    /// `hide <<Serializable>> circle`
+    Stereotype(String),
+    /// `hide $internal`
+    Tag(String),
+    /// `hide enum methods`
+    Kind(ClassType),
+    /// `remove @unlinked`: classes without relationships
+    Unlinked,
+}
+
+/// Part of a class a display command applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DisplayPart {
+    /// The class itself
+    Element,
+    Members,
+    Fields,
+    Methods,
+    Spot,
+    Stereotype,
+}
+
+impl DisplayPart {
+    fn from_keyword(word: &str) -> Option<Self> {
+        match word {
+            "members" => Some(Self::Members),
+            "fields" | "attributes" => Some(Self::Fields),
+            "methods" => Some(Self::Methods),
+            "circle" | "spot" => Some(Self::Spot),
+            "stereotype" | "stereotypes" => Some(Self::Stereotype),
+            _ => None,
+        }
+    }
+}
+
+/// One `hide`/`show`/`remove` command; later commands override earlier ones
+#[derive(Debug, Clone)]
+struct DisplayRule {
+    action: DisplayAction,
+    selector: Selector,
+    part: DisplayPart,
+    /// `hide empty members` only affects classes without members
+    empty_only: bool,
+}
+
+/// What the display commands leave of one class
+#[derive(Debug, Default)]
+struct Display {
+    removed: bool,
+    hidden: bool,
+    members: bool,
+    fields: bool,
+    methods: bool,
+    spot: bool,
+    stereotype: bool,
+    empty_members: bool,
+}
+
+impl Display {
+    fn apply(&mut self, rule: &DisplayRule) {
+        let on = rule.action != DisplayAction::Show;
+        match (rule.action, rule.part) {
+            (DisplayAction::Remove, _) => self.removed = true,
+            (DisplayAction::Show, DisplayPart::Element) => {
+                self.removed = false;
+                self.hidden = false;
+            }
+            (_, DisplayPart::Element) => self.hidden = on,
+            (_, DisplayPart::Members | DisplayPart::Fields | DisplayPart::Methods)
+                if rule.empty_only =>
+            {
+                self.empty_members = on
+            }
+            (_, DisplayPart::Members) => {
+                self.members = on;
+                self.fields = on;
+                self.methods = on;
+            }
+            (_, DisplayPart::Fields) => self.fields = on,
+            (_, DisplayPart::Methods) => self.methods = on,
+            (_, DisplayPart::Spot) => self.spot = on,
+            (_, DisplayPart::Stereotype) => self.stereotype = on,
+        }
+    }
 }
 
 // ============================================================================
@@ -119,26 +848,88 @@ pub struct ClassDiagram {
 
 struct Parser {
     classes: HashMap<String, ClassDef>,
+    /// Class keys in declaration order, which the diagram and layout keep
+    class_order: Vec<String>,
     relationships: Vec<Relationship>,
     current_class: Option<String>,
+    packages: Vec<Package>,
+    /// Full paths of the open `package`/`namespace` blocks, innermost last
+    package_stack: Vec<String>,
+    notes: Vec<Note>,
+    /// Multi-line note collecting text until `end note`
+    open_note: Option<Note>,
+    /// Set once an enum body's literal list has ended (`;` or a separator)
+    literals_done: bool,
+    /// Struct variant spanning several lines: name and fields so far
+    open_variant: Option<(String, Vec<String>)>,
+    /// `class "Long Name" as LN`: alias -> class key
+    aliases: HashMap<String, String>,
+    /// `hide`/`show`/`remove` commands, applied once parsing is done
+    display_rules: Vec<DisplayRule>,
+    together: Vec<Vec<String>>,
+    /// Open `together {` block: classes so far and the package depth it opened at
+    open_together: Option<(Vec<String>, usize)>,
+    direction: Option<LayoutDirection>,
+    errors: Vec<ParseError>,
+    /// Position of the line being parsed, for error reporting
+    line_no: usize,
+    indent: usize,
+}
+
+/// Where a `{` on a declaration line leaves the body
+enum Body<'a> {
+    /// No body, or `{}`
+    None,
+    /// `{` ends the line; members follow on their own lines
+    Open,
+    /// `{ ... }` on one line
+    Inline(&'a str, usize),
+    /// `{ first member` with the rest on following lines
+    OpenWith(&'a str, usize),
 }
 
 impl Parser {
     fn new() -> Self {
         Self {
             classes: HashMap::new(),
+            class_order: Vec::new(),
             relationships: Vec::new(),
             current_class: None,
+            packages: Vec::new(),
+            package_stack: Vec::new(),
+            notes: Vec::new(),
+            open_note: None,
+            literals_done: false,
+            open_variant: None,
+            aliases: HashMap::new(),
+            display_rules: Vec::new(),
+            together: Vec::new(),
+            open_together: None,
+            direction: None,
+            errors: Vec::new(),
+            line_no: 0,
+            indent: 0,
         }
     }
 
-    fn parse(mut self, source: &str) -> ClassDiagram {
+    fn parse(self, source: &str) -> ClassDiagram {
+        self.parse_checked(source).0
+    }
+
+    /// Parse, also returning every syntax error found along the way
+    fn parse_checked(mut self, source: &str) -> (ClassDiagram, Vec<ParseError>) {
         let mut in_diagram = false;
 
-        for line in source.lines() {
-            let line = line.trim();
+        for (index, raw) in source.lines().enumerate() {
+            let line = raw.trim();
+            self.line_no = index + 1;
+            self.indent = raw.len() - raw.trim_start().len();
 
-            if line.is_empty() || line.starts_with("//") || line.starts_with("skinparam") {
+            if line.is_empty()
+                || line.starts_with("//")
+                || line.starts_with('\'')
+                || line.starts_with("skinparam")
+            {
                 continue;
             }
 
@@ -155,106 +946,938 @@ impl Parser {
             }
         }
 
-        ClassDiagram {
-            classes: self.classes.into_values().collect(),
-            relationships: self.relationships,
+        self.indent = 0;
+        if self.open_note.is_some() {
+            self.error(1, "note is missing `end note`");
+        }
+        if let Some(class) = self.current_class.clone() {
+            self.error(
+                1,
+                format!("body of `{}` is missing its closing '}}'", class),
+            );
+        }
+        if self.open_together.is_some() {
+            self.error(1, "`together` block is missing its closing '}'");
+        }
+        if let Some(package) = self.package_stack.last().cloned() {
+            self.error(
+                1,
+                format!("package `{}` is missing its closing '}}'", package),
+            );
         }
+
+        self.apply_display_rules();
+
+        let diagram = ClassDiagram {
+            classes: self
+                .class_order
+                .iter()
+                .filter_map(|name| self.classes.remove(name))
+                .collect(),
+            relationships: self.relationships,
+            packages: self.packages,
+            notes: self.notes,
+            together: self.together,
+            direction: self.direction,
+        };
+        (diagram, self.errors)
+    }
+
+    /// Record an error at a 1-based column of the current (trimmed) line
+    fn error(&mut self, column: usize, message: impl Into<String>) {
+        self.errors.push(ParseError {
+            line: self.line_no,
+            column: self.indent + column,
+            message: message.into(),
+        });
     }
 
     fn parse_line(&mut self, line: &str) {
-        // End of class body
-        if line == "}" {
-            self.current_class = None;
+        // Body of a multi-line note
+        if let Some(note) = self.open_note.as_mut() {
+            if line == "end note" || line == "endnote" {
+                let note = self.open_note.take().unwrap();
+                self.notes.push(note);
+            } else {
+                if !note.text.is_empty() {
+                    note.text.push('\n');
+                }
+                note.text.push_str(line);
+            }
             return;
         }
 
-        // Inside class body
-        if let Some(ref name) = self.current_class.clone() {
-            self.parse_member(&name, line);
+        // Fields of a multi-line struct variant
+        if let Some((name, fields)) = self.open_variant.as_mut() {
+            if let Some(rest) = line.strip_suffix('}') {
+                let rest = rest.trim().trim_end_matches(',');
+                if !rest.is_empty() {
+                    fields.push(rest.to_string());
+                }
+                let literal = EnumLiteral {
+                    name: std::mem::take(name),
+                    data: VariantData::Struct(fields.join(", ")),
+                };
+                self.open_variant = None;
+                if let Some(class) = self
+                    .current_class
+                    .as_ref()
+                    .and_then(|c| self.classes.get_mut(c))
+                {
+                    class.literals.push(literal);
+                }
+            } else {
+                fields.push(line.trim_end_matches(',').to_string());
+            }
             return;
         }
 
-        // Class/interface declaration
-        if line.starts_with("class ")
-            || line.starts_with("interface ")
-            || line.starts_with("abstract ")
-            || line.starts_with("enum ")
-        {
-            self.parse_class_decl(line);
+        // Inside class body; `}` (or `member }`) closes it
+        if let Some(name) = self.current_class.clone() {
+            let closes = line.matches('}').count() > line.matches('{').count();
+            match line.trim_end_matches(';').strip_suffix('}') {
+                Some(rest) if closes => {
+                    self.parse_member(&name, rest, 1);
+                    self.current_class = None;
+                }
+                _ => self.parse_member(&name, line, 1),
+            }
             return;
         }
 
-        // Relationship
-        self.try_parse_relationship(line);
+        let tokens = match tokenize(line, self.line_no) {
+            Ok(tokens) => tokens,
+            Err(mut err) => {
+                err.column += self.indent;
+                self.errors.push(err);
+                return;
+            }
+        };
+        if !tokens.is_empty() {
+            self.parse_statement(line, &tokens);
+        }
     }
 
-    fn parse_class_decl(&mut self, line: &str) {
-        let (class_type, rest) = if line.starts_with("interface ") {
-            (ClassType::Interface, &line[10..])
-        } else if line.starts_with("abstract class ") {
-            (ClassType::Abstract, &line[15..])
-        } else if line.starts_with("abstract ") {
-            (ClassType::Abstract, &line[9..])
-        } else if line.starts_with("enum ") {
-            (ClassType::Enum, &line[5..])
-        } else if line.starts_with("class ") {
-            (ClassType::Class, &line[6..])
-        } else {
-            return;
-        };
+    /// Dispatch one top-level statement on its leading tokens
+    fn parse_statement(&mut self, line: &str, tokens: &[Token]) {
+        let first = tokens[0];
 
-        let rest = rest.trim();
-        let has_body = rest.ends_with('{');
-        let name_part = if has_body {
-            rest[..rest.len() - 1].trim()
-        } else {
-            rest
-        };
+        if first.is_ident("together") && tokens.get(1).is_some_and(|t| t.is_punct('{')) {
+            self.parse_together(&tokens[2..], first);
+            return;
+        }
 
-        // Parse stereotype <<...>>
-        let (name, stereotype) = if let Some(start) = name_part.find("<<") {
-            if let Some(end) = name_part.find(">>") {
-                (
-                    name_part[..start].trim().to_string(),
-                    Some(name_part[start + 2..end].trim().to_string()),
-                )
-            } else {
-                (name_part.to_string(), None)
+        // End of a `together` block, or a line of class names inside one
+        if let Some((_, depth)) = self.open_together {
+            if first.is_punct('}') && depth == self.package_stack.len() {
+                self.parse_together(tokens, first);
+                return;
             }
-        } else {
-            (name_part.to_string(), None)
-        };
-
-        self.classes.insert(
-            name.clone(),
-            ClassDef {
-                name: name.clone(),
-                class_type,
-                fields: Vec::new(),
-                methods: Vec::new(),
-                stereotype,
-                x: 0.0,
-                y: 0.0,
-                width: 0.0,
-                height: 0.0,
-            },
-        );
+            let declares = match first.tok {
+                Tok::Ident(word) => ClassType::from_keyword(word).is_some() || word == "pub",
+                _ => false,
+            };
+            if !declares && tokens.iter().all(|t| t.name().is_some()) {
+                self.parse_together(tokens, first);
+                return;
+            }
+        }
 
-        if has_body {
-            self.current_class = Some(name);
+        // End of the innermost package
+        if first.is_punct('}') {
+            if self.package_stack.pop().is_none() {
+                self.error(first.column, "unmatched '}'");
+            } else if let Some(extra) = tokens.get(1) {
+                self.error(extra.column, "unexpected text after '}'");
+            }
+            return;
         }
-    }
 
-    fn parse_member(&mut self, class_name: &str, line: &str) {
+        if first.is_ident("note") {
+            self.parse_note(line[first.end..].trim());
+            return;
+        }
+
+        let is_statement = !tokens
+            .iter()
+            .any(|t| t.is_punct(':') || matches!(t.tok, Tok::Arrow(_)));
+        if is_statement {
+            let action = match first.tok {
+                Tok::Ident("hide") => Some(DisplayAction::Hide),
+                Tok::Ident("show") => Some(DisplayAction::Show),
+                Tok::Ident("remove") => Some(DisplayAction::Remove),
+                _ => None,
+            };
+            if let Some(action) = action {
+                self.parse_display_rule(action, tokens);
+                return;
+            }
+            if let Some(direction) = direction_statement(tokens) {
+                self.direction = Some(direction);
+                return;
+            }
+        }
+
+        // Optional Rust visibility before `mod`, `struct`, ...
+        let skip = visibility_len(tokens);
+        let keyword = tokens.get(skip).copied();
+        let rest = tokens.get(skip + 1..).unwrap_or_default();
+
+        if let Some(Tok::Ident(word)) = keyword.map(|t| t.tok) {
+            if matches!(word, "package" | "namespace" | "mod") {
+                self.parse_package_decl(word, rest, keyword.unwrap());
+                return;
+            }
+            if word == "abstract" && rest.first().is_some_and(|t| t.is_ident("class")) {
+                self.parse_class_decl(line, ClassType::Abstract, &rest[1..], rest[0]);
+                return;
+            }
+            if let Some(class_type) = ClassType::from_keyword(word) {
+                // `type` and friends double as plain names, as in `type --> Foo`
+                if rest.first().is_some_and(|t| t.name().is_some()) {
+                    self.parse_class_decl(line, class_type, rest, keyword.unwrap());
+                    return;
+                }
+            }
+            if word == "impl" && skip == 0 {
+                self.parse_impl(line, rest, first);
+                return;
+            }
+        }
+
+        // `Foo : +bar()` adds a member to Foo
+        if let (Some(name), Some(colon)) = (first.name(), tokens.get(1)) {
+            if colon.is_punct(':') {
+                let class = self.resolve_class(name);
+                self.ensure_class(&class);
+                let text = &line[colon.end..];
+                self.parse_member(&class, text, column(line, colon.end));
+                return;
+            }
+        }
+
+        if let Some(arrow) = tokens.iter().position(|t| matches!(t.tok, Tok::Arrow(_))) {
+            self.parse_relationship(line, tokens, arrow);
+            return;
+        }
+
+        self.error(first.column, "unrecognised statement");
+    }
+
+    /// Class names inside `together { ... }`, possibly spanning several lines;
+    /// a `}` closes the group
+    fn parse_together(&mut self, tokens: &[Token], at: Token) {
+        let (mut group, depth) = match self.open_together.take() {
+            Some(open) => open,
+            None => (Vec::new(), self.package_stack.len()),
+        };
+        for (i, token) in tokens.iter().enumerate() {
+            if token.is_punct('}') {
+                if let Some(extra) = tokens.get(i + 1) {
+                    self.error(extra.column, "unexpected text after '}'");
+                }
+                if group.len() < 2 {
+                    self.error(at.column, "`together` needs at least two classes");
+                } else {
+                    self.together.push(group);
+                }
+                return;
+            }
+            match token.name() {
+                Some(name) => {
+                    let class = self.resolve_class(name);
+                    self.ensure_class(&class);
+                    group.push(class);
+                }
+                None => {
+                    self.error(token.column, "expected a class name in `together`");
+                    return;
+                }
+            }
+        }
+        self.open_together = Some((group, depth));
+    }
+
+    /// `hide [empty] [selector] [part]`, `show ...` or `remove selector`.
+    ///
+    /// The selector is a class name, `<<stereotype>>`, `$tag`, a kind keyword
+    /// or `@unlinked`; without one the command applies to every class. The part
+    /// is `members`, `fields`, `methods`, `circle` or `stereotype`; without one
+    /// it applies to the class itself.
+    fn parse_display_rule(&mut self, action: DisplayAction, tokens: &[Token]) {
+        let mut i = 1;
+        let empty_only = tokens.get(i).is_some_and(|t| t.is_ident("empty"));
+        if empty_only {
+            i += 1;
+        }
+
+        let is_part = |t: Option<&Token>| match t.map(|t| t.tok) {
+            Some(Tok::Ident(w)) => DisplayPart::from_keyword(w).is_some(),
+            _ => false,
+        };
+        let selector = match tokens.get(i).map(|t| t.tok) {
+            _ if is_part(tokens.get(i)) => Selector::All,
+            Some(Tok::Stereotype(s)) => {
+                i += 1;
+                Selector::Stereotype(s.to_string())
+            }
+            Some(Tok::Punct(sigil @ ('$' | '@'))) => {
+                let name = tokens.get(i + 1).and_then(|t| match t.tok {
+                    Tok::Ident(name) => Some(name),
+                    _ => None,
+                });
+                let selector = match (sigil, name) {
+                    ('$', Some(tag)) => Selector::Tag(tag.to_string()),
+                    ('@', Some("unlinked")) => Selector::Unlinked,
+                    _ => {
+                        self.error(tokens[i].column, "expected `$tag` or `@unlinked`");
+                        return;
+                    }
+                };
+                i += 2;
+                selector
+            }
+            Some(Tok::Ident(word))
+                if ClassType::from_keyword(word).is_some()
+                    && (tokens.len() == i + 1 || is_part(tokens.get(i + 1))) =>
+            {
+                i += 1;
+                Selector::Kind(ClassType::from_keyword(word).unwrap_or(ClassType::Class))
+            }
+            Some(Tok::Ident(name) | Tok::Str(name)) => {
+                i += 1;
+                Selector::Class(name.to_string())
+            }
+            _ => Selector::All,
+        };
+
+        let part = match tokens.get(i) {
+            None => DisplayPart::Element,
+            Some(token) => match token.tok {
+                Tok::Ident(word) if DisplayPart::from_keyword(word).is_some() => {
+                    i += 1;
+                    DisplayPart::from_keyword(word).unwrap_or(DisplayPart::Element)
+                }
+                _ => {
+                    self.error(token.column, "expected a class or `members`, `fields`, `methods`, `circle` or `stereotype`");
+                    return;
+                }
+            },
+        };
+        if let Some(extra) = tokens.get(i) {
+            self.error(extra.column, "unexpected text after display command");
+            return;
+        }
+
+        let at = tokens[0].column;
+        if selector == Selector::All && part == DisplayPart::Element {
+            self.error(at, "expected what to hide, show or remove");
+            return;
+        }
+        if action == DisplayAction::Remove && part != DisplayPart::Element {
+            self.error(at, "`remove` takes classes, not parts of them");
+            return;
+        }
+        if empty_only
+            && !matches!(
+                part,
+                DisplayPart::Members | DisplayPart::Fields | DisplayPart::Methods
+            )
+        {
+            self.error(at, "`empty` only applies to members, fields or methods");
+            return;
+        }
+
+        self.display_rules.push(DisplayRule {
+            action,
+            selector,
+            part,
+            empty_only,
+        });
+    }
+
+    /// Whether a display rule's selector matches a class
+    fn selects(&self, selector: &Selector, class: &ClassDef, linked: &HashSet<&str>) -> bool {
+        match selector {
+            Selector::All => true,
+            Selector::Class(name) => self.resolve_class(name) == class.name,
+            Selector::Stereotype(s) => class.stereotype.as_deref() == Some(s.as_str()),
+            Selector::Tag(tag) => class.tags.contains(tag),
+            Selector::Kind(kind) => class.class_type == *kind,
+            Selector::Unlinked => !linked.contains(class.name.as_str()),
+        }
+    }
+
+    /// Apply `hide`/`show`/`remove` in source order: removed classes leave the
+    /// model with their relationships and notes, hidden parts are dropped from
+    /// the remaining classes so layout and rendering never see them
+    fn apply_display_rules(&mut self) {
+        if self.display_rules.is_empty() {
+            return;
+        }
+
+        let linked: HashSet<&str> = self
+            .relationships
+            .iter()
+            .flat_map(|r| [r.from.as_str(), r.to.as_str()])
+            .collect();
+        let displays: Vec<(String, Display)> = self
+            .class_order
+            .iter()
+            .filter_map(|name| self.classes.get(name))
+            .map(|class| {
+                let mut display = Display::default();
+                for rule in &self.display_rules {
+                    if self.selects(&rule.selector, class, &linked) {
+                        display.apply(rule);
+                    }
+                }
+                (class.name.clone(), display)
+            })
+            .collect();
+
+        let mut removed = HashSet::new();
+        for (name, display) in displays {
+            if display.removed {
+                self.classes.remove(&name);
+                removed.insert(name);
+                continue;
+            }
+            let Some(class) = self.classes.get_mut(&name) else {
+                continue;
+            };
+            class.hidden = display.hidden;
+            class.hide_spot = display.spot;
+            class.hide_empty_members = display.empty_members;
+            if display.members {
+                class.literals.clear();
+            }
+            if display.fields {
+                class.fields.clear();
+            }
+            if display.methods {
+                class.methods.clear();
+            }
+            if display.stereotype {
+                class.stereotype = None;
+            }
+        }
+        if removed.is_empty() {
+            return;
+        }
+        for group in &mut self.together {
+            group.retain(|name| !removed.contains(name));
+        }
+
+        // Drop relationships to removed classes, renumbering note links
+        let mut kept = Vec::new();
+        let mut new_index = Vec::new();
+        for rel in std::mem::take(&mut self.relationships) {
+            if removed.contains(&rel.from) || removed.contains(&rel.to) {
+                new_index.push(None);
+            } else {
+                new_index.push(Some(kept.len()));
+                kept.push(rel);
+            }
+        }
+        self.relationships = kept;
+
+        self.notes.retain_mut(|note| {
+            note.links.retain(|name| !removed.contains(name));
+            match &mut note.target {
+                NoteTarget::Class { name, .. } => !removed.contains(name),
+                NoteTarget::Link(index) => match new_index[*index] {
+                    Some(new) => {
+                        *index = new;
+                        true
+                    }
+                    None => false,
+                },
+                NoteTarget::Free => true,
+            }
+        });
+    }
+
+    /// `note left of Foo : text`, `note on link : text`, `note "text" as N1`,
+    /// or the first line of a multi-line note ending with `end note`
+    fn parse_note(&mut self, rest: &str) {
+        // Free-standing note: `note "text" as N1` or `note as N1`
+        if let Some(quoted) = rest.strip_prefix('"') {
+            match quoted.split_once('"') {
+                Some((text, alias)) => {
+                    let alias = alias
+                        .trim()
+                        .strip_prefix("as ")
+                        .map(|a| a.trim().to_string());
+                    let text = text.replace("\\n", "\n");
+                    self.notes.push(Note::new(alias, text, NoteTarget::Free));
+                }
+                None => self.error(1, "unterminated string"),
+            }
+            return;
+        }
+        if let Some(alias) = rest.strip_prefix("as ") {
+            let note = Note::new(
+                Some(alias.trim().to_string()),
+                String::new(),
+                NoteTarget::Free,
+            );
+            self.open_note = Some(note);
+            return;
+        }
+
+        let (head, text) = match find_unquoted(rest, ":") {
+            Some(colon) => (rest[..colon].trim(), Some(rest[colon + 1..].trim())),
+            None => (rest, None),
+        };
+
+        let target = if head.ends_with("on link") {
+            match self.relationships.len() {
+                0 => {
+                    self.error(1, "`note on link` must follow a relationship");
+                    return;
+                }
+                n => NoteTarget::Link(n - 1),
+            }
+        } else {
+            let mut words = head.splitn(3, ' ');
+            let side = match words.next() {
+                Some("left") => NoteSide::Left,
+                Some("right") => NoteSide::Right,
+                Some("top") => NoteSide::Top,
+                Some("bottom") => NoteSide::Bottom,
+                _ => {
+                    self.error(1, "expected `note left|right|top|bottom of <class>`");
+                    return;
+                }
+            };
+            let (Some("of"), Some(name)) = (words.next(), words.next()) else {
+                self.error(1, "expected `of <class>` after the note side");
+                return;
+            };
+            let name = self.resolve_class(name.trim().trim_matches('"'));
+            self.ensure_class(&name);
+            NoteTarget::Class { name, side }
+        };
+
+        match text {
+            Some(text) => {
+                let text = text.replace("\\n", "\n");
+                self.notes.push(Note::new(None, text, target));
+            }
+            None => self.open_note = Some(Note::new(None, String::new(), target)),
+        }
+    }
+
+    /// `package net {`, `namespace a.b`, `pub mod geo {`
+    fn parse_package_decl(&mut self, keyword: &str, tokens: &[Token], at: Token) {
+        let Some(name) = tokens.first().and_then(|t| t.name()) else {
+            self.error(at.column, format!("expected a name after `{}`", keyword));
+            return;
+        };
+        let mut opens = false;
+        for token in &tokens[1..] {
+            match token.tok {
+                Tok::Stereotype(_) => {}
+                Tok::Punct('{') if !opens => opens = true,
+                _ => {
+                    self.error(token.column, "unexpected text in package declaration");
+                    return;
+                }
+            }
+        }
+        let full = match self.package_stack.last() {
+            Some(parent) => format!("{}.{}", parent, name),
+            None => name.to_string(),
+        };
+        self.register_package(&full);
+        if opens {
+            self.package_stack.push(full);
+        }
+    }
+
+    /// Register a package along with all of its ancestors
+    fn register_package(&mut self, full: &str) {
+        let mut end = 0;
+        for segment in full.split('.') {
+            end += segment.len();
+            let path = &full[..end];
+            if !self.packages.iter().any(|p| p.name == path) {
+                self.packages.push(Package {
+                    name: path.to_string(),
+                    x: 0.0,
+                    y: 0.0,
+                    width: 0.0,
+                    height: 0.0,
+                });
+            }
+            end += 1;
+        }
+    }
+
+    /// Package of the class with the fully qualified `key`, registering it
+    fn package_for(&mut self, key: &str) -> Option<String> {
+        let package = key.rsplit_once('.').map(|(prefix, _)| prefix.to_string());
+        if let Some(ref p) = package {
+            self.register_package(p);
+        }
+        package
+    }
+
+    /// `name` qualified with the package block it appears in
+    fn qualify(&self, name: &str) -> String {
+        match self.package_stack.last() {
+            Some(current) => format!("{}.{}", current, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Resolve a class reference to its fully qualified key.
+    ///
+    /// Aliases come first, then a class in the current package, then one at
+    /// top level, then the only class of that name in any package. A name that
+    /// matches none of these is a new class in the current package.
+    fn resolve_class(&self, name: &str) -> String {
+        if let Some(target) = self.aliases.get(name) {
+            return target.clone();
+        }
+        let qualified = self.qualify(name);
+        if self.classes.contains_key(&qualified) {
+            return qualified;
+        }
+        if self.classes.contains_key(name) {
+            return name.to_string();
+        }
+        let suffix = format!(".{}", name);
+        let mut packaged = self.class_order.iter().filter(|key| key.ends_with(&suffix));
+        match (packaged.next(), packaged.next()) {
+            (Some(key), None) => key.clone(),
+            _ => qualified,
+        }
+    }
+
+    /// Class and member of a relationship end: `Order::customer_id` names the
+    /// `customer_id` row of an already declared `Order`, otherwise the whole
+    /// path is a class name
+    fn resolve_endpoint(&self, name: &str) -> (String, Option<String>) {
+        if let Some((class, member)) = name.rsplit_once("::") {
+            let class = self.resolve_class(class);
+            if !self.classes.contains_key(name) && self.classes.contains_key(&class) {
+                return (class, Some(member.to_string()));
+            }
+        }
+        (self.resolve_class(name), None)
+    }
+
+    /// `class Name<T> as Alias <<stereotype>> extends Base { members }`,
+    /// or `type Name = Target;`
+    fn parse_class_decl(
+        &mut self,
+        line: &str,
+        class_type: ClassType,
+        tokens: &[Token],
+        keyword: Token,
+    ) {
+        let Some(first) = tokens.first().filter(|t| t.name().is_some()) else {
+            self.error(keyword.column, "expected a class name");
+            return;
+        };
+        let mut name = first.name().unwrap_or_default();
+        let mut alias = None;
+        let mut generics = Vec::new();
+        let mut stereotype = None;
+        let mut aliased = None;
+        let mut parents: Vec<(&str, RelationType)> = Vec::new();
+        let mut tags = Vec::new();
+        let mut pinned = None;
+        let mut body = Body::None;
+
+        let mut i = 1;
+        while let Some(token) = tokens.get(i) {
+            i += 1;
+            match token.tok {
+                Tok::Generics(params) => generics = parse_type_params(params),
+                Tok::Stereotype(s) => stereotype = Some(s.to_string()),
+                Tok::Punct('$') => match tokens.get(i).map(|t| t.tok) {
+                    Some(Tok::Ident(tag)) => {
+                        tags.push(tag.to_string());
+                        i += 1;
+                    }
+                    _ => {
+                        self.error(token.column, "expected a tag name after '$'");
+                        return;
+                    }
+                },
+                Tok::Punct('@') => match tokens.get(i..i + 3).and_then(position_tokens) {
+                    Some(at) => {
+                        pinned = Some(at);
+                        i += 3;
+                    }
+                    None => {
+                        let negative = tokens.iter().skip(i).take(4).any(|t| t.is_punct('-'));
+                        let message = if negative {
+                            "pinned positions cannot be negative"
+                        } else {
+                            "expected a position `@ x,y`"
+                        };
+                        self.error(token.column, message);
+                        return;
+                    }
+                },
+                Tok::Ident("as") => match tokens.get(i).map(|t| t.tok) {
+                    // `class "Long Name" as LN` and `class LN as "Long Name"`
+                    Some(Tok::Str(long)) => {
+                        alias = Some(name);
+                        name = long;
+                        i += 1;
+                    }
+                    Some(Tok::Ident(short)) => {
+                        alias = Some(short);
+                        i += 1;
+                    }
+                    _ => {
+                        self.error(token.column, "expected an alias after `as`");
+                        return;
+                    }
+                },
+                Tok::Ident(word @ ("extends" | "implements")) => {
+                    let rel_type = if word == "extends" {
+                        RelationType::Inheritance
+                    } else {
+                        RelationType::Realization
+                    };
+                    while let Some(parent) = tokens.get(i).and_then(|t| t.name()) {
+                        parents.push((parent, rel_type));
+                        i += 1;
+                        if matches!(tokens.get(i).map(|t| t.tok), Some(Tok::Generics(_))) {
+                            i += 1;
+                        }
+                        if !tokens.get(i).is_some_and(|t| t.is_punct(',')) {
+                            break;
+                        }
+                        i += 1;
+                    }
+                }
+                Tok::Punct('=') if class_type == ClassType::TypeAlias => {
+                    let target = line[token.end..].trim().trim_end_matches(';').trim_end();
+                    aliased = Some(target.to_string());
+                    break;
+                }
+                Tok::Punct(';') if i == tokens.len() => {}
+                Tok::Punct('{') => {
+                    match self.body_after(line, tokens, i - 1) {
+                        Some(b) => body = b,
+                        None => return,
+                    }
+                    break;
+                }
+                _ => {
+                    self.error(token.column, "unexpected text in class declaration");
+                    return;
+                }
+            }
+        }
+
+        // A declaration always names a class of the current package
+        let key = match self.aliases.get(name) {
+            Some(target) => target.clone(),
+            None => self.qualify(name),
+        };
+        if let Some(alias) = alias {
+            self.aliases.insert(alias.to_string(), key.clone());
+        }
+        let package = self.package_for(&key);
+        self.add_class(ClassDef {
+            name: key.clone(),
+            class_type,
+            fields: Vec::new(),
+            methods: Vec::new(),
+            literals: Vec::new(),
+            aliased,
+            stereotype,
+            generics,
+            package,
+            tags,
+            hidden: false,
+            hide_spot: false,
+            hide_empty_members: false,
+            pinned,
+            x: 0.0,
+            y: 0.0,
+            width: 0.0,
+            height: 0.0,
+        });
+
+        for (parent, rel_type) in parents {
+            let parent = self.resolve_class(parent);
+            self.ensure_class(&parent);
+            self.relationships
+                .push(Relationship::new(key.clone(), parent, rel_type));
+        }
+
+        if let Some((group, _)) = self.open_together.as_mut() {
+            group.push(key.clone());
+        }
+
+        self.literals_done = false;
+        self.enter_body(&key, body);
+    }
+
+    /// Classify what follows the `{` at `tokens[open]`
+    fn body_after<'l>(&mut self, line: &'l str, tokens: &[Token], open: usize) -> Option<Body<'l>> {
+        let start = tokens[open].end;
+        let mut depth = 0;
+        for (i, token) in tokens.iter().enumerate().skip(open) {
+            if token.is_punct('{') {
+                depth += 1;
+            } else if token.is_punct('}') {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(extra) = tokens.get(i + 1).filter(|t| !t.is_punct(';')) {
+                        self.error(extra.column, "unexpected text after class body");
+                        return None;
+                    }
+                    let inner = &line[start..token.start];
+                    return Some(if inner.trim().is_empty() {
+                        Body::None
+                    } else {
+                        Body::Inline(inner, column(line, start))
+                    });
+                }
+            }
+        }
+        Some(if open + 1 == tokens.len() {
+            Body::Open
+        } else {
+            Body::OpenWith(&line[start..], column(line, start))
+        })
+    }
+
+    /// Parse the members of a one-line body and/or open a multi-line one
+    fn enter_body(&mut self, class: &str, body: Body) {
+        let (text, column, open) = match body {
+            Body::None => return,
+            Body::Open => ("", 1, true),
+            Body::Inline(text, column) => (text, column, false),
+            Body::OpenWith(text, column) => (text, column, true),
+        };
+
+        let is_enum = self
+            .classes
+            .get(class)
+            .is_some_and(|c| c.class_type == ClassType::Enum);
+        if is_enum {
+            // The literal parser handles `,` and `;` itself
+            self.parse_member(class, text, column);
+        } else {
+            for member in split_top_level(text, ';')
+                .into_iter()
+                .flat_map(|part| split_top_level(part, ','))
+            {
+                self.parse_member(class, member, column);
+            }
+        }
+
+        if open {
+            self.current_class = Some(class.to_string());
+        }
+    }
+
+    /// `impl Display for Point` realizes the trait; with a body, the methods
+    /// inside are added to the implementing type
+    fn parse_impl(&mut self, line: &str, tokens: &[Token], keyword: Token) {
+        let mut names = Vec::new();
+        let mut is_for = false;
+        let mut body = Body::None;
+
+        let mut i = 0;
+        while let Some(token) = tokens.get(i) {
+            match token.tok {
+                Tok::Ident("for") => is_for = true,
+                // `where` clauses only constrain the generics
+                Tok::Ident("where") => {
+                    i = tokens
+                        .iter()
+                        .position(|t| t.is_punct('{'))
+                        .unwrap_or(tokens.len());
+                    continue;
+                }
+                Tok::Ident(_) | Tok::Str(_) => names.push(token.name().unwrap_or_default()),
+                Tok::Generics(_) | Tok::Punct('&') => {}
+                Tok::Punct(';') if i + 1 == tokens.len() => {}
+                Tok::Punct('{') => {
+                    match self.body_after(line, tokens, i) {
+                        Some(b) => body = b,
+                        None => return,
+                    }
+                    break;
+                }
+                _ => {
+                    self.error(token.column, "unexpected text in impl block");
+                    return;
+                }
+            }
+            i += 1;
+        }
+
+        let (trait_name, type_name) = match (names.as_slice(), is_for) {
+            ([ty], false) => (None, *ty),
+            ([tr, ty], true) => (Some(*tr), *ty),
+            _ => {
+                self.error(
+                    keyword.column,
+                    "expected `impl Type` or `impl Trait for Type`",
+                );
+                return;
+            }
+        };
+
+        let type_name = self.resolve_class(type_name);
+        if !self.classes.contains_key(&type_name) {
+            self.insert_class(&type_name, ClassType::Struct);
+        }
+        if let Some(trait_name) = trait_name {
+            let trait_name = self.resolve_class(trait_name);
+            if !self.classes.contains_key(&trait_name) {
+                self.insert_class(&trait_name, ClassType::Trait);
+            }
+            self.relationships.push(Relationship::new(
+                type_name.clone(),
+                trait_name,
+                RelationType::Realization,
+            ));
+        }
+
+        self.literals_done = true;
+        self.enter_body(&type_name, body);
+    }
+
+    /// Parse one member of a class body; `column` is where `line` starts
+    fn parse_member(&mut self, class_name: &str, line: &str, column: usize) {
         let line = line.trim();
         if line.is_empty() || line == "{" {
             return;
         }
 
-        // Check visibility prefix
+        let is_enum = self
+            .classes
+            .get(class_name)
+            .is_some_and(|c| c.class_type == ClassType::Enum);
+        if is_enum && !self.literals_done {
+            if let Some(rest) = self.parse_literals(class_name, line) {
+                if !rest.is_empty() {
+                    self.parse_member(class_name, rest, column);
+                }
+                return;
+            }
+        }
+
+        // Check visibility prefix: UML symbols or Rust `pub` / `pub(crate)`
         let (visibility, rest) = if let Some(first) = line.chars().next() {
             if let Some(vis) = Visibility::from_char(first) {
                 (Some(vis), line[1..].trim())
+            } else if let Some((vis, rest)) = Visibility::strip_rust(line) {
+                (Some(vis), rest)
             } else {
                 (None, line)
             }
@@ -262,14 +1885,86 @@ impl Parser {
             (None, line)
         };
 
+        // Rust syntax: `name: Type,` fields and `fn name(&self) -> T;` methods
+        let rest = rest.trim_end_matches([',', ';']).trim_end();
+        let rest = rest.strip_prefix("fn ").unwrap_or(rest);
+
         // Method has parentheses
         if rest.contains('(') {
-            self.parse_method(class_name, visibility, rest);
+            if let Err(message) = self.parse_method(class_name, visibility, rest) {
+                self.error(column, message);
+            }
         } else {
             self.parse_field(class_name, visibility, rest);
         }
     }
 
+    /// Parse a line of enum literals (`RED, GREEN;`, `Some(T)`, `Move { x: i32 }`).
+    ///
+    /// Returns what follows a terminating `;`, or `None` if the line is a
+    /// member rather than literals.
+    fn parse_literals<'l>(&mut self, class_name: &str, line: &'l str) -> Option<&'l str> {
+        // Compartment separators end the literal list
+        if matches!(line, "--" | ".." | "==" | "__") {
+            self.literals_done = true;
+            return Some("");
+        }
+
+        // Members: visibility prefix, a lowercase `name(...)` method, or any
+        // `Name(...)` with a return type. Only a bare `Name(...)` is a literal.
+        let first = line.chars().next()?;
+        let has_return_type = ["):", ") :", ")->", ") ->"]
+            .iter()
+            .any(|arrow| line.contains(arrow));
+        let looks_like_method = line.contains('(') && (first.is_lowercase() || has_return_type);
+        if Visibility::from_char(first).is_some() || looks_like_method {
+            self.literals_done = true;
+            return None;
+        }
+
+        let (list, rest) = match split_top_level(line, ';').as_slice() {
+            [list] => (*list, None),
+            [list, ..] => (*list, Some(line[list.len() + 1..].trim())),
+            [] => (line, None),
+        };
+        if rest.is_some() {
+            self.literals_done = true;
+        }
+
+        let mut literals = Vec::new();
+        let mut opened_variant = false;
+        for part in split_top_level(list, ',') {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+            // `Move {` opens a struct variant whose fields follow on their own lines
+            if let Some(name) = part.strip_suffix('{') {
+                self.open_variant = Some((name.trim().to_string(), Vec::new()));
+                opened_variant = true;
+                continue;
+            }
+            match EnumLiteral::parse(part) {
+                Some(literal) => literals.push(literal),
+                // `RED GREEN`: several unit literals on one line
+                None if !part.contains(['(', '{']) => {
+                    literals.extend(part.split_whitespace().filter_map(EnumLiteral::parse))
+                }
+                None => {}
+            }
+        }
+
+        // Nothing recognisable (e.g. `{static} values()`): leave it to the member parser
+        if literals.is_empty() && !opened_variant && rest.is_none() {
+            return None;
+        }
+
+        if let Some(class) = self.classes.get_mut(class_name) {
+            class.literals.extend(literals);
+        }
+        Some(rest.unwrap_or(""))
+    }
+
     fn parse_field(&mut self, class_name: &str, visibility: Option<Visibility>, rest: &str) {
         let is_static = rest.contains("{static}") || rest.contains("{classifier}");
         let rest = rest.replace("{static}", "").replace("{classifier}", "");
@@ -294,7 +1989,12 @@ impl Parser {
         }
     }
 
-    fn parse_method(&mut self, class_name: &str, visibility: Option<Visibility>, rest: &str) {
+    fn parse_method(
+        &mut self,
+        class_name: &str,
+        visibility: Option<Visibility>,
+        rest: &str,
+    ) -> Result<(), String> {
         let is_static = rest.contains("{static}") || rest.contains("{classifier}");
         let is_abstract = rest.contains("{abstract}");
         let rest = rest
@@ -303,120 +2003,677 @@ impl Parser {
             .replace("{abstract}", "");
         let rest = rest.trim();
 
-        if let (Some(paren_start), Some(paren_end)) = (rest.find('('), rest.find(')')) {
-            let name = rest[..paren_start].trim().to_string();
-            let params = rest[paren_start + 1..paren_end].trim().to_string();
-            let return_type = rest[paren_end..]
-                .find(':')
-                .map(|pos| rest[paren_end + pos + 1..].trim().to_string());
-
-            if let Some(class) = self.classes.get_mut(class_name) {
-                class.methods.push(Method {
-                    visibility,
-                    name,
-                    params,
-                    return_type,
-                    is_static,
-                    is_abstract,
-                });
+        let paren_start = rest.find('(').ok_or("expected '(' in method")?;
+        let mut depth = 0;
+        let paren_end = rest[paren_start..]
+            .char_indices()
+            .find_map(|(i, c)| {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(paren_start + i)
+            })
+            .ok_or("unclosed '(' in method parameters")?;
+
+        let name = rest[..paren_start].trim().to_string();
+        if name.is_empty() {
+            return Err("expected a method name before '('".to_string());
+        }
+        let params = rest[paren_start + 1..paren_end].trim().to_string();
+        let after = &rest[paren_end + 1..];
+        let return_type = after
+            .find("->")
+            .map(|pos| after[pos + 2..].trim().to_string())
+            .or_else(|| {
+                after
+                    .find(':')
+                    .map(|pos| after[pos + 1..].trim().to_string())
+            });
+
+        if let Some(class) = self.classes.get_mut(class_name) {
+            class.methods.push(Method {
+                visibility,
+                name,
+                params,
+                return_type,
+                is_static,
+                is_abstract,
+            });
+        }
+        Ok(())
+    }
+
+    /// `Left ["mult"] arrow ["mult"] Right [: label [>|<]]`, or a note link
+    /// such as `N1 .. Foo`
+    fn parse_relationship<'a>(&mut self, line: &str, tokens: &[Token<'a>], arrow: usize) {
+        let arrow_token = tokens[arrow];
+        let Tok::Arrow(arrow_text) = arrow_token.tok else {
+            return;
+        };
+
+        let colon = tokens[arrow..].iter().position(|t| t.is_punct(':'));
+        let right_end = colon.map_or(tokens.len(), |c| arrow + c);
+        let label = colon.map(|c| line[tokens[arrow + c].end..].trim());
+
+        // Generic arguments on a reference (`Vec<T>`) do not change the class
+        let side = |part: &[Token<'a>]| -> Vec<Tok<'a>> {
+            part.iter()
+                .map(|t| t.tok)
+                .filter(|t| !matches!(t, Tok::Generics(_)))
+                .collect()
+        };
+        let (left, right) = (side(&tokens[..arrow]), side(&tokens[arrow + 1..right_end]));
+
+        // `Order "1"` / `"0..*" LineItem`: the quoted text sits next to the arrow
+        let left = match left.as_slice() {
+            [Tok::Ident(n) | Tok::Str(n)] => Some((*n, RelationEnd::default())),
+            [Tok::Ident(n) | Tok::Str(n), Tok::Str(m)] => Some((*n, RelationEnd::parse(m))),
+            _ => None,
+        };
+        let right = match right.as_slice() {
+            [Tok::Ident(n) | Tok::Str(n)] => Some((*n, RelationEnd::default())),
+            [Tok::Str(m), Tok::Ident(n) | Tok::Str(n)] => Some((*n, RelationEnd::parse(m))),
+            _ => None,
+        };
+        let left_missing = left.is_none();
+        let (Some((left, left_end)), Some((right, right_end))) = (left, right) else {
+            let at = if left_missing {
+                tokens[0].column
+            } else {
+                tokens.get(arrow + 1).unwrap_or(&arrow_token).column
+            };
+            self.error(at, "expected a class on each side of the arrow");
+            return;
+        };
+
+        // Connecting a free note
+        let note_index = |alias: &str| {
+            self.notes
+                .iter()
+                .position(|n| n.alias.as_deref() == Some(alias))
+        };
+        if let Some((note, class)) = note_index(left)
+            .map(|n| (n, right))
+            .or_else(|| note_index(right).map(|n| (n, left)))
+        {
+            let class = self.resolve_class(class);
+            self.ensure_class(&class);
+            self.notes[note].links.push(class);
+            return;
+        }
+
+        let Some(ArrowKind {
+            rel_type,
+            swapped,
+            left_marker,
+            right_marker,
+            dashed,
+            direction,
+            hidden,
+        }) = arrow_kind(arrow_text)
+        else {
+            self.error(
+                arrow_token.column,
+                format!("unknown relationship arrow `{}`", arrow_text),
+            );
+            return;
+        };
+
+        // A trailing `>` or `<` on the label gives its reading direction
+        let (label, reads_right) = match label {
+            Some(text) => {
+                if let Some(rest) = text.strip_suffix('>') {
+                    (Some(rest.trim()), Some(true))
+                } else if let Some(rest) = text.strip_prefix('<') {
+                    (Some(rest.trim()), Some(false))
+                } else if let Some(rest) = text.strip_suffix('<') {
+                    (Some(rest.trim()), Some(false))
+                } else {
+                    (Some(text), None)
+                }
+            }
+            None => (None, None),
+        };
+        let label = label.filter(|l| !l.is_empty()).map(str::to_string);
+
+        let (from, to, from_end, to_end, from_marker, to_marker) = if swapped {
+            (
+                self.resolve_endpoint(right),
+                self.resolve_endpoint(left),
+                right_end,
+                left_end,
+                right_marker,
+                left_marker,
+            )
+        } else {
+            (
+                self.resolve_endpoint(left),
+                self.resolve_endpoint(right),
+                left_end,
+                right_end,
+                left_marker,
+                right_marker,
+            )
+        };
+        let label_direction = reads_right.map(|right| {
+            if right != swapped {
+                LabelDirection::Forward
+            } else {
+                LabelDirection::Backward
+            }
+        });
+
+        let ((from, from_member), (to, to_member)) = (from, to);
+        self.ensure_class(&from);
+        self.ensure_class(&to);
+
+        self.relationships.push(Relationship {
+            from_member,
+            to_member,
+            label,
+            label_direction,
+            from_end,
+            to_end,
+            from_marker,
+            to_marker,
+            dashed,
+            direction: direction.map(|d| if swapped { d.reversed() } else { d }),
+            hidden,
+            ..Relationship::new(from, to, rel_type)
+        });
+    }
+
+    fn ensure_class(&mut self, name: &str) {
+        if !self.classes.contains_key(name) {
+            self.insert_class(name, ClassType::Class);
+        }
+    }
+
+    /// Add or redeclare a class; a redeclared class keeps its first position
+    fn add_class(&mut self, class: ClassDef) {
+        if !self.classes.contains_key(&class.name) {
+            self.class_order.push(class.name.clone());
+        }
+        self.classes.insert(class.name.clone(), class);
+    }
+
+    /// Add an empty class of the given kind
+    fn insert_class(&mut self, name: &str, class_type: ClassType) {
+        let package = self.package_for(name);
+        self.add_class(ClassDef {
+            name: name.to_string(),
+            class_type,
+            fields: Vec::new(),
+            methods: Vec::new(),
+            literals: Vec::new(),
+            aliased: None,
+            stereotype: None,
+            generics: Vec::new(),
+            package,
+            tags: Vec::new(),
+            hidden: false,
+            hide_spot: false,
+            hide_empty_members: false,
+            pinned: None,
+            x: 0.0,
+            y: 0.0,
+            width: 0.0,
+            height: 0.0,
+        });
+    }
+}
+
+/// Number of tokens taken by a leading `pub`, `pub(crate)` or `pub(in path)`
+fn visibility_len(tokens: &[Token]) -> usize {
+    if !tokens.first().is_some_and(|t| t.is_ident("pub")) {
+        return 0;
+    }
+    if !tokens.get(1).is_some_and(|t| t.is_punct('(')) {
+        return 1;
+    }
+    tokens
+        .iter()
+        .position(|t| t.is_punct(')'))
+        .map_or(1, |close| close + 1)
+}
+
+/// `120,40` after the `@` of a pinned class
+fn position_tokens(tokens: &[Token]) -> Option<(f32, f32)> {
+    let number = |token: &Token| match token.tok {
+        Tok::Ident(text) => text.parse::<f32>().ok().filter(|n| n.is_finite()),
+        _ => None,
+    };
+    match tokens {
+        [x, comma, y] if comma.is_punct(',') => Some((number(x)?, number(y)?)),
+        _ => None,
+    }
+}
+
+/// `left to right direction` or `top to bottom direction`
+fn direction_statement(tokens: &[Token]) -> Option<LayoutDirection> {
+    let words: Vec<&str> = tokens
+        .iter()
+        .map(|t| match t.tok {
+            Tok::Ident(word) => Some(word),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    match words.as_slice() {
+        ["left", "to", "right", "direction"] => Some(LayoutDirection::LeftToRight),
+        ["top", "to", "bottom", "direction"] => Some(LayoutDirection::TopToBottom),
+        _ => None,
+    }
+}
+
+/// What an arrow says about a relationship
+struct ArrowKind {
+    rel_type: RelationType,
+    /// The arrow points right-to-left, so `from` is written on the right
+    swapped: bool,
+    left_marker: EndMarker,
+    right_marker: EndMarker,
+    dashed: bool,
+    /// Where the right-hand class goes relative to the left-hand one
+    direction: Option<Direction>,
+    hidden: bool,
+}
+
+/// Relationship type and end markers of an arrow.
+///
+/// A direction (`-up->`, `-l-*`) or `[hidden]` may sit inside the line. A
+/// single-character line (`->`) without one is laid out horizontally.
+///
+/// An arrow is a left head, a solid (`-`) or dotted (`.`) line of any length,
+/// and a right head: `<|--`, `*--`, `..>`, `-->`, `<|--|>`, `}o--||`, ...
+fn arrow_kind(arrow: &str) -> Option<ArrowKind> {
+    let mut direction = None;
+    let mut hidden = false;
+    let mut plain = String::new();
+    let mut rest = arrow;
+    while let Some(c) = rest.chars().next() {
+        let infix = plain
+            .ends_with(['-', '.'])
+            .then(|| arrow_infix(rest))
+            .flatten();
+        if let Some(len) = infix {
+            match &rest[..len] {
+                "[hidden]" => hidden = true,
+                "up" | "u" => direction = Some(Direction::Up),
+                "down" | "do" | "d" => direction = Some(Direction::Down),
+                "left" | "le" | "l" => direction = Some(Direction::Left),
+                "right" | "ri" | "r" => direction = Some(Direction::Right),
+                _ => return None,
+            }
+            rest = &rest[len..];
+        } else {
+            plain.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    let arrow = plain.as_str();
+
+    let line_start = arrow.find(['-', '.'])?;
+    let line_end = arrow.rfind(['-', '.'])? + 1;
+    let (left, body, right) = (
+        &arrow[..line_start],
+        &arrow[line_start..line_end],
+        &arrow[line_end..],
+    );
+    let dotted = body.contains('.');
+    if body.contains('.') && body.contains('-') {
+        return None;
+    }
+    let left_marker = EndMarker::from_left(left)?;
+    let right_marker = EndMarker::from_right(right)?;
+
+    let (rel_type, swapped) = match (left, right, dotted) {
+        ("", "|>", false) => (RelationType::Inheritance, false),
+        ("<|", "", false) => (RelationType::Inheritance, true),
+        ("", "|>", true) => (RelationType::Realization, false),
+        ("<|", "", true) => (RelationType::Realization, true),
+        // The diamond sits on the whole, which becomes `from`
+        ("*", "", false) => (RelationType::Composition, false),
+        ("", "*", false) => (RelationType::Composition, true),
+        ("o", "", false) => (RelationType::Aggregation, false),
+        ("", "o", false) => (RelationType::Aggregation, true),
+        ("", ">", true) => (RelationType::Dependency, false),
+        ("<", "", true) => (RelationType::Dependency, true),
+        ("", ">", false) => (RelationType::DirectedAssoc, false),
+        ("<", "", false) => (RelationType::DirectedAssoc, true),
+        ("", "", false) => (RelationType::Association, false),
+        ("", "", true) => (RelationType::Link, false),
+        // The circled plus sits on the outer class, which becomes `from`
+        ("+", "", _) => (RelationType::Nested, false),
+        ("", "+", _) => (RelationType::Nested, true),
+        _ if left_marker.is_crows_foot() || right_marker.is_crows_foot() => {
+            (RelationType::Entity, false)
+        }
+        _ if left_marker != EndMarker::None && right_marker != EndMarker::None => {
+            (RelationType::Bidirectional, false)
+        }
+        // `#--`, `--x`, `*..` and other plain ends keep the line as written
+        _ => (RelationType::Association, false),
+    };
+    if direction.is_none() && body.len() == 1 {
+        direction = Some(Direction::Right);
+    }
+    Some(ArrowKind {
+        rel_type,
+        swapped,
+        left_marker,
+        right_marker,
+        dashed: dotted,
+        direction,
+        hidden,
+    })
+}
+
+/// Byte offset of `pattern` in `line`, ignoring anything inside double quotes
+fn find_unquoted(line: &str, pattern: &str) -> Option<usize> {
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && line[i..].starts_with(pattern) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Parse the inside of `<K, V: Clone>` into type parameters
+fn parse_type_params(params: &str) -> Vec<TypeParam> {
+    split_top_level(params, ',')
+        .into_iter()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (name, bounds) = p
+                .split_once(':')
+                .or_else(|| p.split_once(" extends "))
+                .map_or((p, None), |(n, b)| (n, Some(b.trim().to_string())));
+            TypeParam {
+                name: name.trim().to_string(),
+                bounds,
+            }
+        })
+        .collect()
+}
+
+/// Split on `sep` outside of any `<>`, `()`, `[]` or `{}` nesting
+fn split_top_level(text: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in text.char_indices() {
+        let arrow = prev == '-';
+        prev = c;
+        match c {
+            '<' | '(' | '[' | '{' => depth += 1,
+            // `->` in a return type is not a closing bracket
+            '>' if arrow => {}
+            '>' | ')' | ']' | '}' => depth -= 1,
+            c if c == sep && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+// ============================================================================
+// Layout Engine
+// ============================================================================
+
+impl ClassDiagram {
+    /// Size, place and route everything, filling in the positions of
+    /// classes, packages, notes, lines and labels
+    pub fn layout(&mut self, style: &DiagramStyle) {
+        self.layout_with(style, &LayeredLayout);
+    }
+
+    /// [`Self::layout`], with the classes of every package and of the
+    /// diagram itself placed by `engine`
+    pub fn layout_with(&mut self, style: &DiagramStyle, engine: &dyn LayoutEngine) {
+        self.calculate_dimensions(style);
+        self.package_layout(style, engine);
+        self.clear_template_boxes();
+        self.route_relationships(style);
+        self.place_relationship_labels(style);
+    }
+
+    /// Move everything down when a pin leaves a template box sticking out
+    /// above the top edge, which `bounds()` cannot grow into
+    fn clear_template_boxes(&mut self) {
+        let top = self
+            .classes
+            .iter()
+            .filter(|c| !c.generics.is_empty())
+            .map(|c| c.y - TEMPLATE_RISE)
+            .fold(0.0f32, f32::min);
+        if top == 0.0 {
+            return;
+        }
+        for class in &mut self.classes {
+            class.y -= top;
+        }
+        for package in &mut self.packages {
+            package.y -= top;
+        }
+        for note in &mut self.notes {
+            note.y -= top;
+        }
+    }
+
+    /// Direction from the source if it sets one, otherwise from the style
+    fn direction(&self, style: &DiagramStyle) -> LayoutDirection {
+        self.direction.unwrap_or(style.direction)
+    }
+
+    /// Route every drawn relationship around the visible classes, falling
+    /// back to a simple elbow between the two boxes when no route exists.
+    /// Generalizations into one parent share a trunk, solid and dashed apart.
+    fn route_relationships(&mut self, style: &DiagramStyle) {
+        let direction = self.direction(style);
+        let visible: Vec<usize> = (0..self.classes.len())
+            .filter(|&i| !self.classes[i].hidden)
+            .collect();
+        let boxes: Vec<Rect> = visible.iter().map(|&i| self.classes[i].rect()).collect();
+        let box_of = |name: &str| visible.iter().position(|&i| self.classes[i].name == name);
+
+        let mut routed = Vec::new();
+        let mut requests = Vec::new();
+        for (r, rel) in self.relationships.iter().enumerate() {
+            if rel.hidden {
+                continue;
+            }
+            let (Some(from), Some(to)) = (box_of(&rel.from), box_of(&rel.to)) else {
+                continue;
+            };
+            let row = |class: usize, member: &Option<String>| {
+                member
+                    .as_deref()
+                    .and_then(|m| member_row_y(&self.classes[visible[class]], m))
+            };
+            let mut request = RouteRequest {
+                from,
+                to,
+                from_row: row(from, &rel.from_member),
+                to_row: row(to, &rel.to_member),
+                prefer: matches!(
+                    rel.rel_type,
+                    RelationType::Inheritance | RelationType::Realization
+                )
+                .then_some(match direction {
+                    LayoutDirection::TopToBottom => Axis::Vertical,
+                    LayoutDirection::LeftToRight => Axis::Horizontal,
+                }),
+                bus: None,
+            };
+            // The bus meets at the triangle, so route towards that end
+            let triangles = (
+                rel.from_marker == EndMarker::Triangle,
+                rel.to_marker == EndMarker::Triangle,
+            );
+            let reversed = triangles == (true, false);
+            if reversed {
+                request = RouteRequest {
+                    from: to,
+                    to: from,
+                    from_row: request.to_row,
+                    to_row: request.from_row,
+                    ..request
+                };
+            }
+            if triangles.0 != triangles.1 {
+                request.bus = Some(usize::from(rel.dashed));
             }
+            routed.push((r, reversed));
+            requests.push(request);
+        }
+
+        let routes = route_edges(&boxes, &requests);
+        for ((r, reversed), route) in routed.into_iter().zip(routes) {
+            let path = match route {
+                Some(mut path) => {
+                    if reversed {
+                        path.reverse();
+                    }
+                    path
+                }
+                None => {
+                    let rel = &self.relationships[r];
+                    match (self.visible_class(&rel.from), self.visible_class(&rel.to)) {
+                        (Some(from), Some(to)) => relationship_path(from, to, rel, direction),
+                        _ => Vec::new(),
+                    }
+                }
+            };
+            self.relationships[r].path = path;
         }
     }
 
-    fn try_parse_relationship(&mut self, line: &str) {
-        let patterns = [
-            ("--|>", RelationType::Inheritance),
-            ("<|--", RelationType::Inheritance),
-            ("..|>", RelationType::Realization),
-            ("<|..", RelationType::Realization),
-            ("*--", RelationType::Composition),
-            ("--*", RelationType::Composition),
-            ("o--", RelationType::Aggregation),
-            ("--o", RelationType::Aggregation),
-            ("..>", RelationType::Dependency),
-            ("<..", RelationType::Dependency),
-            ("-->", RelationType::DirectedAssoc),
-            ("<--", RelationType::DirectedAssoc),
-            ("--", RelationType::Association),
-        ];
-
-        for (pattern, rel_type) in patterns {
-            if let Some(pos) = line.find(pattern) {
-                let left = line[..pos].trim();
-                let right_part = line[pos + pattern.len()..].trim();
-
-                let (right, label) = if let Some(colon) = right_part.find(':') {
-                    (
-                        right_part[..colon].trim(),
-                        Some(right_part[colon + 1..].trim().to_string()),
-                    )
-                } else {
-                    (right_part, None)
-                };
+    /// Place relationship labels, multiplicities and roles where they cover
+    /// the fewest classes, notes, lines and other labels. End labels go
+    /// first, as they have the fewest good spots.
+    fn place_relationship_labels(&mut self, style: &DiagramStyle) {
+        let boxes: Vec<Rect> = self
+            .classes
+            .iter()
+            .filter(|c| !c.hidden)
+            .map(ClassDef::rect)
+            .chain(
+                self.notes
+                    .iter()
+                    .map(|n| Rect::new(n.x, n.y, n.width, n.height)),
+            )
+            .collect();
+        let lines: Vec<Vec<(f32, f32)>> =
+            self.relationships.iter().map(|r| r.path.clone()).collect();
+        let text_width = |text: &str| text.len() as f32 * style.char_width;
 
-                if left.is_empty() || right.is_empty() {
+        let mut slots = Vec::new();
+        let mut requests = Vec::new();
+        for (r, rel) in self.relationships.iter().enumerate() {
+            let path = &rel.path;
+            if path.len() < 2 {
+                continue;
+            }
+            let n = path.len();
+            let ends = [
+                (&rel.from_end, path[0], path[1], false),
+                (&rel.to_end, path[n - 1], path[n - 2], true),
+            ];
+            for (end, at, next, to_end) in ends {
+                if end.is_empty() {
                     continue;
                 }
-
-                let (from, to) = if pattern.starts_with('<') {
-                    (right.to_string(), left.to_string())
-                } else {
-                    (left.to_string(), right.to_string())
-                };
-
-                self.ensure_class(&from);
-                self.ensure_class(&to);
-
-                self.relationships.push(Relationship {
-                    from,
-                    to,
-                    rel_type,
-                    label,
-                });
-                return;
+                if let Some(ref multiplicity) = end.multiplicity {
+                    let width = text_width(multiplicity);
+                    slots.push((r, LabelSlot::Multiplicity(to_end)));
+                    requests.push(end_label(at, next, width, false));
+                }
+                if let Some(ref role) = end.role {
+                    let width = text_width(role);
+                    slots.push((r, LabelSlot::Role(to_end)));
+                    requests.push(end_label(at, next, width, true));
+                }
             }
         }
-    }
-
-    fn ensure_class(&mut self, name: &str) {
-        if !self.classes.contains_key(name) {
-            self.classes.insert(
-                name.to_string(),
-                ClassDef {
-                    name: name.to_string(),
-                    class_type: ClassType::Class,
-                    fields: Vec::new(),
-                    methods: Vec::new(),
-                    stereotype: None,
-                    x: 0.0,
-                    y: 0.0,
-                    width: 0.0,
-                    height: 0.0,
-                },
-            );
+        for (r, rel) in self.relationships.iter().enumerate() {
+            if let (Some(label), true) = (&rel.label, rel.path.len() >= 2) {
+                slots.push((r, LabelSlot::Middle));
+                requests.push(path_label(&rel.path, label_box_width(label, rel, style)));
+            }
         }
-    }
-}
-
-// ============================================================================
-// Layout Engine
-// ============================================================================
 
-impl ClassDiagram {
-    fn layout(&mut self, style: &DiagramStyle) {
-        self.calculate_dimensions(style);
-        self.hierarchical_layout(style);
+        let placed = place_labels(&boxes, &lines, &requests);
+        for ((r, slot), centre) in slots.into_iter().zip(placed) {
+            let rel = &mut self.relationships[r];
+            let Some((x, y)) = centre else { continue };
+            // Text sits on a baseline below the centre of its box
+            let baseline = y + LABEL_HEIGHT / 2.0 - 2.0;
+            match slot {
+                LabelSlot::Middle => {
+                    let label = rel.label.as_deref().unwrap_or_default();
+                    let extra = label_box_width(label, rel, style) - text_width(label);
+                    rel.label_at = Some((x - extra / 2.0, baseline));
+                }
+                LabelSlot::Multiplicity(to_end) => {
+                    let end = if to_end {
+                        &mut rel.to_end
+                    } else {
+                        &mut rel.from_end
+                    };
+                    end.multiplicity_at = Some((x, baseline));
+                }
+                LabelSlot::Role(to_end) => {
+                    let end = if to_end {
+                        &mut rel.to_end
+                    } else {
+                        &mut rel.from_end
+                    };
+                    end.role_at = Some((x, baseline));
+                }
+            }
+        }
     }
 
     fn calculate_dimensions(&mut self, style: &DiagramStyle) {
-        let compartment_height = 25.0;
-        let field_height = 18.0;
         let min_width = 120.0;
 
         for class in &mut self.classes {
-            let name_width = class.name.len() as f32 * style.char_width + style.padding * 2.0;
+            let name_width =
+                class.display_name().len() as f32 * style.char_width + style.padding * 2.0;
             let mut max_width = name_width;
 
+            // Keep the centered name clear of the spot on either side
+            if class.spot().is_some() {
+                max_width += 2.0 * (SPOT_RADIUS * 2.0 + style.padding / 2.0);
+            }
+
+            if let Some(ref aliased) = class.aliased {
+                let len = aliased.len() + 2;
+                max_width = max_width.max(len as f32 * style.char_width + style.padding * 2.0);
+            }
+
+            // Leave room beside the name for the overlapping template box
+            if let Some(label) = class.template_label() {
+                let template_width = template_width(&label, style);
+                max_width = max_width.max(name_width + 2.0 * (template_width - TEMPLATE_OVERHANG));
+            }
+
+            for literal in &class.literals {
+                let len = literal.name.len() + literal.data_label().len();
+                max_width = max_width.max(len as f32 * style.char_width + style.padding * 2.0);
+            }
+
             for field in &class.fields {
                 let text =
                     format_member(field.visibility, &field.name, field.field_type.as_deref());
@@ -432,135 +2689,595 @@ impl ClassDiagram {
 
             class.width = max_width.max(min_width);
 
+            let alias_h = if class.aliased.is_some() {
+                ROW_HEIGHT + style.padding
+            } else {
+                0.0
+            };
+            let literals_h = if class.literals.is_empty() {
+                0.0
+            } else {
+                class.literals.len() as f32 * ROW_HEIGHT + style.padding
+            };
             let fields_h = if class.fields.is_empty() {
                 0.0
             } else {
-                class.fields.len() as f32 * field_height + style.padding
+                class.fields.len() as f32 * ROW_HEIGHT + style.padding
             };
             let methods_h = if class.methods.is_empty() {
                 0.0
             } else {
-                class.methods.len() as f32 * field_height + style.padding
+                class.methods.len() as f32 * ROW_HEIGHT + style.padding
             };
 
+            let min_height = if class.hide_empty_members {
+                HEADER_HEIGHT
+            } else {
+                HEADER_HEIGHT * 2.0
+            };
             class.height =
-                (compartment_height + fields_h + methods_h).max(compartment_height * 2.0);
+                (HEADER_HEIGHT + alias_h + literals_h + fields_h + methods_h).max(min_height);
+        }
+
+        for note in &mut self.notes {
+            let longest = note.lines().map(str::len).max().unwrap_or(0);
+            let lines = note.lines().count().max(1);
+            note.width = longest as f32 * style.char_width + style.padding * 2.0 + NOTE_FOLD;
+            note.height = lines as f32 * NOTE_LINE_HEIGHT + style.padding * 2.0;
+        }
+    }
+
+    /// Notes attached to one side of a class
+    fn side_notes(&self, class: usize, side: NoteSide) -> impl Iterator<Item = usize> + '_ {
+        let name = &self.classes[class].name;
+        self.notes
+            .iter()
+            .enumerate()
+            .filter(move |(_, n)| {
+                matches!(&n.target, NoteTarget::Class { name: target, side: s }
+                    if target == name && *s == side)
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// Space taken by a class's side notes: `(left, right, top, bottom)` extents
+    /// and the largest stack along the class's sides
+    fn side_note_extents(&self, class: usize) -> ([f32; 4], f32, f32) {
+        let mut extents = [0.0f32; 4];
+        let (mut stack_w, mut stack_h) = (0.0f32, 0.0f32);
+        for (slot, side) in [
+            NoteSide::Left,
+            NoteSide::Right,
+            NoteSide::Top,
+            NoteSide::Bottom,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let notes: Vec<&Note> = self
+                .side_notes(class, side)
+                .map(|i| &self.notes[i])
+                .collect();
+            if notes.is_empty() {
+                continue;
+            }
+            if slot < 2 {
+                extents[slot] = notes.iter().map(|n| n.width).fold(0.0, f32::max) + NOTE_GAP;
+                let h: f32 = notes.iter().map(|n| n.height + NOTE_GAP).sum();
+                stack_h = stack_h.max(h - NOTE_GAP);
+            } else {
+                extents[slot] = notes.iter().map(|n| n.height).fold(0.0, f32::max) + NOTE_GAP;
+                let w: f32 = notes.iter().map(|n| n.width + NOTE_GAP).sum();
+                stack_w = stack_w.max(w - NOTE_GAP);
+            }
         }
+        (extents, stack_w, stack_h)
     }
 
-    fn hierarchical_layout(&mut self, style: &DiagramStyle) {
+    /// Lay out classes, keeping the members of each package together.
+    ///
+    /// Packages are laid out innermost first; each one is then treated as a
+    /// single block by the level that contains it.
+    fn package_layout(&mut self, style: &DiagramStyle, engine: &dyn LayoutEngine) {
         if self.classes.is_empty() {
             return;
         }
 
-        let names: Vec<String> = self.classes.iter().map(|c| c.name.clone()).collect();
-        let mut layers: HashMap<String, usize> = HashMap::new();
-        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        // Positions of members relative to the content area of their container
+        let mut relative: HashMap<Member, (f32, f32)> = HashMap::new();
 
-        // Build hierarchy from relationships
-        for rel in &self.relationships {
-            match rel.rel_type {
-                RelationType::Inheritance | RelationType::Realization => {
-                    children
-                        .entry(rel.to.clone())
-                        .or_default()
-                        .push(rel.from.clone());
-                }
-                RelationType::Composition | RelationType::Aggregation => {
-                    children
-                        .entry(rel.from.clone())
-                        .or_default()
-                        .push(rel.to.clone());
-                }
-                _ => {}
-            }
-        }
+        let mut order: Vec<usize> = (0..self.packages.len()).collect();
+        order.sort_by_key(|&i| Reverse(self.packages[i].depth()));
+        for pi in order {
+            let name = self.packages[pi].name.clone();
+            let members = self.members_of(Some(&name));
+            let (positions, width, height) =
+                self.layout_members(&members, Some(&name), style, engine);
+            relative.extend(members.into_iter().zip(positions));
 
-        // Find roots and assign layers via BFS
-        let roots: Vec<_> = names
-            .iter()
-            .filter(|n| {
-                !self.relationships.iter().any(|r| {
-                    (r.rel_type == RelationType::Inheritance
-                        || r.rel_type == RelationType::Realization)
-                        && &r.from == *n
-                })
-            })
-            .cloned()
-            .collect();
+            let tab_width = package_tab_width(&self.packages[pi], style);
+            let package = &mut self.packages[pi];
+            package.width = (width + PACKAGE_PADDING * 2.0).max(tab_width + PACKAGE_PADDING * 2.0);
+            package.height = height + PACKAGE_PADDING * 2.0 + PACKAGE_TAB_HEIGHT;
+        }
 
-        let mut queue: Vec<(String, usize)> = if roots.is_empty() {
-            vec![(names[0].clone(), 0)]
-        } else {
-            roots.into_iter().map(|r| (r, 0)).collect()
-        };
+        let members = self.members_of(None);
+        let (positions, _, _) = self.layout_members(&members, None, style, engine);
+        for (member, (x, y)) in members.into_iter().zip(positions) {
+            self.place_member(member, style.margin + x, style.margin + y, &relative);
+        }
+    }
 
-        while let Some((name, layer)) = queue.pop() {
-            if layers.contains_key(&name) {
-                continue;
+    /// Move a member (and, for packages, everything inside it) to an absolute position
+    fn place_member(
+        &mut self,
+        member: Member,
+        x: f32,
+        y: f32,
+        relative: &HashMap<Member, (f32, f32)>,
+    ) {
+        match member {
+            Member::Class(i) => {
+                let ([left, _, top, _], _, _) = self.side_note_extents(i);
+                let (cx, cy) = (x + left, y + top);
+                self.classes[i].x = cx;
+                self.classes[i].y = cy;
+                self.place_side_notes(i, cx, cy);
             }
-            layers.insert(name.clone(), layer);
+            Member::Note(i) => {
+                self.notes[i].x = x;
+                self.notes[i].y = y;
+            }
+            Member::Package(pi) => {
+                self.packages[pi].x = x;
+                self.packages[pi].y = y;
+                let name = self.packages[pi].name.clone();
+                let content_x = x + PACKAGE_PADDING;
+                let content_y = y + PACKAGE_TAB_HEIGHT + PACKAGE_PADDING;
+                for child in self.members_of(Some(&name)) {
+                    let (rx, ry) = relative.get(&child).copied().unwrap_or((0.0, 0.0));
+                    self.place_member(child, content_x + rx, content_y + ry, relative);
+                }
+            }
+        }
+    }
 
-            if let Some(kids) = children.get(&name) {
-                for kid in kids {
-                    if !layers.contains_key(kid) {
-                        queue.push((kid.clone(), layer + 1));
+    /// Stack a class's side notes along the side they are attached to
+    fn place_side_notes(&mut self, class: usize, cx: f32, cy: f32) {
+        let (width, height) = (self.classes[class].width, self.classes[class].height);
+        for side in [
+            NoteSide::Left,
+            NoteSide::Right,
+            NoteSide::Top,
+            NoteSide::Bottom,
+        ] {
+            let indices: Vec<usize> = self.side_notes(class, side).collect();
+            let (mut along_x, mut along_y) = (cx, cy);
+            for i in indices {
+                let note = &mut self.notes[i];
+                match side {
+                    NoteSide::Left => {
+                        note.x = cx - NOTE_GAP - note.width;
+                        note.y = along_y;
+                        along_y += note.height + NOTE_GAP;
+                    }
+                    NoteSide::Right => {
+                        note.x = cx + width + NOTE_GAP;
+                        note.y = along_y;
+                        along_y += note.height + NOTE_GAP;
+                    }
+                    NoteSide::Top => {
+                        note.x = along_x;
+                        note.y = cy - NOTE_GAP - note.height;
+                        along_x += note.width + NOTE_GAP;
+                    }
+                    NoteSide::Bottom => {
+                        note.x = along_x;
+                        note.y = cy + height + NOTE_GAP;
+                        along_x += note.width + NOTE_GAP;
                     }
                 }
             }
         }
+    }
 
-        // Assign remaining classes
-        let max_layer = layers.values().copied().max().unwrap_or(0);
-        for name in &names {
-            layers.entry(name.clone()).or_insert(max_layer + 1);
-        }
+    /// Direct members of a package, or of the diagram itself for `None`
+    fn members_of(&self, package: Option<&str>) -> Vec<Member> {
+        let packages = self
+            .packages
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.parent() == package)
+            .map(|(i, _)| Member::Package(i));
+        let classes = self
+            .classes
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.package.as_deref() == package)
+            .map(|(i, _)| Member::Class(i));
+        // Notes that are not pinned to a class are placed with the top level
+        let notes = self
+            .notes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| package.is_none() && !matches!(n.target, NoteTarget::Class { .. }))
+            .map(|(i, _)| Member::Note(i));
+        packages.chain(classes).chain(notes).collect()
+    }
 
-        // Group by layer
-        let mut layer_groups: HashMap<usize, Vec<usize>> = HashMap::new();
-        for (i, class) in self.classes.iter().enumerate() {
-            let layer = *layers.get(&class.name).unwrap_or(&0);
-            layer_groups.entry(layer).or_default().push(i);
+    /// The direct member of `container` that holds the class `name`
+    fn member_in(&self, name: &str, container: Option<&str>) -> Option<Member> {
+        let index = self.classes.iter().position(|c| c.name == name)?;
+        let mut package = self.classes[index].package.as_deref();
+        if package == container {
+            return Some(Member::Class(index));
+        }
+        while let Some(p) = package {
+            let parent = p.rsplit_once('.').map(|(parent, _)| parent);
+            if parent == container {
+                return self
+                    .packages
+                    .iter()
+                    .position(|pkg| pkg.name == p)
+                    .map(Member::Package);
+            }
+            package = parent;
         }
+        None
+    }
 
-        // Position classes
-        let mut layer_list: Vec<_> = layer_groups.keys().copied().collect();
-        layer_list.sort();
+    fn member_size(&self, member: Member) -> (f32, f32) {
+        match member {
+            Member::Class(i) => {
+                let ([left, right, top, bottom], stack_w, stack_h) = self.side_note_extents(i);
+                let class = &self.classes[i];
+                (
+                    left + class.width.max(stack_w) + right,
+                    top + class.height.max(stack_h) + bottom,
+                )
+            }
+            Member::Package(i) => (self.packages[i].width, self.packages[i].height),
+            Member::Note(i) => (self.notes[i].width, self.notes[i].height),
+        }
+    }
 
-        let mut current_y = style.margin;
-        for layer in layer_list {
-            let indices = layer_groups.get(&layer).unwrap();
-            let max_height = indices
-                .iter()
-                .map(|&i| self.classes[i].height)
-                .fold(0.0f32, f32::max);
+    /// Classes a free or link note should be laid out next to
+    fn note_neighbours(&self, note: usize) -> Vec<&str> {
+        let note = &self.notes[note];
+        match &note.target {
+            NoteTarget::Class { name, .. } => vec![name.as_str()],
+            NoteTarget::Link(r) => self
+                .relationships
+                .get(*r)
+                .map(|rel| vec![rel.from.as_str(), rel.to.as_str()])
+                .unwrap_or_default(),
+            NoteTarget::Free => note.links.iter().map(String::as_str).collect(),
+        }
+    }
 
-            let mut current_x = style.margin;
-            for &idx in indices {
-                self.classes[idx].x = current_x;
-                self.classes[idx].y = current_y;
-                current_x += self.classes[idx].width + style.spacing_x;
+    /// Lay out the members of one container; returns their relative positions
+    /// and the size of the area they cover
+    fn layout_members(
+        &self,
+        members: &[Member],
+        container: Option<&str>,
+        style: &DiagramStyle,
+        engine: &dyn LayoutEngine,
+    ) -> (Vec<(f32, f32)>, f32, f32) {
+        let sizes: Vec<(f32, f32)> = members.iter().map(|&m| self.member_size(m)).collect();
+        // Left to right is top to bottom with the axes swapped
+        let across = self.direction(style) == LayoutDirection::LeftToRight;
+        let swap = |(a, b): (f32, f32)| if across { (b, a) } else { (a, b) };
+        let index_of = |name: &str| {
+            self.member_in(name, container)
+                .and_then(|m| members.iter().position(|&x| x == m))
+        };
+        let mut edges: Vec<LayoutEdge> = self
+            .relationships
+            .iter()
+            .filter_map(|rel| {
+                let (from, to) = (index_of(&rel.from)?, index_of(&rel.to)?);
+                (from != to).then_some(LayoutEdge {
+                    from,
+                    to,
+                    rel_type: rel.rel_type,
+                    direction: rel
+                        .direction
+                        .map(|d| if across { d.transposed() } else { d }),
+                })
+            })
+            .collect();
+        for (ni, &member) in members.iter().enumerate() {
+            if let Member::Note(i) = member {
+                for name in self.note_neighbours(i) {
+                    if let Some(ci) = index_of(name) {
+                        edges.push(LayoutEdge {
+                            from: ni,
+                            to: ci,
+                            rel_type: RelationType::Association,
+                            direction: None,
+                        });
+                    }
+                }
             }
+        }
+        let groups: Vec<Vec<usize>> = self
+            .together
+            .iter()
+            .map(|group| {
+                let mut indices: Vec<usize> = group.iter().filter_map(|n| index_of(n)).collect();
+                indices.dedup();
+                indices
+            })
+            .filter(|indices| indices.len() > 1)
+            .collect();
 
-            current_y += max_height + style.spacing_y;
+        // Pins are given for the class box; members also hold side notes
+        let origin = if container.is_none() {
+            style.margin
+        } else {
+            0.0
+        };
+        let pinned: Vec<Option<(f32, f32)>> = members
+            .iter()
+            .map(|&member| match member {
+                Member::Class(i) => self.classes[i].pinned.map(|(x, y)| {
+                    let ([left, _, top, _], _, _) = self.side_note_extents(i);
+                    swap((x - left - origin, y - top - origin))
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let place = |sizes: &[(f32, f32)], style: &DiagramStyle| {
+            let graph = LayoutGraph {
+                sizes,
+                edges: &edges,
+                groups: &groups,
+                pinned: &pinned,
+            };
+            arrange(engine, &graph, style)
+        };
+        let mut positions: Vec<(f32, f32)> = if across {
+            let sizes: Vec<(f32, f32)> = sizes.iter().map(|&s| swap(s)).collect();
+            let style = DiagramStyle {
+                spacing_x: style.spacing_y,
+                spacing_y: style.spacing_x,
+                ..style.clone()
+            };
+            place(&sizes, &style).into_iter().map(swap).collect()
+        } else {
+            place(&sizes, style)
+        };
+        // Free boxes fitted above or left of a pin may not fit in the
+        // container; move everything in, pins included
+        let (left, top) = positions
+            .iter()
+            .fold((-origin, -origin), |(x, y), p| (x.min(p.0), y.min(p.1)));
+        for p in &mut positions {
+            *p = (p.0 - left - origin, p.1 - top - origin);
         }
+        let width = positions
+            .iter()
+            .zip(&sizes)
+            .map(|((x, _), (w, _))| x + w)
+            .fold(0.0f32, f32::max);
+        let height = positions
+            .iter()
+            .zip(&sizes)
+            .map(|((_, y), (_, h))| y + h)
+            .fold(0.0f32, f32::max);
+        (positions, width, height)
     }
 
     fn bounds(&self, style: &DiagramStyle) -> (f32, f32) {
-        let max_x = self
+        let boxes = self
             .classes
             .iter()
-            .map(|c| c.x + c.width)
-            .fold(0.0f32, f32::max);
-        let max_y = self
-            .classes
-            .iter()
-            .map(|c| c.y + c.height)
-            .fold(0.0f32, f32::max);
+            .map(|c| {
+                let overhang = if c.generics.is_empty() {
+                    0.0
+                } else {
+                    TEMPLATE_OVERHANG
+                };
+                (c.x + c.width + overhang, c.y + c.height)
+            })
+            .chain(
+                self.packages
+                    .iter()
+                    .map(|p| (p.x + p.width, p.y + p.height)),
+            )
+            .chain(self.notes.iter().map(|n| (n.x + n.width, n.y + n.height)))
+            .chain(
+                self.relationships
+                    .iter()
+                    .flat_map(|r| r.path.iter().copied()),
+            )
+            .chain(
+                self.relationships
+                    .iter()
+                    .flat_map(|r| label_corners(r, style)),
+            );
+        let (max_x, max_y) =
+            boxes.fold((0.0f32, 0.0f32), |(mx, my), (x, y)| (mx.max(x), my.max(y)));
         (max_x + style.margin, max_y + style.margin)
     }
 }
 
+/// A unit placed by the layout: a class with its side notes, a whole
+/// package as one block, or a free-standing note
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Member {
+    Class(usize),
+    Package(usize),
+    Note(usize),
+}
+
+/// Padding between a package frame and its content
+const PACKAGE_PADDING: f32 = 15.0;
+/// Height of the folder tab above a package frame
+const PACKAGE_TAB_HEIGHT: f32 = 18.0;
+
+fn package_tab_width(package: &Package, style: &DiagramStyle) -> f32 {
+    package.display_name().len() as f32 * style.char_width + style.padding * 2.0
+}
+
+/// Radius of the circled kind letter in a class header
+const SPOT_RADIUS: f32 = 8.0;
+
+/// Height of the name compartment at the top of a class box
+const HEADER_HEIGHT: f32 = 25.0;
+/// Height of one literal, field or method row
+const ROW_HEIGHT: f32 = 18.0;
+/// Space above the first and below the last row of a compartment
+const COMPARTMENT_PADDING: f32 = 4.0;
+/// Text baselines sit this far below the row centre
+const BASELINE_OFFSET: f32 = 4.0;
+
+/// Distance between a note and the class it is attached to
+const NOTE_GAP: f32 = 20.0;
+/// Size of a note's folded corner
+const NOTE_FOLD: f32 = 8.0;
+const NOTE_LINE_HEIGHT: f32 = 15.0;
+
+/// How far the template box sticks out past the class's top-right corner
+const TEMPLATE_OVERHANG: f32 = 8.0;
+/// Height of the template box
+const TEMPLATE_HEIGHT: f32 = 16.0;
+/// How far the template box sticks out above the class's top edge
+const TEMPLATE_RISE: f32 = TEMPLATE_HEIGHT / 2.0 + 2.0;
+
+fn template_width(label: &str, style: &DiagramStyle) -> f32 {
+    label.len() as f32 * style.char_width + 8.0
+}
+
+/// Height of the box a relationship label is placed in
+const LABEL_HEIGHT: f32 = 12.0;
+/// Space between a line and the labels beside it
+const LABEL_GAP: f32 = 5.0;
+/// Room for the reading-direction arrow right of a label
+const READING_ARROW_WIDTH: f32 = 16.0;
+
+/// Which text of a relationship a placed label is; `true` for the `to` end
+#[derive(Debug, Clone, Copy)]
+enum LabelSlot {
+    Middle,
+    Multiplicity(bool),
+    Role(bool),
+}
+
+fn label_box_width(label: &str, rel: &Relationship, style: &DiagramStyle) -> f32 {
+    let arrow = if rel.label_direction.is_some() {
+        READING_ARROW_WIDTH
+    } else {
+        0.0
+    };
+    label.len() as f32 * style.char_width + arrow
+}
+
+/// Bottom-right corners of the placed texts of a relationship
+fn label_corners(rel: &Relationship, style: &DiagramStyle) -> Vec<(f32, f32)> {
+    let corner = |text: &Option<String>, at: Option<(f32, f32)>, extra: f32| {
+        let width = text.as_ref()?.len() as f32 * style.char_width;
+        let (x, y) = at?;
+        Some((x + width / 2.0 + extra, y + 2.0))
+    };
+    let arrow = if rel.label_direction.is_some() {
+        READING_ARROW_WIDTH
+    } else {
+        0.0
+    };
+    [
+        corner(&rel.label, rel.label_at, arrow),
+        corner(
+            &rel.from_end.multiplicity,
+            rel.from_end.multiplicity_at,
+            0.0,
+        ),
+        corner(&rel.from_end.role, rel.from_end.role_at, 0.0),
+        corner(&rel.to_end.multiplicity, rel.to_end.multiplicity_at, 0.0),
+        corner(&rel.to_end.role, rel.to_end.role_at, 0.0),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Candidates for a label beside the line near its middle: above or right of
+/// the line first, then further out along it
+fn path_label(path: &[(f32, f32)], width: f32) -> LabelRequest {
+    let lengths: Vec<f32> = path
+        .windows(2)
+        .map(|w| (w[1].0 - w[0].0).abs() + (w[1].1 - w[0].1).abs())
+        .collect();
+    let total: f32 = lengths.iter().sum();
+
+    let mut candidates = Vec::new();
+    for fraction in [0.5f32, 0.4, 0.6, 0.3, 0.7, 0.2, 0.8] {
+        // Walk to the point this far along the line
+        let mut left = total * fraction;
+        let mut segment = 0;
+        while segment + 1 < lengths.len() && left > lengths[segment] {
+            left -= lengths[segment];
+            segment += 1;
+        }
+        let (a, b) = (path[segment], path[segment + 1]);
+        let t = if lengths[segment] > 0.0 {
+            left / lengths[segment]
+        } else {
+            0.0
+        };
+        let (px, py) = (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+
+        let cost = (fraction - 0.5).abs() * 60.0;
+        if (b.0 - a.0).abs() >= (b.1 - a.1).abs() {
+            let dy = LABEL_HEIGHT / 2.0 + LABEL_GAP;
+            candidates.push(((px, py - dy), cost));
+            candidates.push(((px, py + dy), cost + 5.0));
+        } else {
+            let dx = width / 2.0 + LABEL_GAP;
+            candidates.push(((px + dx, py), cost));
+            candidates.push(((px - dx, py), cost + 5.0));
+        }
+    }
+    LabelRequest {
+        width,
+        height: LABEL_HEIGHT,
+        candidates,
+    }
+}
+
+/// Candidates for a multiplicity or role beside the end of a line at `end`,
+/// whose first segment heads towards `next`. Multiplicities prefer the side
+/// above or right of the line, roles the other.
+fn end_label(end: (f32, f32), next: (f32, f32), width: f32, role: bool) -> LabelRequest {
+    let horizontal = (next.0 - end.0).abs() >= (next.1 - end.1).abs();
+    let mut candidates = Vec::new();
+    for (step, along) in [6.0f32, 18.0, 30.0].into_iter().enumerate() {
+        for side in [-1.0f32, 1.0] {
+            let (centre, above_or_right) = if horizontal {
+                let dir = if next.0 >= end.0 { 1.0 } else { -1.0 };
+                let x = end.0 + dir * (along + width / 2.0);
+                let y = end.1 + side * (LABEL_HEIGHT / 2.0 + LABEL_GAP);
+                ((x, y), side < 0.0)
+            } else {
+                let dir = if next.1 >= end.1 { 1.0 } else { -1.0 };
+                let y = end.1 + dir * (along + LABEL_HEIGHT / 2.0);
+                let x = end.0 + side * (width / 2.0 + LABEL_GAP);
+                ((x, y), side > 0.0)
+            };
+            let preferred = above_or_right != role;
+            let cost = step as f32 * 4.0 + if preferred { 0.0 } else { 6.0 };
+            candidates.push((centre, cost));
+        }
+    }
+    LabelRequest {
+        width,
+        height: LABEL_HEIGHT,
+        candidates,
+    }
+}
+
 fn format_member(vis: Option<Visibility>, name: &str, typ: Option<&str>) -> String {
     let v = vis.map(|v| v.symbol()).unwrap_or("");
     match typ {
@@ -581,6 +3298,23 @@ fn format_method_text(m: &Method) -> String {
 // Renderer
 // ============================================================================
 
+/// Parse leniently, pin classes from the layout file, and lay out with `engine`
+pub fn laid_out(
+    source: &str,
+    style: &DiagramStyle,
+    layout: Option<&str>,
+    engine: &dyn LayoutEngine,
+) -> ClassDiagram {
+    let mut diagram = Parser::new().parse(source);
+    if let Some(layout) = layout {
+        if let Err(e) = diagram.apply_layout(layout) {
+            eprintln!("Warning: {}", e);
+        }
+    }
+    diagram.layout_with(style, engine);
+    diagram
+}
+
 /// Render diagram with default behavior (no file CSS)
 pub fn render(source: &str, style: &DiagramStyle) -> String {
     render_with_file_css(source, style, None)
@@ -588,8 +3322,20 @@ pub fn render(source: &str, style: &DiagramStyle) -> String {
 
 /// Render diagram with optional file CSS layer
 pub fn render_with_file_css(source: &str, style: &DiagramStyle, file_css: Option<&str>) -> String {
-    let mut diagram = Parser::new().parse(source);
-    diagram.layout(style);
+    render_with_layout(source, style, file_css, None, &LayeredLayout)
+}
+
+/// Render diagram with optional file CSS layer and layout file, placing
+/// classes with `engine`. A layout file that cannot be read is reported and
+/// otherwise ignored.
+pub fn render_with_layout(
+    source: &str,
+    style: &DiagramStyle,
+    file_css: Option<&str>,
+    layout: Option<&str>,
+    engine: &dyn LayoutEngine,
+) -> String {
+    let diagram = laid_out(source, style, layout, engine);
 
     let (width, height) = diagram.bounds(style);
     let inline_css = crate::common::extract_custom_css(source);
@@ -600,12 +3346,18 @@ pub fn render_with_file_css(source: &str, style: &DiagramStyle, file_css: Option
 
     // Check for shadows on each class type and create filters
     let class_types = [
-        ("class", "class-shadow"),
-        ("interface", "interface-shadow"),
-        ("abstract-class", "abstract-class-shadow"),
-        ("enum", "enum-shadow"),
+        ClassType::Class,
+        ClassType::Interface,
+        ClassType::Abstract,
+        ClassType::Enum,
+        ClassType::Struct,
+        ClassType::Trait,
+        ClassType::Union,
+        ClassType::TypeAlias,
     ];
-    for (class_name, filter_id) in &class_types {
+    for class_type in class_types {
+        let class_name = class_type.css_class();
+        let filter_id = format!("{}-shadow", class_name);
         if svg.has_shadow(class_name) {
             let dx = svg.css_prop_or(class_name, "shadow-dx", 0.0);
             let dy = svg.css_prop_or(class_name, "shadow-dy", 0.0);
@@ -635,34 +3387,218 @@ pub fn render_with_file_css(source: &str, style: &DiagramStyle, file_css: Option
 <marker id="cls-diamond-empty" viewBox="0 0 12 12" refX="12" refY="6" markerWidth="12" markerHeight="12" orient="auto-start-reverse">
 <path d="M 0 6 L 6 0 L 12 6 L 6 12 z" class="marker-diamond-empty"/>
 </marker>
+<marker id="cls-plus" viewBox="0 0 12 12" refX="12" refY="6" markerWidth="10" markerHeight="10" orient="auto-start-reverse">
+<circle cx="6" cy="6" r="5.5" class="marker-plus"/>
+<path d="M 6 2 L 6 10 M 2 6 L 10 6" class="marker-plus"/>
+</marker>
+<marker id="cls-square" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="8" markerHeight="8" orient="auto-start-reverse">
+<rect x="0.5" y="0.5" width="9" height="9" class="marker-square"/>
+</marker>
+<marker id="cls-cross" viewBox="0 0 14 10" refX="14" refY="5" markerWidth="10" markerHeight="8" orient="auto-start-reverse">
+<path d="M 1 1 L 9 9 M 1 9 L 9 1" class="marker-cross"/>
+</marker>
+<marker id="cls-zero-or-one" viewBox="0 0 20 12" refX="20" refY="6" markerWidth="14" markerHeight="10" orient="auto-start-reverse">
+<path d="M 14 0 L 14 12" class="marker-crow"/>
+<circle cx="7" cy="6" r="3.5" class="marker-crow"/>
+</marker>
+<marker id="cls-exactly-one" viewBox="0 0 20 12" refX="20" refY="6" markerWidth="14" markerHeight="10" orient="auto-start-reverse">
+<path d="M 10 0 L 10 12 M 14 0 L 14 12" class="marker-crow"/>
+</marker>
+<marker id="cls-zero-or-many" viewBox="0 0 20 12" refX="20" refY="6" markerWidth="14" markerHeight="10" orient="auto-start-reverse">
+<path d="M 12 6 L 20 0 M 12 6 L 20 12 M 12 6 L 20 6" class="marker-crow"/>
+<circle cx="7" cy="6" r="3.5" class="marker-crow"/>
+</marker>
+<marker id="cls-one-or-many" viewBox="0 0 20 12" refX="20" refY="6" markerWidth="14" markerHeight="10" orient="auto-start-reverse">
+<path d="M 12 6 L 20 0 M 12 6 L 20 12 M 12 6 L 20 6 M 9 0 L 9 12" class="marker-crow"/>
+</marker>
 "#);
     defs.push_str("</defs>");
     svg.push(&defs);
 
-    // Render relationships first (behind classes)
-    for rel in &diagram.relationships {
-        render_relationship(&mut svg, &diagram, rel, style);
+    // Render packages first, outermost at the back
+    let mut packages: Vec<&Package> = diagram.packages.iter().collect();
+    packages.sort_by_key(|p| p.depth());
+    for package in packages {
+        render_package(&mut svg, package, style);
+    }
+
+    // Render relationships and note connectors (behind classes)
+    for rel in &diagram.relationships {
+        render_relationship(&mut svg, &diagram, rel, style);
+    }
+    // Notes on a hidden class are hidden with it
+    let notes: Vec<&Note> = diagram
+        .notes
+        .iter()
+        .filter(|note| match &note.target {
+            NoteTarget::Class { name, .. } => diagram.visible_class(name).is_some(),
+            _ => true,
+        })
+        .collect();
+    for note in &notes {
+        render_note_connectors(&mut svg, &diagram, note);
+    }
+
+    // Render classes
+    for class in diagram.classes.iter().filter(|c| !c.hidden) {
+        render_class(&mut svg, class, style);
+        render_template(&mut svg, class, style);
+    }
+
+    for note in notes {
+        render_note(&mut svg, note, style);
+    }
+
+    svg.finish()
+}
+
+/// Draw a package as a folder: a name tab on top of the frame
+fn render_package(svg: &mut SvgBuilder, package: &Package, style: &DiagramStyle) {
+    let tab_width = package_tab_width(package, style);
+    svg.rect_class(
+        package.x,
+        package.y,
+        tab_width,
+        PACKAGE_TAB_HEIGHT,
+        "package-tab",
+    );
+    svg.rect_class(
+        package.x,
+        package.y + PACKAGE_TAB_HEIGHT,
+        package.width,
+        package.height - PACKAGE_TAB_HEIGHT,
+        "package",
+    );
+    svg.text_class(
+        package.x + style.padding,
+        package.y + PACKAGE_TAB_HEIGHT - 5.0,
+        package.display_name(),
+        "package-name",
+    );
+}
+
+/// Draw a note as a box with a folded top-right corner
+fn render_note(svg: &mut SvgBuilder, note: &Note, style: &DiagramStyle) {
+    let (x, y, w, h) = (note.x, note.y, note.width, note.height);
+    svg.polygon_class(
+        &[
+            (x, y),
+            (x + w - NOTE_FOLD, y),
+            (x + w, y + NOTE_FOLD),
+            (x + w, y + h),
+            (x, y + h),
+        ],
+        "note",
+    );
+    svg.polyline_class(
+        &[
+            (x + w - NOTE_FOLD, y),
+            (x + w - NOTE_FOLD, y + NOTE_FOLD),
+            (x + w, y + NOTE_FOLD),
+        ],
+        "note",
+        "",
+    );
+    for (i, line) in note.lines().enumerate() {
+        svg.text_class(
+            x + style.padding,
+            y + style.padding + (i as f32 + 1.0) * NOTE_LINE_HEIGHT - 4.0,
+            line,
+            "note-text",
+        );
+    }
+}
+
+/// Draw the dashed lines from a note to what it annotates
+fn render_note_connectors(svg: &mut SvgBuilder, diagram: &ClassDiagram, note: &Note) {
+    let note_box = (note.x, note.y, note.width, note.height);
+    let class_box = |name: &str| {
+        diagram
+            .visible_class(name)
+            .map(|c| (c.x, c.y, c.width, c.height))
+    };
+
+    let targets: Vec<(f32, f32)> = match &note.target {
+        NoteTarget::Class { name, .. } => class_box(name).into_iter().map(center).collect(),
+        NoteTarget::Free => note
+            .links
+            .iter()
+            .filter_map(|name| class_box(name))
+            .map(center)
+            .collect(),
+        // Undrawn links have no path and so no connector
+        NoteTarget::Link(r) => {
+            let path = &diagram.relationships[*r].path;
+            path.get(path.len() / 2).copied().into_iter().collect()
+        }
+    };
+
+    for target in targets {
+        let start = box_edge_point(note_box, target);
+        // Class targets end on the class outline, link targets on the line itself
+        let end = match &note.target {
+            NoteTarget::Link(_) => target,
+            NoteTarget::Class { name, .. } => class_box(name)
+                .map(|b| box_edge_point(b, start))
+                .unwrap_or(target),
+            NoteTarget::Free => note
+                .links
+                .iter()
+                .filter_map(|name| class_box(name))
+                .find(|&b| center(b) == target)
+                .map(|b| box_edge_point(b, start))
+                .unwrap_or(target),
+        };
+        svg.line_class(start.0, start.1, end.0, end.1, "note-connector");
     }
+}
 
-    // Render classes
-    for class in &diagram.classes {
-        render_class(&mut svg, class, style);
+fn center((x, y, w, h): (f32, f32, f32, f32)) -> (f32, f32) {
+    (x + w / 2.0, y + h / 2.0)
+}
+
+/// Where the line from the center of a box towards `toward` leaves the box
+fn box_edge_point(rect: (f32, f32, f32, f32), toward: (f32, f32)) -> (f32, f32) {
+    let (cx, cy) = center(rect);
+    let (dx, dy) = (toward.0 - cx, toward.1 - cy);
+    if dx == 0.0 && dy == 0.0 {
+        return (cx, cy);
     }
+    let tx = if dx == 0.0 {
+        f32::INFINITY
+    } else {
+        rect.2 / 2.0 / dx.abs()
+    };
+    let ty = if dy == 0.0 {
+        f32::INFINITY
+    } else {
+        rect.3 / 2.0 / dy.abs()
+    };
+    let t = tx.min(ty).min(1.0);
+    (cx + dx * t, cy + dy * t)
+}
 
-    svg.finish()
+/// Draw the dashed UML template box over a generic class's top-right corner
+fn render_template(svg: &mut SvgBuilder, class: &ClassDef, style: &DiagramStyle) {
+    let Some(label) = class.template_label() else {
+        return;
+    };
+    let width = template_width(&label, style);
+    let x = class.x + class.width - width + TEMPLATE_OVERHANG;
+    let y = class.y - TEMPLATE_RISE;
+    svg.rect_class(x, y, width, TEMPLATE_HEIGHT, "class-template");
+    svg.text_class(
+        x + width / 2.0,
+        y + TEMPLATE_HEIGHT - 4.0,
+        &label,
+        "class-template-text",
+    );
 }
 
 fn render_class(svg: &mut SvgBuilder, class: &ClassDef, style: &DiagramStyle) {
-    let compartment_height = 25.0;
-    let field_height = 18.0;
-
     // Determine class CSS based on type
-    let (box_class, filter_id) = match class.class_type {
-        ClassType::Interface => ("interface", "interface-shadow"),
-        ClassType::Abstract => ("abstract-class", "abstract-class-shadow"),
-        ClassType::Enum => ("enum", "enum-shadow"),
-        ClassType::Class => ("class", "class-shadow"),
-    };
+    let box_class = class.class_type.css_class();
+    let filter_id = format!("{}-shadow", box_class);
 
     // Get border radius from CSS custom properties
     let rx = svg.css_prop_or(box_class, "rx", 0.0);
@@ -670,7 +3606,7 @@ fn render_class(svg: &mut SvgBuilder, class: &ClassDef, style: &DiagramStyle) {
 
     // Apply shadow filter if defined
     let filter = if svg.has_shadow(box_class) {
-        Some(filter_id)
+        Some(filter_id.as_str())
     } else {
         None
     };
@@ -700,24 +3636,48 @@ fn render_class(svg: &mut SvgBuilder, class: &ClassDef, style: &DiagramStyle) {
         y += 10.0;
     }
 
-    // Name (italic for interface/abstract)
+    // Name (italic for interface/abstract/trait)
     let name_class = match class.class_type {
-        ClassType::Interface => "interface-name",
+        ClassType::Interface | ClassType::Trait => "interface-name",
         ClassType::Abstract => "abstract-class-name",
         _ => "class-name",
     };
     svg.text_class(
         class.x + class.width / 2.0,
-        y + compartment_height / 2.0 + 4.0,
-        &class.name,
+        y + HEADER_HEIGHT / 2.0 + BASELINE_OFFSET,
+        class.display_name(),
         name_class,
     );
 
-    y = class.y + compartment_height;
+    // Circled kind letter left of the name
+    if let Some(letter) = class.spot() {
+        let cx = class.x + style.padding + SPOT_RADIUS;
+        let cy = y + HEADER_HEIGHT / 2.0;
+        svg.push(&format!(
+            r#"<circle cx="{}" cy="{}" r="{}" class="spot spot-{}"/>"#,
+            cx, cy, SPOT_RADIUS, box_class
+        ));
+        svg.text_class(cx, cy + BASELINE_OFFSET, &letter.to_string(), "spot-text");
+    }
+
+    y = class.y + HEADER_HEIGHT;
+
+    // Aliased type of `type Foo = Bar`
+    if let Some(ref aliased) = class.aliased {
+        svg.line_class(class.x, y, class.x + class.width, y, "class-separator");
+        y += COMPARTMENT_PADDING + ROW_HEIGHT;
+        svg.text_class(
+            class.x + style.padding,
+            y,
+            &format!("= {}", aliased),
+            "type-alias-target",
+        );
+        y += COMPARTMENT_PADDING;
+    }
 
     // Separator class based on type
     let separator_class = match class.class_type {
-        ClassType::Interface => "interface-separator",
+        ClassType::Interface | ClassType::Trait => "interface-separator",
         ClassType::Abstract => "abstract-class-separator",
         _ => "class-separator",
     };
@@ -725,11 +3685,39 @@ fn render_class(svg: &mut SvgBuilder, class: &ClassDef, style: &DiagramStyle) {
     // Separator after header
     svg.line_class(class.x, y, class.x + class.width, y, separator_class);
 
+    // Enum literals, with any variant payload in its own tspan
+    if !class.literals.is_empty() {
+        y += COMPARTMENT_PADDING;
+        for literal in &class.literals {
+            y += ROW_HEIGHT;
+            let data = literal.data_label();
+            let data = if data.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    r#"<tspan class="enum-literal-data">{}</tspan>"#,
+                    escape_xml(&data)
+                )
+            };
+            svg.push(&format!(
+                r#"<text x="{}" y="{}" class="enum-literal">{}{}</text>"#,
+                class.x + style.padding,
+                y,
+                escape_xml(&literal.name),
+                data
+            ));
+        }
+        y += COMPARTMENT_PADDING;
+        if !class.fields.is_empty() || !class.methods.is_empty() {
+            svg.line_class(class.x, y, class.x + class.width, y, separator_class);
+        }
+    }
+
     // Fields (interfaces don't have fields, but we handle it gracefully)
     if !class.fields.is_empty() {
-        y += 4.0;
+        y += COMPARTMENT_PADDING;
         for field in &class.fields {
-            y += field_height;
+            y += ROW_HEIGHT;
             let text = format_member(field.visibility, &field.name, field.field_type.as_deref());
             let field_class = match (class.class_type, field.is_static) {
                 (ClassType::Abstract, true) => {
@@ -747,15 +3735,15 @@ fn render_class(svg: &mut SvgBuilder, class: &ClassDef, style: &DiagramStyle) {
                 escape_xml(&text)
             ));
         }
-        y += 4.0;
+        y += COMPARTMENT_PADDING;
         svg.line_class(class.x, y, class.x + class.width, y, separator_class);
     }
 
     // Methods
     if !class.methods.is_empty() {
-        y += 4.0;
+        y += COMPARTMENT_PADDING;
         for method in &class.methods {
-            y += field_height;
+            y += ROW_HEIGHT;
             let text = format_method_text(method);
             let method_class = match class.class_type {
                 ClassType::Interface => {
@@ -799,27 +3787,20 @@ fn render_relationship(
     svg: &mut SvgBuilder,
     diagram: &ClassDiagram,
     rel: &Relationship,
-    _style: &DiagramStyle,
+    style: &DiagramStyle,
 ) {
-    let from = diagram.classes.iter().find(|c| c.name == rel.from);
-    let to = diagram.classes.iter().find(|c| c.name == rel.to);
+    if rel.hidden {
+        return;
+    }
+    let from = diagram.visible_class(&rel.from);
+    let to = diagram.visible_class(&rel.to);
 
     let (from, to) = match (from, to) {
         (Some(f), Some(t)) => (f, t),
         _ => return,
     };
 
-    let (dashed, marker_start, marker_end) = match rel.rel_type {
-        RelationType::Inheritance => (false, "", "url(#cls-triangle)"),
-        RelationType::Realization => (true, "", "url(#cls-triangle)"),
-        RelationType::Composition => (false, "url(#cls-diamond-filled)", ""),
-        RelationType::Aggregation => (false, "url(#cls-diamond-empty)", ""),
-        RelationType::Association => (false, "", ""),
-        RelationType::Dependency => (true, "", "url(#cls-arrow)"),
-        RelationType::DirectedAssoc => (false, "", "url(#cls-arrow)"),
-    };
-
-    let points = calculate_path(from, to, rel.rel_type);
+    let points = &rel.path;
 
     if !points.is_empty() {
         let points_str: String = points
@@ -827,36 +3808,155 @@ fn render_relationship(
             .map(|(x, y)| format!("{},{}", x, y))
             .collect::<Vec<_>>()
             .join(" ");
-        let class = if dashed {
+        let class = if rel.dashed {
             "relationship relationship-dashed"
         } else {
             "relationship"
         };
-        let ms = if marker_start.is_empty() {
-            String::new()
-        } else {
-            format!(r#" marker-start="{}""#, marker_start)
-        };
-        let me = if marker_end.is_empty() {
-            String::new()
-        } else {
-            format!(r#" marker-end="{}""#, marker_end)
-        };
+        let ms = rel
+            .from_marker
+            .marker_id()
+            .map(|id| format!(r#" marker-start="url(#{})""#, id))
+            .unwrap_or_default();
+        let me = rel
+            .to_marker
+            .marker_id()
+            .map(|id| format!(r#" marker-end="url(#{})""#, id))
+            .unwrap_or_default();
 
         svg.push(&format!(
             r#"<polyline points="{}" class="{}"{}{}/>"#,
             points_str, class, ms, me
         ));
 
-        if let Some(ref label) = rel.label {
-            let mid = points.len() / 2;
-            let (mx, my) = points.get(mid).copied().unwrap_or((0.0, 0.0));
-            svg.text_class(mx, my - 5.0, label, "relationship-label");
+        if let (Some(label), Some((x, y))) = (&rel.label, rel.label_at) {
+            svg.text_class(x, y, label, "relationship-label");
+
+            if let Some(direction) = rel.label_direction {
+                let (target, source) = match direction {
+                    LabelDirection::Forward => (to, from),
+                    LabelDirection::Backward => (from, to),
+                };
+                let label_width = label.len() as f32 * style.char_width;
+                render_reading_arrow(svg, x + label_width / 2.0 + 8.0, y - 3.0, source, target);
+            }
+        }
+
+        for end in [&rel.from_end, &rel.to_end] {
+            if let (Some(mult), Some((x, y))) = (&end.multiplicity, end.multiplicity_at) {
+                svg.text_class(x, y, mult, "relationship-multiplicity");
+            }
+            if let (Some(role), Some((x, y))) = (&end.role, end.role_at) {
+                svg.text_class(x, y, role, "relationship-role");
+            }
+        }
+    }
+}
+
+/// Draw a small filled triangle pointing from `source` towards `target`
+fn render_reading_arrow(
+    svg: &mut SvgBuilder,
+    x: f32,
+    y: f32,
+    source: &ClassDef,
+    target: &ClassDef,
+) {
+    let dx = (target.x + target.width / 2.0) - (source.x + source.width / 2.0);
+    let dy = (target.y + target.height / 2.0) - (source.y + source.height / 2.0);
+    let s = 4.0;
+    let points = if dx.abs() >= dy.abs() {
+        let d = if dx >= 0.0 { s } else { -s };
+        [(x - d, y - s), (x + d, y), (x - d, y + s)]
+    } else {
+        let d = if dy >= 0.0 { s } else { -s };
+        [(x - s, y - d), (x, y + d), (x + s, y - d)]
+    };
+    svg.polygon_class(&points, "relationship-label-arrow");
+}
+
+/// Path of a relationship line, attached to member rows when it names any
+fn relationship_path(
+    from: &ClassDef,
+    to: &ClassDef,
+    rel: &Relationship,
+    direction: LayoutDirection,
+) -> Vec<(f32, f32)> {
+    let from_row = rel
+        .from_member
+        .as_deref()
+        .and_then(|m| member_row_y(from, m));
+    let to_row = rel.to_member.as_deref().and_then(|m| member_row_y(to, m));
+    if from_row.is_some() || to_row.is_some() {
+        return member_path(from, from_row, to, to_row);
+    }
+    match direction {
+        LayoutDirection::TopToBottom => calculate_path(from.rect(), to.rect(), rel.rel_type),
+        // Route the mirrored boxes and mirror the path back, so the
+        // preference for vertical inheritance lines turns sideways
+        LayoutDirection::LeftToRight => {
+            let flip = |r: Rect| Rect::new(r.y, r.x, r.height, r.width);
+            calculate_path(flip(from.rect()), flip(to.rect()), rel.rel_type)
+                .into_iter()
+                .map(|(x, y)| (y, x))
+                .collect()
+        }
+    }
+}
+
+/// Vertical centre of the literal, field or method row named `member`,
+/// following the row positions used by `render_class`
+fn member_row_y(class: &ClassDef, member: &str) -> Option<f32> {
+    let mut y = class.y + HEADER_HEIGHT;
+    if class.aliased.is_some() {
+        y += COMPARTMENT_PADDING + ROW_HEIGHT + COMPARTMENT_PADDING;
+    }
+    let compartments: [Vec<&str>; 3] = [
+        class.literals.iter().map(|l| l.name.as_str()).collect(),
+        class.fields.iter().map(|f| f.name.as_str()).collect(),
+        class.methods.iter().map(|m| m.name.as_str()).collect(),
+    ];
+    for rows in compartments.iter().filter(|rows| !rows.is_empty()) {
+        y += COMPARTMENT_PADDING;
+        if let Some(index) = rows.iter().position(|&name| name == member) {
+            return Some(y + (index + 1) as f32 * ROW_HEIGHT - BASELINE_OFFSET);
         }
+        y += rows.len() as f32 * ROW_HEIGHT + COMPARTMENT_PADDING;
+    }
+    None
+}
+
+/// Route a line between member rows. An end with a row leaves from that row
+/// on the side facing the other class; an end without one uses the box centre.
+/// Boxes stacked over each other are joined around their right-hand side.
+fn member_path(
+    from: &ClassDef,
+    from_row: Option<f32>,
+    to: &ClassDef,
+    to_row: Option<f32>,
+) -> Vec<(f32, f32)> {
+    let route_margin = 15.0;
+    let sy = from_row.unwrap_or(from.y + from.height / 2.0);
+    let ey = to_row.unwrap_or(to.y + to.height / 2.0);
+
+    if to.x - (from.x + from.width) > route_margin * 2.0 {
+        let (sx, ex) = (from.x + from.width, to.x);
+        let mid_x = (sx + ex) / 2.0;
+        return vec![(sx, sy), (mid_x, sy), (mid_x, ey), (ex, ey)];
+    }
+    if from.x - (to.x + to.width) > route_margin * 2.0 {
+        let (sx, ex) = (from.x, to.x + to.width);
+        let mid_x = (sx + ex) / 2.0;
+        return vec![(sx, sy), (mid_x, sy), (mid_x, ey), (ex, ey)];
     }
+
+    let (sx, ex) = (from.x + from.width, to.x + to.width);
+    let route_x = sx.max(ex) + route_margin;
+    vec![(sx, sy), (route_x, sy), (route_x, ey), (ex, ey)]
 }
 
-fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec<(f32, f32)> {
+/// Elbow between two boxes given as `(x, y, width, height)`, for when the
+/// router finds no way round
+fn calculate_path(from: Rect, to: Rect, rel_type: RelationType) -> Vec<(f32, f32)> {
     let from_cx = from.x + from.width / 2.0;
     let from_cy = from.y + from.height / 2.0;
     let to_cx = to.x + to.width / 2.0;
@@ -874,7 +3974,7 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
     if prefer_vertical && dy.abs() > 20.0 {
         if dy > 0.0 {
             // From is above To
-            let (sx, sy) = (from_cx, from.y + from.height);
+            let (sx, sy) = (from_cx, from.bottom());
             let (ex, ey) = (to_cx, to.y);
             if (sx - ex).abs() < 10.0 {
                 return vec![(sx, sy), (ex, ey)];
@@ -883,7 +3983,7 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
             return vec![(sx, sy), (sx, mid_y), (ex, mid_y), (ex, ey)];
         } else {
             let (sx, sy) = (from_cx, from.y);
-            let (ex, ey) = (to_cx, to.y + to.height);
+            let (ex, ey) = (to_cx, to.bottom());
             if (sx - ex).abs() < 10.0 {
                 return vec![(sx, sy), (ex, ey)];
             }
@@ -895,7 +3995,7 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
     // Horizontal routing
     if dx.abs() > dy.abs() || !prefer_vertical {
         if dx > 0.0 {
-            let (sx, sy) = (from.x + from.width, from_cy);
+            let (sx, sy) = (from.right(), from_cy);
             let (ex, ey) = (to.x, to_cy);
             if ex - sx > route_margin * 2.0 {
                 let mid_x = (sx + ex) / 2.0;
@@ -904,7 +4004,7 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
             let route_y = if from_cy > to_cy {
                 from.y.min(to.y) - route_margin
             } else {
-                (from.y + from.height).max(to.y + to.height) + route_margin
+                from.bottom().max(to.bottom()) + route_margin
             };
             return vec![
                 (sx, sy),
@@ -916,7 +4016,7 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
             ];
         } else {
             let (sx, sy) = (from.x, from_cy);
-            let (ex, ey) = (to.x + to.width, to_cy);
+            let (ex, ey) = (to.right(), to_cy);
             if sx - ex > route_margin * 2.0 {
                 let mid_x = (sx + ex) / 2.0;
                 return vec![(sx, sy), (mid_x, sy), (mid_x, ey), (ex, ey)];
@@ -924,7 +4024,7 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
             let route_y = if from_cy > to_cy {
                 from.y.min(to.y) - route_margin
             } else {
-                (from.y + from.height).max(to.y + to.height) + route_margin
+                from.bottom().max(to.bottom()) + route_margin
             };
             return vec![
                 (sx, sy),
@@ -939,26 +4039,57 @@ fn calculate_path(from: &ClassDef, to: &ClassDef, rel_type: RelationType) -> Vec
 
     // Vertical
     if dy > 0.0 {
-        let (sx, sy) = (from_cx, from.y + from.height);
+        let (sx, sy) = (from_cx, from.bottom());
         let (ex, ey) = (to_cx, to.y);
         let mid_y = (sy + ey) / 2.0;
         vec![(sx, sy), (sx, mid_y), (ex, mid_y), (ex, ey)]
     } else {
         let (sx, sy) = (from_cx, from.y);
-        let (ex, ey) = (to_cx, to.y + to.height);
+        let (ex, ey) = (to_cx, to.bottom());
         let mid_y = (sy + ey) / 2.0;
         vec![(sx, sy), (sx, mid_y), (ex, mid_y), (ex, ey)]
     }
 }
 
+/// Whether `rest` is a name, with any generic parameters, followed by a body,
+/// `=` or nothing
+fn declares_name(rest: &str) -> bool {
+    let rest = rest.trim();
+    let end = rest
+        .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '.' | ':')))
+        .unwrap_or(rest.len());
+    if end == 0 {
+        return false;
+    }
+    let mut tail = &rest[end..];
+    if tail.starts_with('<') && !tail.starts_with("<<") {
+        let mut depth = 0;
+        let Some(close) = tail.find(|c| {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        }) else {
+            return false;
+        };
+        tail = &tail[close + 1..];
+    }
+    let tail = tail.trim_start();
+    tail.is_empty() || tail.starts_with('{') || tail.starts_with('=')
+}
+
 /// Check if source looks like a class diagram
 pub fn is_class_diagram(source: &str) -> bool {
     for line in source.lines() {
         let line = line.trim();
-        if line.starts_with("class ")
-            || line.starts_with("interface ")
-            || line.starts_with("abstract ")
-            || line.starts_with("enum ")
+        if ClassType::from_decl(line).is_some()
+            || line.starts_with("impl ")
+            || line.starts_with("impl<")
+            || line.strip_prefix("mod ").is_some_and(declares_name)
+            || line.starts_with("package ")
+            || line.starts_with("namespace ")
             || line.contains("--|>")
             || line.contains("<|--")
             || line.contains("..|>")
@@ -1001,9 +4132,669 @@ mod tests {
         assert_eq!(diagram.relationships[0].rel_type, RelationType::Inheritance);
     }
 
+    #[test]
+    fn test_parse_packages() {
+        let source = "@start_uml\npackage net {\npackage core {\nclass Socket {\n+ open()\n}\n}\nclass Addr\n}\nnet.core.Socket --> net.Addr\nutil.Buffer --> Socket\n@end_uml";
+        let diagram = Parser::new().parse(source);
+        let names: Vec<_> = diagram.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["net", "net.core", "util"]);
+
+        // Classes are keyed by their full path; dotted references and a
+        // short name used outside the package resolve to them
+        assert_eq!(diagram.classes.len(), 3);
+        assert_eq!(diagram.relationships[0].from, "net.core.Socket");
+        assert_eq!(diagram.relationships[0].to, "net.Addr");
+        assert_eq!(diagram.relationships[1].to, "net.core.Socket");
+        let buffer = diagram
+            .classes
+            .iter()
+            .find(|c| c.name == "util.Buffer")
+            .unwrap();
+        assert_eq!(buffer.package.as_deref(), Some("util"));
+        assert_eq!(buffer.display_name(), "Buffer");
+    }
+
+    #[test]
+    fn test_same_name_in_two_packages() {
+        let source = "@start_uml\npackage shop {\nclass Order {\n+ total: u32\n}\nclass Cart\nCart --> Order\n}\npackage billing {\nclass Order {\n+ invoice: u32\n}\nOrder --> shop.Order\n}\nclass Order\nOrder --> Cart\n@end_uml";
+        let diagram = ClassDiagram::parse(source).unwrap();
+        let names: Vec<&str> = diagram.classes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["shop.Order", "shop.Cart", "billing.Order", "Order"]);
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+        assert_eq!(class("shop.Order").fields[0].name, "total");
+        assert_eq!(class("billing.Order").fields[0].name, "invoice");
+        assert_eq!(class("billing.Order").display_name(), "Order");
+
+        // Short names resolve in the current package before the top level
+        let ends: Vec<_> = diagram
+            .relationships
+            .iter()
+            .map(|r| (r.from.as_str(), r.to.as_str()))
+            .collect();
+        assert_eq!(
+            ends,
+            [
+                ("shop.Cart", "shop.Order"),
+                ("billing.Order", "shop.Order"),
+                ("Order", "shop.Cart"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multiplicities() {
+        let source = "@start_uml\nOrder \"1\" *-- \"items 0..*\" LineItem : contains >\nCustomer \"buyer\" <-- \"*\" Order : < places\n@end_uml";
+        let diagram = Parser::new().parse(source);
+        assert_eq!(diagram.classes.len(), 3);
+
+        let contains = &diagram.relationships[0];
+        assert_eq!(
+            (contains.from.as_str(), contains.to.as_str()),
+            ("Order", "LineItem")
+        );
+        assert_eq!(contains.label.as_deref(), Some("contains"));
+        assert_eq!(contains.label_direction, Some(LabelDirection::Forward));
+        assert_eq!(contains.from_end.multiplicity.as_deref(), Some("1"));
+        assert_eq!(contains.to_end.multiplicity.as_deref(), Some("0..*"));
+        assert_eq!(contains.to_end.role.as_deref(), Some("items"));
+
+        // `<--` swaps the ends, so the `<` label reads from -> to
+        let places = &diagram.relationships[1];
+        assert_eq!(
+            (places.from.as_str(), places.to.as_str()),
+            ("Order", "Customer")
+        );
+        assert_eq!(places.from_end.multiplicity.as_deref(), Some("*"));
+        assert_eq!(places.to_end.role.as_deref(), Some("buyer"));
+        assert_eq!(places.label.as_deref(), Some("places"));
+        assert_eq!(places.label_direction, Some(LabelDirection::Forward));
+
+        let svg = render(source, &DiagramStyle::default());
+        assert!(svg.contains(">0..*<"));
+        assert!(svg.contains("relationship-label-arrow"));
+    }
+
+    #[test]
+    fn test_notes() {
+        let source = "@start_uml\nclass Foo\nclass Bar\nFoo --> Bar\nnote left of Foo : a\\nb\nnote top of Bar\nfirst\nsecond\nend note\nnote on link : cached\nnote \"Shared\" as N1\nN1 .. Foo\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        assert_eq!(diagram.classes.len(), 2);
+        assert_eq!(diagram.relationships.len(), 1);
+        assert_eq!(diagram.notes.len(), 4);
+        assert_eq!(diagram.notes[0].text, "a\nb");
+        assert_eq!(diagram.notes[1].text, "first\nsecond");
+        assert_eq!(diagram.notes[2].target, NoteTarget::Link(0));
+        assert_eq!(diagram.notes[3].links, ["Foo"]);
+
+        diagram.layout(&DiagramStyle::default());
+        let foo = diagram.classes.iter().find(|c| c.name == "Foo").unwrap();
+        let bar = diagram.classes.iter().find(|c| c.name == "Bar").unwrap();
+        let left = &diagram.notes[0];
+        assert!(left.x + left.width <= foo.x);
+        let top = &diagram.notes[1];
+        assert!(top.y + top.height <= bar.y);
+
+        // No note overlaps a class
+        for note in &diagram.notes {
+            for c in &diagram.classes {
+                let apart = note.x + note.width <= c.x
+                    || c.x + c.width <= note.x
+                    || note.y + note.height <= c.y
+                    || c.y + c.height <= note.y;
+                assert!(apart, "note {:?} overlaps {}", note.text, c.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_enum_literals() {
+        let source = "@start_uml\nenum Color {\nRED, GREEN\nBLUE;\n+ hex() : String\n}\nenum Shape<T> {\nNone\nSome(T)\nMove { x: i32, y: i32 }\nResize {\nw: u32,\nh: u32\n}\nis_some() : bool\n}\n@end_uml";
+        let diagram = Parser::new().parse(source);
+        let color = diagram.classes.iter().find(|c| c.name == "Color").unwrap();
+        let names: Vec<_> = color.literals.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, ["RED", "GREEN", "BLUE"]);
+        assert!(color.fields.is_empty());
+        assert_eq!(color.methods.len(), 1);
+
+        let shape = diagram.classes.iter().find(|c| c.name == "Shape").unwrap();
+        assert_eq!(shape.literals.len(), 4);
+        assert_eq!(shape.literals[1].data, VariantData::Tuple("T".to_string()));
+        assert_eq!(
+            shape.literals[2].data,
+            VariantData::Struct("x: i32, y: i32".to_string())
+        );
+        assert_eq!(
+            shape.literals[3].data,
+            VariantData::Struct("w: u32, h: u32".to_string())
+        );
+        assert_eq!(shape.methods[0].name, "is_some");
+
+        let svg = render(source, &DiagramStyle::default());
+        assert!(svg.contains(r#"class="enum-literal">RED</text>"#));
+        assert!(svg.contains(r#"<tspan class="enum-literal-data">(T)</tspan>"#));
+        assert!(!svg.contains(r#"class="class-field-name"#));
+    }
+
+    #[test]
+    fn test_uppercase_methods() {
+        let source = "@start_uml\nclass Factory {\nNew(x: i32)\nBuild() : Factory\n}\nenum Op {\nAdd(i32)\nNew(x: i32) -> Op\nParse(s: &str): Op\n}\nenum Unit {\nMeter;\nNew(x: i32)\n}\n@end_uml";
+        let diagram = Parser::new().parse(source);
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+        let methods = |name: &str| -> Vec<String> {
+            class(name).methods.iter().map(|m| m.name.clone()).collect()
+        };
+
+        // Outside enums a `Name(...)` is always a method
+        let factory = class("Factory");
+        assert!(factory.literals.is_empty());
+        assert_eq!(methods("Factory"), ["New", "Build"]);
+        assert_eq!(factory.methods[0].params, "x: i32");
+
+        // In an enum, a return type marks a method even in the literal section
+        let op = class("Op");
+        assert_eq!(op.literals.len(), 1);
+        assert_eq!(op.literals[0].data, VariantData::Tuple("i32".to_string()));
+        assert_eq!(methods("Op"), ["New", "Parse"]);
+        assert_eq!(op.methods[0].return_type.as_deref(), Some("Op"));
+
+        // After `;` the literal section is over
+        assert_eq!(class("Unit").literals.len(), 1);
+        assert_eq!(methods("Unit"), ["New"]);
+    }
+
+    #[test]
+    fn test_rust_kinds() {
+        let source = "@start_uml\npub mod geo {\npub struct Point {\npub x: f64,\npub(crate) y: f64,\ncache: u8\n}\n}\npub(crate) trait Shape {\nfn area(&self) -> f64;\n}\nunion Bits\ntype Points = Vec<Point>;\nimpl<T: Copy> Display for Point {\npub fn fmt(&self, f: &mut Formatter) -> Result\n}\nimpl Shape for Point\n@end_uml";
+        assert!(is_class_diagram(source));
+        let diagram = Parser::new().parse(source);
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+
+        let point = class("geo.Point");
+        assert_eq!(point.class_type, ClassType::Struct);
+        let vis: Vec<_> = point.fields.iter().map(|f| f.visibility).collect();
+        assert_eq!(
+            vis,
+            [Some(Visibility::Public), Some(Visibility::Package), None]
+        );
+        assert_eq!(point.methods[0].name, "fmt");
+        assert_eq!(point.methods[0].return_type.as_deref(), Some("Result"));
+
+        let shape = class("Shape");
+        assert_eq!(shape.class_type, ClassType::Trait);
+        assert_eq!(shape.methods[0].return_type.as_deref(), Some("f64"));
+        assert_eq!(class("Bits").class_type, ClassType::Union);
+        assert_eq!(class("Points").aliased.as_deref(), Some("Vec<Point>"));
+        assert_eq!(class("Display").class_type, ClassType::Trait);
+
+        let realized: Vec<_> = diagram
+            .relationships
+            .iter()
+            .filter(|r| r.rel_type == RelationType::Realization)
+            .map(|r| (r.from.as_str(), r.to.as_str()))
+            .collect();
+        assert_eq!(realized, [("geo.Point", "Display"), ("geo.Point", "Shape")]);
+
+        let svg = render(source, &DiagramStyle::default());
+        assert!(svg.contains(r#"class="spot spot-struct""#));
+        assert!(svg.contains(r#"class="interface-separator""#));
+        assert!(svg.contains(">= Vec&lt;Point&gt;<"));
+    }
+
+    #[test]
+    fn test_parse_generics() {
+        let source = "@start_uml\nclass HashMap<K: Hash + Eq, V> <<collection>> {\n+ get(k: &K) : Option<&V>\n}\nclass Wrapper<T: Into<Vec<u8>>>\nWrapper --> HashMap\n@end_uml";
+        let diagram = Parser::new().parse(source);
+        let map = diagram
+            .classes
+            .iter()
+            .find(|c| c.name == "HashMap")
+            .unwrap();
+        assert_eq!(map.stereotype.as_deref(), Some("collection"));
+        assert_eq!(map.generics.len(), 2);
+        assert_eq!(map.generics[0].name, "K");
+        assert_eq!(map.generics[0].bounds.as_deref(), Some("Hash + Eq"));
+        assert_eq!(map.generics[1].bounds, None);
+        assert_eq!(map.template_label().as_deref(), Some("K: Hash + Eq, V"));
+
+        let wrapper = diagram
+            .classes
+            .iter()
+            .find(|c| c.name == "Wrapper")
+            .unwrap();
+        assert_eq!(wrapper.generics[0].bounds.as_deref(), Some("Into<Vec<u8>>"));
+
+        let svg = render(source, &DiagramStyle::default());
+        assert!(svg.contains("class-template"));
+        assert!(svg.contains(">K: Hash + Eq, V<"));
+    }
+
+    #[test]
+    fn test_packages_enclose_members() {
+        let source = "@start_uml\npackage a {\nclass A1\nclass A2\npackage b {\nclass B1\n}\n}\nclass Outside\nA1 <|-- Outside\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        diagram.layout(&DiagramStyle::default());
+
+        let contains = |p: &Package, x: f32, y: f32, w: f32, h: f32| {
+            x >= p.x && y >= p.y && x + w <= p.x + p.width && y + h <= p.y + p.height
+        };
+        let pkg = |name: &str| diagram.packages.iter().find(|p| p.name == name).unwrap();
+        for class in &diagram.classes {
+            let inside_a = contains(pkg("a"), class.x, class.y, class.width, class.height);
+            assert_eq!(inside_a, class.name != "Outside", "{}", class.name);
+        }
+        let b = pkg("a.b");
+        assert!(contains(pkg("a"), b.x, b.y, b.width, b.height));
+    }
+
+    #[test]
+    fn test_tokenized_grammar() {
+        let source = r#"@start_uml
+class "Long Name" as LN <<entity>> {}
+struct Point { x: i32, y: i32 }
+class Foo
+Foo : +bar(a: i32) : bool
+LN --> Foo : uses
+@end_uml"#;
+        let diagram = ClassDiagram::parse(source).unwrap();
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+
+        assert_eq!(class("Long Name").stereotype.as_deref(), Some("entity"));
+        assert_eq!(class("Point").fields.len(), 2);
+        assert_eq!(class("Foo").methods[0].name, "bar");
+        assert_eq!(diagram.relationships[0].from, "Long Name");
+        assert_eq!(diagram.relationships[0].to, "Foo");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let source = "@start_uml\nclass Foo {\n  foo) (\n}\nclass \"Open\n~~~\n@end_uml";
+        let errors = ClassDiagram::parse(source).unwrap_err();
+        let positions: Vec<_> = errors.iter().map(|e| (e.line, e.column)).collect();
+        assert!(positions.contains(&(5, 7)), "{:?}", errors);
+        assert!(errors.iter().any(|e| e.line == 6), "{:?}", errors);
+
+        // Lenient rendering still draws what could be parsed
+        let svg = render(source, &DiagramStyle::default());
+        assert!(svg.contains(">Foo<"));
+    }
+
+    #[test]
+    fn test_relationship_kinds() {
+        let source = "@start_uml\nOuter +-- Inner\nA .. B\nCustomer }o--|| Order\nA <|--|> B\nA #--x C\n@end_uml";
+        let diagram = Parser::new().parse(source);
+        let rels = &diagram.relationships;
+
+        let nested = &rels[0];
+        assert_eq!(nested.rel_type, RelationType::Nested);
+        assert_eq!(
+            (nested.from.as_str(), nested.to.as_str()),
+            ("Outer", "Inner")
+        );
+        assert_eq!(nested.from_marker, EndMarker::Plus);
+
+        assert_eq!(rels[1].rel_type, RelationType::Link);
+        assert!(rels[1].dashed);
+        assert_eq!(rels[1].to_marker, EndMarker::None);
+
+        assert_eq!(rels[2].rel_type, RelationType::Entity);
+        assert_eq!(rels[2].from_marker, EndMarker::ZeroOrMany);
+        assert_eq!(rels[2].to_marker, EndMarker::ExactlyOne);
+
+        assert_eq!(rels[3].rel_type, RelationType::Bidirectional);
+        assert_eq!(rels[3].from_marker, EndMarker::Triangle);
+        assert_eq!(rels[3].to_marker, EndMarker::Triangle);
+
+        assert_eq!(rels[4].from_marker, EndMarker::Square);
+        assert_eq!(rels[4].to_marker, EndMarker::Cross);
+
+        let svg = render(source, &DiagramStyle::default());
+        for id in [
+            "cls-plus",
+            "cls-zero-or-many",
+            "cls-exactly-one",
+            "cls-square",
+            "cls-cross",
+        ] {
+            assert!(svg.contains(&format!(r#"<marker id="{}""#, id)), "{}", id);
+            assert!(svg.contains(&format!("url(#{})", id)), "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_member_endpoints() {
+        let source = "@start_uml\nclass Customer {\n+id: u64\n+name: String\n}\nclass Order {\n+id: u64\n+customer_id: u64\n}\nOrder::customer_id --> Customer::id\na::b --> Order\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        diagram.layout(&DiagramStyle::default());
+
+        let rel = &diagram.relationships[0];
+        assert_eq!((rel.from.as_str(), rel.to.as_str()), ("Order", "Customer"));
+        assert_eq!(rel.from_member.as_deref(), Some("customer_id"));
+        assert_eq!(rel.to_member.as_deref(), Some("id"));
+
+        // An undeclared prefix keeps the whole path as the class name
+        assert_eq!(diagram.relationships[1].from, "a::b");
+        assert_eq!(diagram.relationships[1].from_member, None);
+
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+        let (order, customer) = (class("Order"), class("Customer"));
+        let points = relationship_path(order, customer, rel, LayoutDirection::TopToBottom);
+        let first_row = member_row_y(order, "id").unwrap();
+        let second_row = member_row_y(order, "customer_id").unwrap();
+        assert_eq!(second_row - first_row, 18.0);
+        assert_eq!(points[0].1, second_row);
+        assert_eq!(
+            points.last().unwrap().1,
+            member_row_y(customer, "id").unwrap()
+        );
+
+        // The routed line stays on the same rows
+        assert_eq!(rel.path[0].1, second_row);
+        assert_eq!(
+            rel.path.last().unwrap().1,
+            member_row_y(customer, "id").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_label_placement() {
+        let source = "@start_uml\nCustomer \"1\" -- \"0..*\" Order : places >\nOrder \"1\" *-- \"items 1..*\" Item : contains\nCustomer \"buyer\" -- \"*\" Item : wishes\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        let style = DiagramStyle::default();
+        diagram.layout(&style);
+
+        // Every text box, as placed, must stay clear of classes and each other
+        let text_box = |text: &Option<String>, at: Option<(f32, f32)>| {
+            let width = text.as_ref()?.len() as f32 * style.char_width;
+            let (x, y) = at?;
+            Some(Rect::new(
+                x - width / 2.0,
+                y - LABEL_HEIGHT + 2.0,
+                width,
+                LABEL_HEIGHT,
+            ))
+        };
+        let mut texts: Vec<Rect> = Vec::new();
+        for rel in &diagram.relationships {
+            let ends = [&rel.from_end, &rel.to_end];
+            texts.extend(text_box(&rel.label, rel.label_at));
+            for end in ends {
+                texts.extend(text_box(&end.multiplicity, end.multiplicity_at));
+                texts.extend(text_box(&end.role, end.role_at));
+            }
+        }
+        assert_eq!(texts.len(), 10);
+
+        let overlaps = |a: Rect, b: Rect| {
+            a.x < b.right() && b.x < a.right() && a.y < b.bottom() && b.y < a.bottom()
+        };
+        for (i, &text) in texts.iter().enumerate() {
+            for class in &diagram.classes {
+                assert!(!overlaps(text, class.rect()));
+            }
+            for &other in &texts[i + 1..] {
+                assert!(!overlaps(text, other), "{:?} {:?}", text, other);
+            }
+        }
+    }
+
+    #[test]
+    fn test_routes_avoid_classes() {
+        // Left sits between Top and Bottom, in the way of a straight line
+        let source =
+            "@start_uml\nTop <|-- Left\nLeft <|-- Bottom\nTop <|-- Bottom\nTop --> Right\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        diagram.layout(&DiagramStyle::default());
+
+        for rel in &diagram.relationships {
+            assert!(rel.path.len() >= 2);
+            for w in rel.path.windows(2) {
+                let ((x1, y1), (x2, y2)) = (w[0], w[1]);
+                assert!(x1 == x2 || y1 == y2, "not orthogonal: {:?}", rel.path);
+                for class in &diagram.classes {
+                    let crosses = x1.min(x2) < class.x + class.width
+                        && x1.max(x2) > class.x
+                        && y1.min(y2) < class.y + class.height
+                        && y1.max(y2) > class.y;
+                    assert!(!crosses, "{:?} crosses {}", rel.path, class.name);
+                }
+            }
+        }
+
+        // Both subclasses of Top meet it through one shared port
+        let rels = &diagram.relationships;
+        assert_eq!(rels[0].path.last(), rels[2].path.last());
+    }
+
+    #[test]
+    fn test_display_commands() {
+        let source = r#"@start_uml
+class Order {
+  +id: u64
+  +total(): f64
+}
+class Audit $internal
+struct Point <<Serializable>> {
+  x: i32
+}
+class Lonely
+class Empty
+Order --> Audit
+Order --> Point
+Order --> Empty
+note right of Audit : internal only
+hide methods
+hide <<Serializable>> circle
+hide empty members
+hide $internal
+remove @unlinked
+hide Empty
+show Empty
+hide Point fields
+@end_uml"#;
+        let (diagram, errors) = Parser::new().parse_checked(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name);
+
+        assert!(class("Lonely").is_none());
+        let order = class("Order").unwrap();
+        assert!(order.methods.is_empty());
+        assert_eq!(order.fields.len(), 1);
+        assert!(class("Audit").unwrap().hidden);
+        assert_eq!(class("Audit").unwrap().tags, ["internal"]);
+        assert!(!class("Empty").unwrap().hidden);
+        let point = class("Point").unwrap();
+        assert!(point.spot().is_none() && point.fields.is_empty());
+        assert!(point.hide_empty_members);
+
+        let svg = render(source, &DiagramStyle::default());
+        assert!(!svg.contains(">Audit<"));
+        assert!(!svg.contains("internal only"));
+        assert!(!svg.contains(">Lonely<"));
+        assert!(!svg.contains("total()"));
+        assert!(svg.contains(">Empty<"));
+
+        let errors =
+            ClassDiagram::parse("@start_uml\nhide\nremove Foo methods\nhide Foo bar\n@end_uml")
+                .unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_direction_hints() {
+        let source = "@start_uml\nclass Base\nBase -left-* Whole\nBase -right-o Part\nBase -up-|> Parent\nBase -down-> Detail\ntogether {\nclass X\nZ\n}\nX -[hidden]- Y\n@end_uml";
+        let (mut diagram, errors) = Parser::new().parse_checked(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        // Directions are stored from `from` towards `to`
+        let rels = &diagram.relationships;
+        assert_eq!(
+            (rels[0].from.as_str(), rels[0].direction),
+            ("Whole", Some(Direction::Right))
+        );
+        assert_eq!(rels[2].direction, Some(Direction::Up));
+        assert!(rels[4].hidden);
+        assert_eq!(diagram.together, [vec!["X".to_string(), "Z".to_string()]]);
+
+        diagram.layout(&DiagramStyle::default());
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+        let base = class("Base");
+        assert!(class("Whole").x < base.x && class("Whole").y == base.y);
+        assert!(class("Part").x > base.x && class("Part").y == base.y);
+        assert!(class("Parent").y < base.y);
+        assert!(class("Detail").y > base.y);
+        let (x, z) = (class("X"), class("Z"));
+        assert_eq!(x.y, z.y);
+        assert!((x.x - z.x).abs() <= x.width + DiagramStyle::default().spacing_x);
+
+        let svg = render(source, &DiagramStyle::default());
+        assert_eq!(svg.matches("<polyline").count(), 4);
+    }
+
+    #[test]
+    fn test_declaration_order() {
+        let source = "@start_uml\nclass Zeta\nclass Alpha\nMid --> Omega\npackage p {\nclass Beta\n}\nclass Zeta {\n+ x: i32\n}\nAlpha --> Zeta\n@end_uml";
+        let diagram = ClassDiagram::parse(source).unwrap();
+        let names: Vec<&str> = diagram.classes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["Zeta", "Alpha", "Mid", "Omega", "p.Beta"]);
+
+        let style = DiagramStyle::default();
+        let first = render(source, &style);
+        for _ in 0..8 {
+            assert_eq!(render(source, &style), first);
+        }
+    }
+
+    #[test]
+    fn test_layout_direction() {
+        let body = "class Animal\nAnimal <|-- Dog\nAnimal <|-- Cat\nDog -up-> Bone\n";
+        let source = format!("@start_uml\nleft to right direction\n{body}@end_uml");
+        let mut diagram = ClassDiagram::parse(&source).unwrap();
+        assert_eq!(diagram.direction, Some(LayoutDirection::LeftToRight));
+
+        diagram.layout(&DiagramStyle::default());
+        let class = |name: &str| diagram.classes.iter().find(|c| c.name == name).unwrap();
+        let animal = class("Animal");
+        for child in ["Dog", "Cat"] {
+            assert!(class(child).x > animal.x + animal.width);
+        }
+        // Hints keep their meaning on the page
+        assert!(class("Bone").y < class("Dog").y);
+        // Inheritance arrives at the parent's side, not its top or bottom
+        let end = *diagram.relationships[0].path.last().unwrap();
+        assert_eq!(end.0, animal.x + animal.width);
+
+        // The style option does the same, and the source overrides it
+        let plain = format!("@start_uml\n{body}@end_uml");
+        let across = DiagramStyle::default().with_direction(LayoutDirection::LeftToRight);
+        assert_eq!(
+            render(&plain, &across),
+            render(&source, &DiagramStyle::default())
+        );
+        let down = format!("@start_uml\ntop to bottom direction\n{body}@end_uml");
+        assert_eq!(
+            render(&down, &across),
+            render(&plain, &DiagramStyle::default())
+        );
+    }
+
+    #[test]
+    fn test_position_pinning() {
+        let source = "@start_uml\nclass Base @ 400,300\nBase <|-- A\nBase <|-- B\npackage p {\nclass C @ 10,0\nclass D\n}\nA --> C\n@end_uml";
+        let mut diagram = ClassDiagram::parse(source).unwrap();
+        assert_eq!(diagram.classes[0].pinned, Some((400.0, 300.0)));
+        diagram.layout(&DiagramStyle::default());
+        let class = |d: &ClassDiagram, name: &str| {
+            let c = d.classes.iter().find(|c| c.name == name).unwrap();
+            (c.x, c.y, c.width, c.height)
+        };
+        assert_eq!(class(&diagram, "Base").0, 400.0);
+        assert_eq!(class(&diagram, "Base").1, 300.0);
+        let package = &diagram.packages[0];
+        assert_eq!(class(&diagram, "p.C").0, package.x + PACKAGE_PADDING + 10.0);
+        // Free classes make room for the pinned one
+        let base = class(&diagram, "Base");
+        for name in ["A", "B"] {
+            let c = class(&diagram, name);
+            let apart = c.0 + c.2 <= base.0
+                || base.0 + base.2 <= c.0
+                || c.1 + c.3 <= base.1
+                || base.1 + base.3 <= c.1;
+            assert!(apart, "{} overlaps Base", name);
+        }
+
+        // An exported layout pins everything where it was
+        let json = diagram.layout_json();
+        assert!(json.contains("\"p.C\": {\"x\": 10, \"y\": 0}"));
+        let plain = source.replace(" @ 400,300", "").replace(" @ 10,0", "");
+        let mut pinned = ClassDiagram::parse(&plain).unwrap();
+        pinned.apply_layout(&json).unwrap();
+        pinned.layout(&DiagramStyle::default());
+        for c in &diagram.classes {
+            assert_eq!(class(&pinned, &c.name), class(&diagram, &c.name));
+        }
+        assert_eq!(pinned.layout_json(), json);
+
+        assert!(pinned.apply_layout("{\"Base\": {\"x\": 1}}").is_err());
+        assert!(pinned.apply_layout("[1, 2]").is_err());
+        assert!(pinned
+            .apply_layout("{\"Gone\": {\"x\": 1, \"y\": 2}}")
+            .is_ok());
+        assert!(ClassDiagram::parse("@start_uml\nclass Foo @ 1\n@end_uml").is_err());
+
+        // A bad entry leaves the whole file unapplied
+        let mut fresh = ClassDiagram::parse(&plain).unwrap();
+        let half = "{\"A\": {\"x\": 5, \"y\": 6}, \"B\": {\"x\": -5, \"y\": 6}}";
+        let error = fresh.apply_layout(half).unwrap_err();
+        assert!(error.message.contains("negative"));
+        assert!(fresh.classes.iter().all(|c| c.pinned.is_none()));
+        let errors = ClassDiagram::parse("@start_uml\nclass Foo @ -10,20\n@end_uml").unwrap_err();
+        assert_eq!(errors[0].message, "pinned positions cannot be negative");
+
+        // Pins inside the margin are kept exactly
+        let mut edge =
+            ClassDiagram::parse("@start_uml\nclass Foo @ 5,0\nFoo --> Bar\n@end_uml").unwrap();
+        edge.layout(&DiagramStyle::default());
+        assert_eq!((edge.classes[0].x, edge.classes[0].y), (5.0, 0.0));
+        assert!(edge.layout_json().contains("\"Foo\": {\"x\": 5, \"y\": 0}"));
+
+        // A generic class pinned to the top still has room for its template box
+        let source = "@start_uml\nclass HashMap<K, V> @ 0,0\nclass Other @ 5,0\n@end_uml";
+        let mut generic = ClassDiagram::parse(source).unwrap();
+        generic.layout(&DiagramStyle::default());
+        assert_eq!(generic.classes[0].y, TEMPLATE_RISE);
+        assert_eq!(generic.classes[1].y, TEMPLATE_RISE);
+        let svg = render(source, &DiagramStyle::default());
+        let template = svg.split("class=\"class-template\"").next().unwrap();
+        let y = template.rsplit("y=\"").next().unwrap();
+        assert!(y.starts_with("0\""), "template box starts at y={}", y);
+
+        // A free parent of a class pinned to the top edge still goes above it
+        let source = "@start_uml\nclass Mid @ 200,0\nBase <|-- Mid\nMid <|-- A\n@end_uml";
+        let mut middle = ClassDiagram::parse(source).unwrap();
+        middle.layout(&DiagramStyle::default());
+        let (mid, base, a) = (
+            class(&middle, "Mid"),
+            class(&middle, "Base"),
+            class(&middle, "A"),
+        );
+        assert!(base.1 >= 0.0 && base.1 + base.3 < mid.1 && mid.1 + mid.3 < a.1);
+        assert_eq!(mid.0, 200.0);
+    }
+
     #[test]
     fn test_is_class_diagram() {
         assert!(is_class_diagram("class Foo {}"));
         assert!(!is_class_diagram("participant A\nA -> B: msg"));
+        assert!(is_class_diagram("pub struct Point {"));
+        assert!(is_class_diagram("type Id = u64"));
+        assert!(is_class_diagram("trait Store<K: Hash, V>"));
+        assert!(is_class_diagram("mod net {"));
+
+        // Prose that starts with a Rust keyword is not a declaration
+        let sequence = "@start_uml\nnote over Alice\ntype the password\n\
+            struct fields are checked\nunion of both roles\ntrait objects: none\n\
+            mod 7 applied\nend note\nAlice -> Bob: login\n@end_uml";
+        assert!(!is_class_diagram(sequence));
     }
 }
@@ -0,0 +1,342 @@
+//! Line lexer for the class diagram grammar.
+//!
+//! Each statement line is split into [`Token`]s carrying their column, so the
+//! grammar in `class_diagram` can match on structure instead of substrings and
+//! report errors where they occur. Member text inside class bodies is free-form
+//! (types like `&mut Vec<u8>`), so the grammar takes it from the raw line using
+//! the token offsets rather than lexing it.
+
+use std::fmt;
+
+/// A syntax error in a diagram source, with 1-based line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Kind of a lexed token
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tok<'a> {
+    /// Name, keyword or number; dotted and `::` paths stay in one token
+    Ident(&'a str),
+    /// Contents of a `"quoted"` string
+    Str(&'a str),
+    /// Contents of a `<<stereotype>>`
+    Stereotype(&'a str),
+    /// Contents of `<...>` written directly after a name
+    Generics(&'a str),
+    /// A relationship arrow such as `--|>`, `*--` or `..>`
+    Arrow(&'a str),
+    /// Any other single character
+    Punct(char),
+}
+
+/// A token with its position in the line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub tok: Tok<'a>,
+    /// Byte offset of the token start
+    pub start: usize,
+    /// Byte offset just past the token
+    pub end: usize,
+    /// 1-based character column of the token start
+    pub column: usize,
+}
+
+impl Token<'_> {
+    pub fn is_punct(&self, c: char) -> bool {
+        self.tok == Tok::Punct(c)
+    }
+
+    pub fn is_ident(&self, word: &str) -> bool {
+        self.tok == Tok::Ident(word)
+    }
+
+    /// The name written by an identifier or quoted string
+    pub fn name(&self) -> Option<&str> {
+        match self.tok {
+            Tok::Ident(s) | Tok::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Characters that may appear inside an arrow
+const ARROW_CHARS: &str = "-.|<>*#+^";
+
+/// 1-based character column of a byte offset
+pub fn column(line: &str, offset: usize) -> usize {
+    line[..offset].chars().count() + 1
+}
+
+/// Split one line into tokens. `line_no` is only used for error positions.
+pub fn tokenize(line: &str, line_no: usize) -> Result<Vec<Token<'_>>, ParseError> {
+    let bytes = line.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let error = |offset: usize, message: &str| ParseError {
+        line: line_no,
+        column: column(line, offset),
+        message: message.to_string(),
+    };
+
+    while i < line.len() {
+        let c = line[i..].chars().next().unwrap_or(' ');
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+        let next = line[i + c.len_utf8()..].chars().next();
+        let prev = line[..i].chars().next_back();
+        let start = i;
+
+        let tok = if c == '"' {
+            let close = line[i + 1..]
+                .find('"')
+                .ok_or_else(|| error(start, "unterminated string"))?;
+            i += close + 2;
+            Tok::Str(&line[start + 1..i - 1])
+        } else if line[i..].starts_with("<<") {
+            let close = line[i + 2..]
+                .find(">>")
+                .ok_or_else(|| error(start, "unterminated stereotype"))?;
+            i += close + 4;
+            Tok::Stereotype(line[start + 2..i - 2].trim())
+        } else if c == '<' && prev.is_some_and(is_ident_char) && !next.is_some_and(is_arrow_body) {
+            let close = matching_angle(&line[i..]).ok_or_else(|| error(start, "unclosed '<'"))?;
+            i += close + 1;
+            Tok::Generics(&line[start + 1..i - 1])
+        } else if is_arrow_start(&line[i..], prev) {
+            i += c.len_utf8();
+            while let Some(a) = line[i..].chars().next() {
+                // `-up->`, `-[hidden]-`: a direction or option inside the line
+                if line[..i].ends_with(['-', '.']) {
+                    if let Some(len) = arrow_infix(&line[i..]) {
+                        i += len;
+                        continue;
+                    }
+                }
+                let after = line[i + a.len_utf8()..].chars().next();
+                let head_letter = matches!(a, 'o' | 'x') && !after.is_some_and(is_ident_char);
+                // Crow's foot `o{` / `|{` closes the arrow
+                let crow = a == '{' && line[..i].ends_with(['o', '|']);
+                if ARROW_CHARS.contains(a) || head_letter || crow {
+                    i += a.len_utf8();
+                } else {
+                    break;
+                }
+            }
+            Tok::Arrow(&line[start..i])
+        } else if is_ident_char(c) {
+            while i < line.len() {
+                let a = line[i..].chars().next().unwrap_or(' ');
+                let after = line[i + a.len_utf8()..].chars().next();
+                if is_ident_char(a)
+                    || (a == '.' && after.is_some_and(is_ident_char))
+                    || (a == ':' && after == Some(':'))
+                {
+                    i += a.len_utf8();
+                } else if a == ':' && i > start && bytes[i - 1] == b':' {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            Tok::Ident(&line[start..i])
+        } else {
+            i += c.len_utf8();
+            Tok::Punct(c)
+        };
+
+        tokens.push(Token {
+            tok,
+            start,
+            end: i,
+            column: column(line, start),
+        });
+    }
+    Ok(tokens)
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_arrow_body(c: char) -> bool {
+    matches!(c, '-' | '.' | '|' | '<')
+}
+
+/// Whether an arrow starts at the beginning of `rest`: a run of `-`/`.`, or
+/// an arrow head (`<|`, `*`, `o`, `}o`, ...) directly followed by the line
+fn is_arrow_start(rest: &str, prev: Option<char>) -> bool {
+    let mut chars = rest.chars();
+    let (c, next, third) = (chars.next(), chars.next(), chars.next());
+    let is_line = |n: Option<char>| n.is_some_and(|n| matches!(n, '-' | '.'));
+    let next_is_line = is_line(next);
+    match c {
+        Some('-' | '.') => {
+            next_is_line
+                || next.is_some_and(|n| ARROW_CHARS.contains(n))
+                || arrow_infix(&rest[1..]).is_some()
+        }
+        Some('<') => next_is_line || next == Some('|'),
+        Some('*' | '#' | '+' | '^') => next_is_line,
+        // Letters are heads only when they stand alone: `o--`, not `foo--`
+        Some('o' | 'x') => next_is_line && !prev.is_some_and(is_ident_char),
+        // Crow's foot ends: `}o--`, `}|--`, `|o--`, `||--`
+        Some('}' | '|') => next_is_line || (matches!(next, Some('o' | '|')) && is_line(third)),
+        _ => false,
+    }
+}
+
+/// Length of a direction (`up`, `l`, ...) or bracketed option (`[hidden]`)
+/// at the start of `rest`, when the arrow line continues after it
+pub fn arrow_infix(rest: &str) -> Option<usize> {
+    let len = if rest.starts_with('[') {
+        rest.find(']')? + 1
+    } else {
+        let word = rest.split(|c: char| !c.is_ascii_alphabetic()).next()?;
+        if !matches!(
+            word,
+            "up" | "down" | "left" | "right" | "u" | "d" | "l" | "r" | "do" | "le" | "ri"
+        ) {
+            return None;
+        }
+        word.len()
+    };
+    rest[len..].starts_with(['-', '.']).then_some(len)
+}
+
+/// Byte offset of the `>` closing the `<` at the start of `text`
+fn matching_angle(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut prev = ' ';
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            // `->` inside a bound (`F: Fn() -> T`) is not a closing bracket
+            '>' if prev != '-' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<Tok<'_>> {
+        tokenize(line, 1)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.tok)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_declarations() {
+        assert_eq!(
+            kinds(r#"class "Long Name" as LN <<entity>> {}"#),
+            [
+                Tok::Ident("class"),
+                Tok::Str("Long Name"),
+                Tok::Ident("as"),
+                Tok::Ident("LN"),
+                Tok::Stereotype("entity"),
+                Tok::Punct('{'),
+                Tok::Punct('}'),
+            ]
+        );
+        assert_eq!(
+            kinds("struct Map<K, Vec<V>> {"),
+            [
+                Tok::Ident("struct"),
+                Tok::Ident("Map"),
+                Tok::Generics("K, Vec<V>"),
+                Tok::Punct('{'),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_relationships() {
+        assert_eq!(
+            kinds(r#"net.Order "1" *-- "0..*" Item : has >"#),
+            [
+                Tok::Ident("net.Order"),
+                Tok::Str("1"),
+                Tok::Arrow("*--"),
+                Tok::Str("0..*"),
+                Tok::Ident("Item"),
+                Tok::Punct(':'),
+                Tok::Ident("has"),
+                Tok::Punct('>'),
+            ]
+        );
+        assert_eq!(
+            kinds("foo o-- bar --o baz <|.. a::b"),
+            [
+                Tok::Ident("foo"),
+                Tok::Arrow("o--"),
+                Tok::Ident("bar"),
+                Tok::Arrow("--o"),
+                Tok::Ident("baz"),
+                Tok::Arrow("<|.."),
+                Tok::Ident("a::b"),
+            ]
+        );
+        assert_eq!(
+            kinds("A }o--|| B <|--|> C +-- D .. E x--o{ F"),
+            [
+                Tok::Ident("A"),
+                Tok::Arrow("}o--||"),
+                Tok::Ident("B"),
+                Tok::Arrow("<|--|>"),
+                Tok::Ident("C"),
+                Tok::Arrow("+--"),
+                Tok::Ident("D"),
+                Tok::Arrow(".."),
+                Tok::Ident("E"),
+                Tok::Arrow("x--o{"),
+                Tok::Ident("F"),
+            ]
+        );
+        assert_eq!(
+            kinds("A -up-|> B -[hidden]- C -l-* D"),
+            [
+                Tok::Ident("A"),
+                Tok::Arrow("-up-|>"),
+                Tok::Ident("B"),
+                Tok::Arrow("-[hidden]-"),
+                Tok::Ident("C"),
+                Tok::Arrow("-l-*"),
+                Tok::Ident("D"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        let err = tokenize(r#"class "Open"#, 7).unwrap_err();
+        assert_eq!((err.line, err.column), (7, 7));
+        assert_eq!(tokenize("class Foo<T", 1).unwrap_err().column, 10);
+    }
+}
@@ -41,53 +41,6 @@ pub fn extract_custom_css(source: &str) -> Option<String> {
     }
 }
 
-/// Extract CSS custom properties (--property: value) for a specific class
-/// This allows controlling SVG attributes like rx/ry via CSS-like syntax
-pub fn extract_css_property(css: &str, class: &str, property: &str) -> Option<f32> {
-    // Find the class block
-    let class_pattern = format!(".{}", class);
-    let mut in_class = false;
-    let mut brace_depth = 0;
-
-    for line in css.lines() {
-        let trimmed = line.trim();
-
-        if trimmed.contains(&class_pattern) && trimmed.contains('{') {
-            in_class = true;
-            brace_depth = 1;
-            continue;
-        }
-
-        if in_class {
-            if trimmed.contains('{') {
-                brace_depth += 1;
-            }
-            if trimmed.contains('}') {
-                brace_depth -= 1;
-                if brace_depth == 0 {
-                    break;
-                }
-            }
-
-            // Look for --property: value;
-            let prop_pattern = format!("--{}:", property);
-            if let Some(pos) = trimmed.find(&prop_pattern) {
-                let value_start = pos + prop_pattern.len();
-                let value_str = &trimmed[value_start..];
-                // Extract number before ; or end of line
-                let value_str = value_str.trim().trim_end_matches(';').trim();
-                // Remove 'px' suffix if present
-                let value_str = value_str.trim_end_matches("px");
-                if let Ok(val) = value_str.parse::<f32>() {
-                    return Some(val);
-                }
-            }
-        }
-    }
-
-    None
-}
-
 /// Collected CSS custom properties for rendering
 #[derive(Debug, Clone, Default)]
 pub struct CssProperties {
@@ -116,7 +69,7 @@ impl CssProperties {
 
             // Check for class selector
             if trimmed.starts_with('.') && trimmed.contains('{') {
-                if let Some(class_end) = trimmed.find(|c| c == ' ' || c == '{') {
+                if let Some(class_end) = trimmed.find([' ', '{']) {
                     current_class = Some(trimmed[1..class_end].to_string());
                     brace_depth = 1;
                 }
@@ -205,6 +158,9 @@ pub struct DiagramStyle {
     pub class_bg_color: String,
     pub class_border_color: String,
     pub interface_bg_color: String,
+    /// Which way class hierarchies grow; a `left to right direction` line in
+    /// the source overrides it
+    pub direction: LayoutDirection,
 
     // Dimensions
     pub margin: f32,
@@ -236,6 +192,7 @@ impl Default for DiagramStyle {
             class_bg_color: "#F0F0F0".into(),
             class_border_color: "#333333".into(),
             interface_bg_color: "#E8F4E8".into(),
+            direction: LayoutDirection::TopToBottom,
 
             margin: 30.0,
             padding: 10.0,
@@ -267,6 +224,54 @@ impl DiagramStyle {
         self.font_color = color.to_string();
         self
     }
+
+    /// Create style with class hierarchies laid out in the given direction
+    pub fn with_direction(mut self, direction: LayoutDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+}
+
+/// Direction in which layered class diagrams grow from parents to children
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LayoutDirection {
+    /// Parents above their children (`top to bottom direction`)
+    #[default]
+    TopToBottom,
+    /// Parents left of their children (`left to right direction`)
+    LeftToRight,
+}
+
+// ============================================================================
+// Geometry
+// ============================================================================
+
+/// Axis-aligned rectangle in diagram coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
 }
 
 // ============================================================================
@@ -330,11 +335,6 @@ impl SvgBuilder {
         Self { output, css_props }
     }
 
-    /// Get a CSS custom property value (--name) for a class
-    pub fn css_prop(&self, class: &str, property: &str) -> Option<f32> {
-        self.css_props.get(class, property)
-    }
-
     /// Get a CSS custom property value with default
     pub fn css_prop_or(&self, class: &str, property: &str, default: f32) -> f32 {
         self.css_props.get_or(class, property, default)
@@ -356,23 +356,6 @@ impl SvgBuilder {
         ));
     }
 
-    /// Draw a rectangle with CSS class and rounded corners
-    pub fn rect_rounded_class(
-        &mut self,
-        x: f32,
-        y: f32,
-        w: f32,
-        h: f32,
-        rx: f32,
-        ry: f32,
-        class: &str,
-    ) {
-        self.output.push_str(&format!(
-            r#"<rect x="{}" y="{}" width="{}" height="{}" rx="{}" ry="{}" class="{}"/>"#,
-            x, y, w, h, rx, ry, class
-        ));
-    }
-
     /// Draw a rectangle with CSS class, rounded corners, and optional filter
     pub fn rect_rounded_class_filtered(
         &mut self,
@@ -534,7 +517,7 @@ impl SvgBuilder {
             .join(" ");
 
         let dash = if dashed {
-            format!(r#" stroke-dasharray="5,5""#)
+            r#" stroke-dasharray="5,5""#.to_string()
         } else {
             String::new()
         };
@@ -0,0 +1,1123 @@
+//! Orthogonal routing of relationship lines around class boxes.
+//!
+//! Every box is an obstacle grown by a clearance margin. The router lays a
+//! sparse grid over the diagram made of the obstacle edges, the port lines and
+//! the channels midway between boxes, and runs A* over it with a cost for each
+//! bend, so lines take few corners and never cut through a box. Grid segments
+//! used by earlier lines cost extra, and a final pass spreads lines that still
+//! share a channel a few pixels apart.
+//!
+//! Lines ending on the same side of a box get ports of their own, spread along
+//! the side in an order that keeps them from crossing. Lines on a bus (the
+//! generalizations into one parent) instead share a port and merge into one
+//! trunk.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::common::Rect;
+
+/// A line to route between two boxes
+#[derive(Debug, Clone, Copy)]
+pub struct RouteRequest {
+    pub from: usize,
+    pub to: usize,
+    /// Row the line leaves from, on the left or right side of `from`
+    pub from_row: Option<f32>,
+    /// Row the line arrives at, on the left or right side of `to`
+    pub to_row: Option<f32>,
+    /// Axis the line should preferably leave and arrive along, for
+    /// inheritance and realization
+    pub prefer: Option<Axis>,
+    /// Lines on the same bus that arrive at the same side of `to` share
+    /// one port and merge into a trunk, like generalizations in UML tools
+    pub bus: Option<usize>,
+}
+
+/// Direction a line runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    /// Through the top and bottom sides
+    Vertical,
+    /// Through the left and right sides
+    Horizontal,
+}
+
+/// Space kept free around every box
+const CLEARANCE: f32 = 12.0;
+/// Extra cost of a corner, in pixels of line length
+const BEND_COST: f32 = 40.0;
+/// Extra cost of a port on a side facing away from the other box
+const SIDE_COST: f32 = 60.0;
+/// Extra cost per pixel for each earlier line on the same grid segment
+const SHARED_COST: f32 = 1.5;
+/// Extra cost per pixel for running along the clearance edge of a box
+const HUG_COST: f32 = 0.25;
+/// Share of the normal cost paid on segments already used by the same bus
+const BUS_COST: f32 = 0.5;
+/// Distance between lines spread apart in a shared channel
+const NUDGE_GAP: f32 = 6.0;
+/// Largest distance between neighbouring ports on one side
+const PORT_GAP: f32 = 16.0;
+/// Space kept free at either end of a side when spreading ports
+const PORT_INSET: f32 = 8.0;
+/// Coordinates closer than this are the same grid line
+const EPSILON: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Side {
+    /// Unit step leaving the box through this side
+    fn outward(self) -> Dir {
+        match self {
+            Self::Top => Dir::Up,
+            Self::Bottom => Dir::Down,
+            Self::Left => Dir::Left,
+            Self::Right => Dir::Right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dir {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Dir {
+    const ALL: [Dir; 4] = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn opposite(self) -> Self {
+        match self {
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+}
+
+/// Where a line may attach to a box
+struct Port {
+    side: Side,
+    /// Point on the box outline
+    point: (f32, f32),
+    /// Grid node just outside the clearance margin
+    node: usize,
+    cost: f32,
+}
+
+/// Route every request around `boxes`. A request gets `None` when no path
+/// exists, for example when its boxes overlap each other.
+///
+/// A first pass with ports at the side centres picks the side each end
+/// attaches to. The ends sharing a side are then spread along it, ordered
+/// by where their lines turn so they do not cross, and a second pass routes
+/// between those fixed ports.
+pub fn route_edges(boxes: &[Rect], requests: &[RouteRequest]) -> Vec<Option<Vec<(f32, f32)>>> {
+    if requests.is_empty() {
+        return Vec::new();
+    }
+
+    let (mut xs, mut ys): (Vec<f32>, Vec<f32>) = boxes
+        .iter()
+        .map(|r| (r.x + r.width / 2.0, r.y + r.height / 2.0))
+        .unzip();
+    ys.extend(
+        requests
+            .iter()
+            .flat_map(|r| [r.from_row, r.to_row])
+            .flatten(),
+    );
+    let grid = Grid::new(boxes, &xs, &ys);
+    let free_ports: Vec<(Vec<Port>, Vec<Port>)> =
+        requests.iter().map(|r| ports(&grid, boxes, r)).collect();
+    let groups: Vec<usize> = bus_groups(requests, |r| requests[r].to);
+    let first = route_all(&grid, &free_ports, &groups);
+
+    let ends = assign_ports(boxes, requests, &first);
+    for &(start, goal) in ends.iter().flatten() {
+        for end in [start, goal] {
+            xs.push(end.point.0);
+            ys.push(end.point.1);
+        }
+    }
+    let grid = Grid::new(boxes, &xs, &ys);
+    let fixed_ports: Vec<(Vec<Port>, Vec<Port>)> = ends
+        .iter()
+        .map(|ends| {
+            let Some((start, goal)) = ends else {
+                return (Vec::new(), Vec::new());
+            };
+            let port = |end: &End| grid.port(end.side, end.point, CLEARANCE);
+            (
+                port(start).into_iter().collect(),
+                port(goal).into_iter().collect(),
+            )
+        })
+        .collect();
+    // Lines on one bus share the port side chosen for them
+    let groups = bus_groups(requests, |r| {
+        let side = ends[r].map_or(0, |(_, goal)| goal.side as usize);
+        requests[r].to * 4 + side
+    });
+    let second = route_all(&grid, &fixed_ports, &groups);
+
+    let mut routes: Vec<Option<Vec<(f32, f32)>>> = second
+        .into_iter()
+        .zip(first)
+        .map(|(second, first)| second.or(first).map(|route| route.points))
+        .collect();
+
+    // Ends attached to a member row must stay on it
+    let pinned: Vec<(bool, bool)> = requests
+        .iter()
+        .map(|r| (r.from_row.is_some(), r.to_row.is_some()))
+        .collect();
+    nudge(&mut routes, &pinned, &groups, true);
+    nudge(&mut routes, &pinned, &groups, false);
+    routes
+}
+
+/// Candidate start and goal ports of a request
+fn ports(grid: &Grid, boxes: &[Rect], request: &RouteRequest) -> (Vec<Port>, Vec<Port>) {
+    let (from, to) = (boxes[request.from], boxes[request.to]);
+    // A box related to itself loops from its right side round to its top,
+    // or to its left side when the line ends at a row
+    if request.from == request.to {
+        let goal_side = if request.to_row.is_some() {
+            Side::Left
+        } else {
+            Side::Top
+        };
+        let mut start = box_ports(grid, from, to, request.from_row, None);
+        let mut goal = box_ports(grid, to, from, request.to_row, None);
+        start.retain(|p| p.side == Side::Right);
+        goal.retain(|p| p.side == goal_side);
+        return (start, goal);
+    }
+    (
+        box_ports(grid, from, to, request.from_row, request.prefer),
+        box_ports(grid, to, from, request.to_row, request.prefer),
+    )
+}
+
+/// A routed line with the sides its ends attach to
+struct Route {
+    points: Vec<(f32, f32)>,
+    start: Side,
+    goal: Side,
+}
+
+/// A line end fixed to a point on a box side
+#[derive(Debug, Clone, Copy)]
+struct End {
+    side: Side,
+    point: (f32, f32),
+}
+
+/// Route each request between its candidate ports, in order. Lines of the
+/// same group may share grid segments; other lines pay for sharing them.
+fn route_all(
+    grid: &Grid,
+    ports: &[(Vec<Port>, Vec<Port>)],
+    groups: &[usize],
+) -> Vec<Option<Route>> {
+    let mut usage = Usage::new(grid.len());
+    ports
+        .iter()
+        .zip(groups)
+        .map(|((starts, goals), &group)| {
+            let nodes = grid.search(starts, goals, &usage, group)?;
+            usage.record(&nodes, group);
+            let (start, goal) = (nodes[0], nodes[nodes.len() - 1]);
+            let start = starts.iter().find(|p| p.node == start)?;
+            let goal = goals.iter().find(|p| p.node == goal)?;
+
+            let mut points = vec![start.point];
+            points.extend(nodes.iter().map(|&n| grid.point(n)));
+            points.push(goal.point);
+            Some(Route {
+                points: straighten(simplify(points)),
+                start: start.side,
+                goal: goal.side,
+            })
+        })
+        .collect()
+}
+
+/// Group number of each request: requests on the same bus that share `key`
+/// get the same group, every other request a group of its own
+fn bus_groups(requests: &[RouteRequest], key: impl Fn(usize) -> usize) -> Vec<usize> {
+    let mut buses: Vec<(usize, usize)> = Vec::new();
+    (0..requests.len())
+        .map(|r| {
+            let Some(bus) = requests[r].bus else {
+                return r;
+            };
+            let id = (bus, key(r));
+            let index = buses.iter().position(|&b| b == id).unwrap_or_else(|| {
+                buses.push(id);
+                buses.len() - 1
+            });
+            requests.len() + index
+        })
+        .collect()
+}
+
+/// Spread the ends sharing a box side along it. Ends on a member row keep
+/// their row, and the ends of one bus share a single port.
+fn assign_ports(
+    boxes: &[Rect],
+    requests: &[RouteRequest],
+    routes: &[Option<Route>],
+) -> Vec<Option<(End, End)>> {
+    // (box, side, bus, route, is goal, sort key)
+    let mut slots: Vec<(usize, Side, Option<usize>, usize, bool, PortKey)> = Vec::new();
+    for (r, route) in routes.iter().enumerate() {
+        let Some(route) = route else { continue };
+        let request = &requests[r];
+        if request.from_row.is_none() {
+            let key = port_key(route.start, &route.points);
+            slots.push((request.from, route.start, None, r, false, key));
+        }
+        if request.to_row.is_none() {
+            let mut back = route.points.clone();
+            back.reverse();
+            let key = port_key(route.goal, &back);
+            slots.push((request.to, route.goal, request.bus, r, true, key));
+        }
+    }
+
+    let mut ends: Vec<Option<(End, End)>> = routes
+        .iter()
+        .map(|route| {
+            let route = route.as_ref()?;
+            let end = |side, point| End { side, point };
+            Some((
+                end(route.start, route.points[0]),
+                end(route.goal, route.points[route.points.len() - 1]),
+            ))
+        })
+        .collect();
+
+    let mut done = vec![false; slots.len()];
+    for i in 0..slots.len() {
+        if done[i] {
+            continue;
+        }
+        let (b, side) = (slots[i].0, slots[i].1);
+        let on_side: Vec<usize> = (i..slots.len())
+            .filter(|&j| slots[j].0 == b && slots[j].1 == side)
+            .collect();
+        for &j in &on_side {
+            done[j] = true;
+        }
+
+        let rect = boxes[b];
+        let horizontal = matches!(side, Side::Top | Side::Bottom);
+        let (low, length) = if horizontal {
+            (rect.x, rect.width)
+        } else {
+            (rect.y, rect.height)
+        };
+        let centre = low + length / 2.0;
+
+        // A bus takes one port, ranked as a straight line from the centre
+        let mut ports: Vec<(PortKey, Vec<usize>)> = Vec::new();
+        for &j in &on_side {
+            match slots[j].2 {
+                Some(bus) => {
+                    let shared = ports
+                        .iter_mut()
+                        .find(|(_, members)| members.iter().any(|&k| slots[k].2 == Some(bus)));
+                    match shared {
+                        Some((_, members)) => members.push(j),
+                        None => ports.push(((1, centre, 0.0), vec![j])),
+                    }
+                }
+                None => ports.push((slots[j].5, vec![j])),
+            }
+        }
+        ports.sort_by(|a, b| cmp_keys(&a.0, &b.0));
+
+        let n = ports.len() as f32;
+        let gap = if ports.len() > 1 {
+            PORT_GAP.min(((length - 2.0 * PORT_INSET) / (n - 1.0)).max(0.0))
+        } else {
+            0.0
+        };
+        for (k, (_, members)) in ports.iter().enumerate() {
+            let along = centre + (k as f32 - (n - 1.0) / 2.0) * gap;
+            for &j in members {
+                let (_, _, _, r, is_goal, _) = slots[j];
+                let Some((start, goal)) = ends[r].as_mut() else {
+                    continue;
+                };
+                let end = if is_goal { goal } else { start };
+                if horizontal {
+                    end.point.0 = along;
+                } else {
+                    end.point.1 = along;
+                }
+            }
+        }
+    }
+    ends
+}
+
+/// Order of the ends on one side, ascending along it
+type PortKey = (u8, f32, f32);
+
+/// Sort key of a line end on `side`, from its route starting at the port.
+/// Lines turning towards the low end of the side come first, the closest
+/// turn outermost; then straight lines by where they lead; then lines
+/// turning towards the high end, the closest turn outermost. Nested this
+/// way, lines leaving one side never cross each other.
+fn port_key(side: Side, points: &[(f32, f32)]) -> PortKey {
+    let horizontal = matches!(side, Side::Top | Side::Bottom);
+    let along = |p: (f32, f32)| if horizontal { p.0 } else { p.1 };
+    let across = |p: (f32, f32)| if horizontal { p.1 } else { p.0 };
+    let last = along(points[points.len() - 1]);
+    if points.len() < 3 {
+        return (1, last, 0.0);
+    }
+    let turn = (across(points[1]) - across(points[0])).abs();
+    let step = along(points[2]) - along(points[1]);
+    if step < 0.0 {
+        (0, turn, last)
+    } else if step > 0.0 {
+        (2, -turn, last)
+    } else {
+        (1, last, 0.0)
+    }
+}
+
+fn cmp_keys(a: &PortKey, b: &PortKey) -> Ordering {
+    a.0.cmp(&b.0)
+        .then(a.1.total_cmp(&b.1))
+        .then(a.2.total_cmp(&b.2))
+}
+
+/// Ports of `rect` at the middle of each side, or at `row` on the left and
+/// right sides, priced by whether they face `other`. A vertical line only
+/// takes the left and right sides cheaply when the boxes sit side by side,
+/// a horizontal one the top and bottom sides when they are stacked.
+fn box_ports(
+    grid: &Grid,
+    rect: Rect,
+    other: Rect,
+    row: Option<f32>,
+    prefer: Option<Axis>,
+) -> Vec<Port> {
+    let (cx, cy) = (rect.x + rect.width / 2.0, rect.y + rect.height / 2.0);
+    let dx = other.x + other.width / 2.0 - cx;
+    let dy = other.y + other.height / 2.0 - cy;
+    let beside = other.y < rect.bottom() && other.bottom() > rect.y;
+    let stacked = other.x < rect.right() && other.right() > rect.x;
+
+    let sides: &[Side] = if row.is_some() {
+        &[Side::Left, Side::Right]
+    } else {
+        &[Side::Top, Side::Bottom, Side::Left, Side::Right]
+    };
+    let row = row.unwrap_or(cy);
+    sides
+        .iter()
+        .filter_map(|&side| {
+            let (point, facing, horizontal) = match side {
+                Side::Top => ((cx, rect.y), dy < 0.0, false),
+                Side::Bottom => ((cx, rect.bottom()), dy > 0.0, false),
+                Side::Left => ((rect.x, row), dx < 0.0, true),
+                Side::Right => ((rect.right(), row), dx > 0.0, true),
+            };
+            let mut cost = CLEARANCE;
+            if !facing {
+                cost += SIDE_COST;
+            }
+            let against = match prefer {
+                Some(Axis::Vertical) => horizontal && !beside,
+                Some(Axis::Horizontal) => !horizontal && !stacked,
+                None => false,
+            };
+            if against {
+                cost += BEND_COST * 3.0;
+            }
+            grid.port(side, point, cost)
+        })
+        .collect()
+}
+
+/// How many routed lines use each grid segment, in total and per group
+struct Usage {
+    total: Vec<[u16; 2]>,
+    by_group: HashMap<(usize, usize, usize), u16>,
+}
+
+impl Usage {
+    fn new(nodes: usize) -> Self {
+        Self {
+            total: vec![[0; 2]; nodes],
+            by_group: HashMap::new(),
+        }
+    }
+
+    fn record(&mut self, nodes: &[usize], group: usize) {
+        for pair in nodes.windows(2) {
+            let (a, b) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+            if a == b {
+                continue;
+            }
+            let axis = usize::from(b - a != 1);
+            self.total[a][axis] = self.total[a][axis].saturating_add(1);
+            *self.by_group.entry((group, a, axis)).or_default() += 1;
+        }
+    }
+
+    /// Cost per pixel of a segment for a line of `group`: dearer for each
+    /// other line on it, cheaper when its own bus already runs there
+    fn factor(&self, segment: usize, axis: usize, group: usize) -> f32 {
+        let own = self
+            .by_group
+            .get(&(group, segment, axis))
+            .copied()
+            .unwrap_or(0);
+        let others = self.total[segment][axis] - own;
+        let base = if own > 0 { BUS_COST } else { 1.0 };
+        base + SHARED_COST * others as f32
+    }
+}
+
+/// Sparse routing grid: the crossings of every interesting horizontal and
+/// vertical line that lie outside the grown boxes
+struct Grid {
+    xs: Vec<f32>,
+    ys: Vec<f32>,
+    /// Node lies strictly inside a grown box
+    blocked: Vec<bool>,
+    /// Segment to the right / below the node is free, indexed `[node][axis]`
+    open: Vec<[bool; 2]>,
+    /// Segment to the right / below the node runs along a grown box edge
+    hugs: Vec<[bool; 2]>,
+}
+
+impl Grid {
+    /// Grid around `boxes`, with extra lines through the given coordinates
+    fn new(boxes: &[Rect], extra_xs: &[f32], extra_ys: &[f32]) -> Self {
+        let grown: Vec<Rect> = boxes
+            .iter()
+            .map(|r| {
+                Rect::new(
+                    r.x - CLEARANCE,
+                    r.y - CLEARANCE,
+                    r.width + CLEARANCE * 2.0,
+                    r.height + CLEARANCE * 2.0,
+                )
+            })
+            .collect();
+
+        let mut xs: Vec<f32> = grown.iter().flat_map(|r| [r.x, r.right()]).collect();
+        let mut ys: Vec<f32> = grown.iter().flat_map(|r| [r.y, r.bottom()]).collect();
+        add_channels(&mut xs);
+        add_channels(&mut ys);
+        xs.extend_from_slice(extra_xs);
+        ys.extend_from_slice(extra_ys);
+        let xs = dedup_sorted(xs);
+        let ys = dedup_sorted(ys);
+
+        let inside = |px: f32, py: f32| {
+            grown.iter().any(|r| {
+                px > r.x + EPSILON
+                    && px < r.right() - EPSILON
+                    && py > r.y + EPSILON
+                    && py < r.bottom() - EPSILON
+            })
+        };
+        let on_edge = |px: f32, py: f32| {
+            grown.iter().any(|r| {
+                let within_x = px > r.x - EPSILON && px < r.right() + EPSILON;
+                let within_y = py > r.y - EPSILON && py < r.bottom() + EPSILON;
+                (within_y && ((px - r.x).abs() < EPSILON || (px - r.right()).abs() < EPSILON))
+                    || (within_x
+                        && ((py - r.y).abs() < EPSILON || (py - r.bottom()).abs() < EPSILON))
+            })
+        };
+        let (nx, ny) = (xs.len(), ys.len());
+        let mut blocked = vec![false; nx * ny];
+        let mut open = vec![[false; 2]; nx * ny];
+        let mut hugs = vec![[false; 2]; nx * ny];
+        for yi in 0..ny {
+            for xi in 0..nx {
+                let node = yi * nx + xi;
+                blocked[node] = inside(xs[xi], ys[yi]);
+                if xi + 1 < nx {
+                    let mid = (xs[xi] + xs[xi + 1]) / 2.0;
+                    open[node][0] = !inside(mid, ys[yi]);
+                    hugs[node][0] = on_edge(mid, ys[yi]);
+                }
+                if yi + 1 < ny {
+                    let mid = (ys[yi] + ys[yi + 1]) / 2.0;
+                    open[node][1] = !inside(xs[xi], mid);
+                    hugs[node][1] = on_edge(xs[xi], mid);
+                }
+            }
+        }
+        Self {
+            xs,
+            ys,
+            blocked,
+            open,
+            hugs,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.blocked.len()
+    }
+
+    fn point(&self, node: usize) -> (f32, f32) {
+        let nx = self.xs.len();
+        (self.xs[node % nx], self.ys[node / nx])
+    }
+
+    fn node_at(&self, x: f32, y: f32) -> Option<usize> {
+        let find = |lines: &[f32], v: f32| lines.iter().position(|&l| (l - v).abs() < EPSILON);
+        Some(find(&self.ys, y)? * self.xs.len() + find(&self.xs, x)?)
+    }
+
+    /// A port at `point` on a box side, entering the grid just outside the
+    /// clearance margin
+    fn port(&self, side: Side, point: (f32, f32), cost: f32) -> Option<Port> {
+        let (x, y) = match side {
+            Side::Top => (point.0, point.1 - CLEARANCE),
+            Side::Bottom => (point.0, point.1 + CLEARANCE),
+            Side::Left => (point.0 - CLEARANCE, point.1),
+            Side::Right => (point.0 + CLEARANCE, point.1),
+        };
+        Some(Port {
+            side,
+            point,
+            node: self.node_at(x, y)?,
+            cost,
+        })
+    }
+
+    /// Neighbour of `node` one grid line away in `dir`, if the segment is free
+    fn step(&self, node: usize, dir: Dir) -> Option<usize> {
+        let nx = self.xs.len();
+        let (xi, yi) = (node % nx, node / nx);
+        let next = match dir {
+            Dir::Left if xi > 0 && self.open[node - 1][0] => node - 1,
+            Dir::Right if xi + 1 < nx && self.open[node][0] => node + 1,
+            Dir::Up if yi > 0 && self.open[node - nx][1] => node - nx,
+            Dir::Down if yi + 1 < self.ys.len() && self.open[node][1] => node + nx,
+            _ => return None,
+        };
+        (!self.blocked[next]).then_some(next)
+    }
+
+    /// Cheapest grid path from any start port to any goal port for a line
+    /// of `group`
+    fn search(
+        &self,
+        starts: &[Port],
+        goals: &[Port],
+        usage: &Usage,
+        group: usize,
+    ) -> Option<Vec<usize>> {
+        let state_count = self.len() * 4;
+        let mut cost = vec![f32::INFINITY; state_count];
+        let mut prev = vec![usize::MAX; state_count];
+        let mut heap = BinaryHeap::new();
+
+        let heuristic = |node: usize| {
+            let (x, y) = self.point(node);
+            goals
+                .iter()
+                .map(|g| {
+                    let (gx, gy) = self.point(g.node);
+                    (gx - x).abs() + (gy - y).abs()
+                })
+                .fold(f32::INFINITY, f32::min)
+        };
+
+        for port in starts {
+            let state = port.node * 4 + port.side.outward().index();
+            if port.cost < cost[state] {
+                cost[state] = port.cost;
+                heap.push(Entry {
+                    priority: port.cost + heuristic(port.node),
+                    state,
+                    done: false,
+                });
+            }
+        }
+
+        while let Some(Entry {
+            priority,
+            state,
+            done,
+        }) = heap.pop()
+        {
+            if done {
+                return Some(self.trace(state, &prev));
+            }
+            let (node, dir) = (state / 4, Dir::ALL[state % 4]);
+            let g = cost[state];
+            if priority > g + heuristic(node) + EPSILON {
+                continue;
+            }
+
+            // Arriving here may finish the route through a goal port
+            for goal in goals.iter().filter(|goal| goal.node == node) {
+                let inward = goal.side.outward().opposite();
+                if dir == inward.opposite() {
+                    continue;
+                }
+                let bend = if dir == inward { 0.0 } else { BEND_COST };
+                heap.push(Entry {
+                    priority: g + bend + goal.cost,
+                    state,
+                    done: true,
+                });
+            }
+
+            for next_dir in Dir::ALL {
+                if next_dir == dir.opposite() {
+                    continue;
+                }
+                let Some(next) = self.step(node, next_dir) else {
+                    continue;
+                };
+                let (a, b) = (self.point(node), self.point(next));
+                let length = (a.0 - b.0).abs() + (a.1 - b.1).abs();
+                let axis = usize::from(matches!(next_dir, Dir::Up | Dir::Down));
+                let segment = node.min(next);
+                let hug = if self.hugs[segment][axis] {
+                    HUG_COST
+                } else {
+                    0.0
+                };
+                let bend = if next_dir == dir { 0.0 } else { BEND_COST };
+                let next_cost = g + length * (usage.factor(segment, axis, group) + hug) + bend;
+                let next_state = next * 4 + next_dir.index();
+                if next_cost < cost[next_state] {
+                    cost[next_state] = next_cost;
+                    prev[next_state] = state;
+                    heap.push(Entry {
+                        priority: next_cost + heuristic(next),
+                        state: next_state,
+                        done: false,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Grid nodes on the way to `state`, first to last
+    fn trace(&self, mut state: usize, prev: &[usize]) -> Vec<usize> {
+        let mut nodes = vec![state / 4];
+        while prev[state] != usize::MAX {
+            state = prev[state];
+            nodes.push(state / 4);
+        }
+        nodes.reverse();
+        nodes
+    }
+}
+
+/// A search state in the A* queue, cheapest first
+struct Entry {
+    priority: f32,
+    state: usize,
+    /// The route is complete once this entry is taken
+    done: bool,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed for the max-heap; ties go to finished routes, then the
+        // lower state, so equal-cost searches always pick the same path
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then(self.done.cmp(&other.done))
+            .then(other.state.cmp(&self.state))
+    }
+}
+
+/// Add the centre line of every gap between grown boxes wide enough to be
+/// a channel, so lines run down the middle instead of hugging a box
+fn add_channels(lines: &mut Vec<f32>) {
+    let sorted = dedup_sorted(lines.clone());
+    for pair in sorted.windows(2) {
+        if pair[1] - pair[0] > CLEARANCE * 2.0 {
+            lines.push((pair[0] + pair[1]) / 2.0);
+        }
+    }
+}
+
+fn dedup_sorted(mut lines: Vec<f32>) -> Vec<f32> {
+    lines.sort_by(f32::total_cmp);
+    lines.dedup_by(|a, b| (*a - *b).abs() < EPSILON);
+    lines
+}
+
+/// Drop repeated points and the middle of straight runs
+fn simplify(points: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+    let mut out: Vec<(f32, f32)> = Vec::with_capacity(points.len());
+    for p in points {
+        if out.last().is_some_and(|&q| same_point(p, q)) {
+            continue;
+        }
+        if out.len() >= 2 {
+            let (a, b) = (out[out.len() - 2], out[out.len() - 1]);
+            let straight = ((a.0 - b.0).abs() < EPSILON && (b.0 - p.0).abs() < EPSILON)
+                || ((a.1 - b.1).abs() < EPSILON && (b.1 - p.1).abs() < EPSILON);
+            if straight {
+                out.pop();
+            }
+        }
+        out.push(p);
+    }
+    out
+}
+
+/// Remove jogs shorter than the nudge gap, which come from neighbouring grid
+/// lines a few pixels apart, by moving an inner segment onto its neighbour's
+/// line. Shared lines are spread apart properly by `nudge` afterwards.
+fn straighten(mut points: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+    let mut i = 1;
+    while i + 2 < points.len() {
+        let (a, b, c, d) = (points[i - 1], points[i], points[i + 1], points[i + 2]);
+        let vertical_jog = a.0 == b.0 && c.0 == d.0 && (b.0 - c.0).abs() < NUDGE_GAP;
+        let horizontal_jog = a.1 == b.1 && c.1 == d.1 && (b.1 - c.1).abs() < NUDGE_GAP;
+        // Only Z-shaped jogs; the lines before and after run the same way
+        let same_way = (b.0 - a.0) * (d.0 - c.0) + (b.1 - a.1) * (d.1 - c.1) > 0.0;
+        if (vertical_jog || horizontal_jog) && same_way {
+            // Move whichever side is not attached to a port
+            let (from, to) = if i + 2 < points.len() - 1 {
+                (i + 1, b)
+            } else if i > 1 {
+                (i - 1, c)
+            } else {
+                i += 1;
+                continue;
+            };
+            for p in &mut points[from..from + 2] {
+                if vertical_jog {
+                    p.0 = to.0;
+                } else {
+                    p.1 = to.1;
+                }
+            }
+            points = simplify(points);
+            i = 1;
+            continue;
+        }
+        i += 1;
+    }
+    points
+}
+
+fn same_point(a: (f32, f32), b: (f32, f32)) -> bool {
+    (a.0 - b.0).abs() < EPSILON && (a.1 - b.1).abs() < EPSILON
+}
+
+/// Spread segments of different routes that run along the same line and
+/// overlap, so each stays visible. Moving an end segment slides its port
+/// along the box side, so ends pinned to a member row are left alone. Lines
+/// of one group (a bus) stay together.
+fn nudge(
+    routes: &mut [Option<Vec<(f32, f32)>>],
+    pinned: &[(bool, bool)],
+    groups: &[usize],
+    vertical: bool,
+) {
+    // (line, low, high, route, segment)
+    let mut segments: Vec<(f32, f32, f32, usize, usize)> = Vec::new();
+    for (r, route) in routes.iter().enumerate() {
+        let Some(points) = route else { continue };
+        let first = usize::from(pinned[r].0);
+        let last = (points.len() - 1).saturating_sub(usize::from(pinned[r].1));
+        for s in first..last {
+            let (a, b) = (points[s], points[s + 1]);
+            let (line, lo, hi) = if vertical {
+                (a.0, a.1.min(b.1), a.1.max(b.1))
+            } else {
+                (a.1, a.0.min(b.0), a.0.max(b.0))
+            };
+            let along = if vertical { a.0 == b.0 } else { a.1 == b.1 };
+            if along {
+                segments.push((line, lo, hi, r, s));
+            }
+        }
+    }
+    segments.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+    let mut i = 0;
+    while i < segments.len() {
+        // Gather a run of overlapping segments on one line
+        let mut j = i + 1;
+        let mut reach = segments[i].2;
+        while j < segments.len()
+            && (segments[j].0 - segments[i].0).abs() < EPSILON
+            && segments[j].1 < reach - EPSILON
+        {
+            reach = reach.max(segments[j].2);
+            j += 1;
+        }
+        let cluster = &segments[i..j];
+        i = j;
+        // One representative segment per group
+        let mut lanes: Vec<(f32, f32, f32, usize, usize)> = Vec::new();
+        for &segment in cluster {
+            if !lanes.iter().any(|l| groups[l.3] == groups[segment.3]) {
+                lanes.push(segment);
+            }
+        }
+        if lanes.len() < 2 {
+            continue;
+        }
+
+        // Order by where the neighbouring segments lead, to avoid crossings
+        let lean = |&(_, _, _, r, s): &(f32, f32, f32, usize, usize)| {
+            let points = routes[r].as_ref().map_or(&[][..], |p| &p[..]);
+            let before = points[s.saturating_sub(1)];
+            let after = points[(s + 2).min(points.len() - 1)];
+            if vertical {
+                before.0 + after.0
+            } else {
+                before.1 + after.1
+            }
+        };
+        lanes.sort_by(|a, b| lean(a).total_cmp(&lean(b)));
+
+        let n = lanes.len() as f32;
+        let gap = NUDGE_GAP.min((CLEARANCE - 2.0) * 2.0 / (n - 1.0));
+        for (k, lane) in lanes.iter().enumerate() {
+            let offset = (k as f32 - (n - 1.0) / 2.0) * gap;
+            for &(_, _, _, r, s) in cluster.iter().filter(|c| groups[c.3] == groups[lane.3]) {
+                if let Some(points) = routes[r].as_mut() {
+                    for p in &mut points[s..s + 2] {
+                        if vertical {
+                            p.0 += offset;
+                        } else {
+                            p.1 += offset;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(from: usize, to: usize) -> RouteRequest {
+        RouteRequest {
+            from,
+            to,
+            from_row: None,
+            to_row: None,
+            prefer: None,
+            bus: None,
+        }
+    }
+
+    fn is_orthogonal(points: &[(f32, f32)]) -> bool {
+        points
+            .windows(2)
+            .all(|w| w[0].0 == w[1].0 || w[0].1 == w[1].1)
+    }
+
+    /// Whether a segment passes through the inside of a box
+    fn crosses(a: (f32, f32), b: (f32, f32), r: Rect) -> bool {
+        let (lx, hx) = (a.0.min(b.0), a.0.max(b.0));
+        let (ly, hy) = (a.1.min(b.1), a.1.max(b.1));
+        lx < r.right() && hx > r.x && ly < r.bottom() && hy > r.y
+    }
+
+    #[test]
+    fn test_routes_around_obstacle() {
+        // A blocker sits squarely between the two ends
+        let boxes = [
+            Rect::new(0.0, 100.0, 80.0, 40.0),
+            Rect::new(200.0, 80.0, 80.0, 80.0),
+            Rect::new(400.0, 100.0, 80.0, 40.0),
+        ];
+        let routes = route_edges(&boxes, &[request(0, 2)]);
+        let points = routes[0].as_ref().unwrap();
+        assert!(is_orthogonal(points));
+        assert_eq!(points[0], (80.0, 120.0));
+        assert_eq!(*points.last().unwrap(), (400.0, 120.0));
+        assert!(points.windows(2).all(|w| !crosses(w[0], w[1], boxes[1])));
+    }
+
+    #[test]
+    fn test_straight_when_clear() {
+        let boxes = [
+            Rect::new(0.0, 0.0, 80.0, 40.0),
+            Rect::new(0.0, 200.0, 80.0, 40.0),
+        ];
+        let routes = route_edges(
+            &boxes,
+            &[RouteRequest {
+                prefer: Some(Axis::Vertical),
+                ..request(1, 0)
+            }],
+        );
+        assert_eq!(
+            routes[0].as_deref(),
+            Some(&[(40.0, 200.0), (40.0, 40.0)][..])
+        );
+    }
+
+    #[test]
+    fn test_parallel_edges_kept_apart() {
+        let boxes = [
+            Rect::new(0.0, 0.0, 80.0, 40.0),
+            Rect::new(0.0, 300.0, 80.0, 40.0),
+            Rect::new(300.0, 0.0, 80.0, 40.0),
+            Rect::new(300.0, 300.0, 80.0, 40.0),
+            Rect::new(150.0, 100.0, 80.0, 140.0),
+        ];
+        let routes = route_edges(&boxes, &[request(0, 3), request(2, 1)]);
+        let (a, b) = (routes[0].as_ref().unwrap(), routes[1].as_ref().unwrap());
+        for (p, q) in a.windows(2).flat_map(|s| b.windows(2).map(move |t| (s, t))) {
+            let collinear_vertical = p[0].0 == p[1].0 && q[0].0 == q[1].0 && p[0].0 == q[0].0;
+            let collinear_horizontal = p[0].1 == p[1].1 && q[0].1 == q[1].1 && p[0].1 == q[0].1;
+            if collinear_vertical {
+                let overlap = p[0].1.max(p[1].1).min(q[0].1.max(q[1].1))
+                    - p[0].1.min(p[1].1).max(q[0].1.min(q[1].1));
+                assert!(overlap <= 0.0, "{:?} {:?}", a, b);
+            }
+            if collinear_horizontal {
+                let overlap = p[0].0.max(p[1].0).min(q[0].0.max(q[1].0))
+                    - p[0].0.min(p[1].0).max(q[0].0.min(q[1].0));
+                assert!(overlap <= 0.0, "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_self_loop_and_rows() {
+        let boxes = [
+            Rect::new(0.0, 0.0, 80.0, 60.0),
+            Rect::new(200.0, 0.0, 80.0, 60.0),
+        ];
+        let routes = route_edges(
+            &boxes,
+            &[
+                request(0, 0),
+                RouteRequest {
+                    from_row: Some(45.0),
+                    ..request(0, 1)
+                },
+            ],
+        );
+        let lap = routes[0].as_ref().unwrap();
+        assert_eq!(lap[0], (80.0, 30.0));
+        assert_eq!(*lap.last().unwrap(), (40.0, 0.0));
+        assert!(is_orthogonal(lap));
+        let row = routes[1].as_ref().unwrap();
+        assert_eq!(row[0], (80.0, 45.0));
+        assert!(is_orthogonal(row));
+    }
+
+    /// A parent above three children spread out below it
+    fn family() -> [Rect; 4] {
+        [
+            Rect::new(200.0, 0.0, 120.0, 60.0),
+            Rect::new(0.0, 200.0, 80.0, 40.0),
+            Rect::new(220.0, 200.0, 80.0, 40.0),
+            Rect::new(440.0, 200.0, 80.0, 40.0),
+        ]
+    }
+
+    #[test]
+    fn test_preferred_axis() {
+        // Diagonal neighbours: either pair of sides would do
+        let boxes = [
+            Rect::new(0.0, 0.0, 80.0, 40.0),
+            Rect::new(200.0, 200.0, 80.0, 40.0),
+        ];
+        let prefer = |axis| RouteRequest {
+            prefer: Some(axis),
+            ..request(1, 0)
+        };
+        let routes = route_edges(&boxes, &[prefer(Axis::Vertical), prefer(Axis::Horizontal)]);
+        let (down, across) = (routes[0].as_ref().unwrap(), routes[1].as_ref().unwrap());
+        assert_eq!(
+            (down[0], *down.last().unwrap()),
+            ((240.0, 200.0), (40.0, 40.0))
+        );
+        assert_eq!(
+            (across[0], *across.last().unwrap()),
+            ((200.0, 220.0), (80.0, 20.0))
+        );
+    }
+
+    #[test]
+    fn test_ports_spread_in_order() {
+        let boxes = family();
+        let down = |to| RouteRequest {
+            prefer: Some(Axis::Vertical),
+            ..request(0, to)
+        };
+        let routes = route_edges(&boxes, &[down(3), down(1), down(2)]);
+        let starts: Vec<(f32, f32)> = routes.iter().map(|r| r.as_ref().unwrap()[0]).collect();
+        // All leave the bottom of the parent, left to right as their targets
+        assert!(starts.iter().all(|p| p.1 == 60.0));
+        assert!(starts[1].0 < starts[2].0 && starts[2].0 < starts[0].0);
+        assert_eq!(starts[2].0, 260.0);
+    }
+
+    #[test]
+    fn test_bus_shares_trunk() {
+        let boxes = family();
+        let child = |from| RouteRequest {
+            prefer: Some(Axis::Vertical),
+            bus: Some(0),
+            ..request(from, 0)
+        };
+        let routes = route_edges(&boxes, &[child(1), child(2), child(3)]);
+        let routes: Vec<&Vec<(f32, f32)>> = routes.iter().map(|r| r.as_ref().unwrap()).collect();
+        for route in &routes {
+            assert!(is_orthogonal(route));
+            assert_eq!(*route.last().unwrap(), (260.0, 60.0));
+        }
+        // The side branches join the middle line on one horizontal
+        let join = |route: &Vec<(f32, f32)>| route[route.len() - 2].1;
+        assert_eq!(join(routes[0]), join(routes[2]));
+    }
+}
@@ -0,0 +1,455 @@
+//! Minimal JSON reader and writer used by the importers and layout files.
+//!
+//! Only what the crate needs: parsing a document into a [`JsonValue`] tree, a
+//! few typed accessors, and writing a tree back out. Numbers keep their source
+//! text, so integers too large for an `f64` (such as nanosecond timestamps)
+//! stay exact.
+
+use std::fmt;
+
+/// A parsed JSON value. Object keys keep their source order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    /// A number as written in the source
+    Number(String),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Look up a key in an object
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Look up the first of several alternative keys in an object
+    pub fn get_any(&self, keys: &[&str]) -> Option<&JsonValue> {
+        keys.iter().find_map(|k| self.get(k))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Interpret an integer number, or a string holding one, as a `u128`
+    pub fn as_u128(&self) -> Option<u128> {
+        match self {
+            Self::Number(n) => n.parse().ok(),
+            Self::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl From<f64> for JsonValue {
+    /// A number, or `null` for the infinities and NaN, which JSON cannot hold
+    fn from(n: f64) -> Self {
+        if n.is_finite() {
+            Self::Number(n.to_string())
+        } else {
+            Self::Null
+        }
+    }
+}
+
+/// A JSON syntax error at a byte offset
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+/// Parse a complete JSON document
+pub fn parse(source: &str) -> Result<JsonValue, JsonError> {
+    let mut reader = Reader {
+        bytes: source.as_bytes(),
+        source,
+        pos: 0,
+        depth: 0,
+    };
+    let value = reader.value()?;
+    reader.skip_whitespace();
+    if reader.pos != reader.bytes.len() {
+        return Err(reader.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Write a value as indented JSON. Arrays and objects holding only scalars
+/// stay on one line, so small records read as one entry per line.
+pub fn to_string_pretty(value: &JsonValue) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, 0);
+    out.push('\n');
+    out
+}
+
+fn write_value(out: &mut String, value: &JsonValue, indent: usize) {
+    match value {
+        JsonValue::Null => out.push_str("null"),
+        JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        JsonValue::Number(n) => out.push_str(n),
+        JsonValue::String(s) => write_string(out, s),
+        JsonValue::Array(items) => {
+            write_list(out, ('[', ']'), items.iter().map(|v| (None, v)), indent)
+        }
+        JsonValue::Object(entries) => write_list(
+            out,
+            ('{', '}'),
+            entries.iter().map(|(k, v)| (Some(k.as_str()), v)),
+            indent,
+        ),
+    }
+}
+
+fn write_list<'a>(
+    out: &mut String,
+    (open, close): (char, char),
+    items: impl Iterator<Item = (Option<&'a str>, &'a JsonValue)> + Clone,
+    indent: usize,
+) {
+    let flat = items
+        .clone()
+        .all(|(_, v)| !matches!(v, JsonValue::Array(_) | JsonValue::Object(_)));
+    out.push(open);
+    for (i, (key, value)) in items.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if flat {
+            if i > 0 {
+                out.push(' ');
+            }
+        } else {
+            out.push('\n');
+            out.push_str(&"  ".repeat(indent + 1));
+        }
+        if let Some(key) = key {
+            write_string(out, key);
+            out.push_str(": ");
+        }
+        write_value(out, value, indent + 1);
+    }
+    if !flat {
+        out.push('\n');
+        out.push_str(&"  ".repeat(indent));
+    }
+    out.push(close);
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Deepest nesting of arrays and objects accepted, so hostile input cannot
+/// overflow the stack
+const MAX_DEPTH: usize = 128;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    source: &'a str,
+    pos: usize,
+    /// Arrays and objects currently open
+    depth: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> JsonError {
+        JsonError {
+            offset: self.pos,
+            message: message.to_string(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, text: &str, value: JsonValue) -> Result<JsonValue, JsonError> {
+        if self.source[self.pos..].starts_with(text) {
+            self.pos += text.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, JsonError> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<JsonValue, JsonError>,
+    ) -> Result<JsonValue, JsonError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let value = self.value()?;
+            entries.push((key, value));
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Object(entries));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<JsonValue, JsonError> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(JsonValue::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(&b) = self.bytes.get(self.pos) {
+                if b == b'"' || b == b'\\' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            out.push_str(&self.source[start..self.pos]);
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.bytes.get(self.pos) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let c = self.unicode_escape()?;
+                            out.push(c);
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    out.push(escaped);
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let hex = self
+            .source
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("truncated unicode escape"))?;
+        // `from_str_radix` would also take a sign such as `+04a`
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(self.error("invalid unicode escape"));
+        }
+        let code =
+            u32::from_str_radix(hex, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) && self.source[self.pos..].starts_with("\\u")
+        {
+            self.pos += 2;
+            let low = self.hex4()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        Ok(char::from_u32(code).unwrap_or('\u{FFFD}'))
+    }
+
+    fn number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.pos;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.bytes.get(self.pos) {
+            self.pos += 1;
+        }
+        let text = &self.source[start..self.pos];
+        match text.parse::<f64>() {
+            Ok(_) => Ok(JsonValue::Number(text.to_string())),
+            Err(_) => Err(JsonError {
+                offset: start,
+                message: "invalid number".to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_nested() {
+        let value = parse(r#"{"a": [1, -2.5e1, true, null], "b": {"c": "x\"é"}}"#).unwrap();
+        let a = value.get("a").and_then(|v| v.as_array()).unwrap();
+        assert_eq!(a[1].as_f64(), Some(-25.0));
+        assert_eq!(a[3], JsonValue::Null);
+        assert_eq!(
+            value
+                .get("b")
+                .and_then(|b| b.get("c"))
+                .and_then(|c| c.as_str()),
+            Some("x\"é")
+        );
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let source = r#"{"a": {"x": 1.5, "y": -2}, "b\"\n": [true, null], "c": []}"#;
+        let value = parse(source).unwrap();
+        let text = to_string_pretty(&value);
+        assert_eq!(
+            text,
+            "{\n  \"a\": {\"x\": 1.5, \"y\": -2},\n  \"b\\\"\\n\": [true, null],\n  \"c\": []\n}\n"
+        );
+        assert_eq!(parse(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_large_integers_stay_exact() {
+        let value = parse(r#"[1700000000123456789, 2.5, -1, "42"]"#).unwrap();
+        let items = value.as_array().unwrap();
+        assert_eq!(items[0].as_u128(), Some(1_700_000_000_123_456_789));
+        assert_eq!(items[1].as_u128(), None);
+        assert_eq!(items[2].as_u128(), None);
+        assert_eq!(items[3].as_u128(), Some(42));
+        assert_eq!(
+            to_string_pretty(&value),
+            "[1700000000123456789, 2.5, -1, \"42\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("{\"a\": }").unwrap_err().offset, 6);
+        assert!(parse("[1, 2").is_err());
+        assert!(parse("{} x").is_err());
+        assert!(parse(r#""\u+04a""#).is_err());
+        assert_eq!(parse(r#""\u004A""#).unwrap(), JsonValue::String("J".into()));
+
+        let deep = |n: usize| "[".repeat(n) + &"]".repeat(n);
+        assert!(parse(&deep(MAX_DEPTH)).is_ok());
+        let error = parse(&deep(100_000)).unwrap_err();
+        assert_eq!(
+            (error.offset, error.message.as_str()),
+            (MAX_DEPTH, "nesting too deep")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Placement of relationship labels clear of boxes, lines and each other.
+//!
+//! Every label comes with candidate positions, each with a cost saying how
+//! much it is preferred. A candidate is scored by how much of it is covered by
+//! boxes and other labels and by how much line runs through it. Labels are
+//! placed greedily, then each is moved once more to its best candidate now
+//! that all the others are known.
+
+use crate::common::Rect;
+
+/// A label to place: its size and the centres it may take
+#[derive(Debug, Clone)]
+pub struct LabelRequest {
+    pub width: f32,
+    pub height: f32,
+    /// Candidate centres with the cost of choosing each
+    pub candidates: Vec<((f32, f32), f32)>,
+}
+
+/// Cost of a candidate fully covered by a box or another label
+const OVERLAP_COST: f32 = 1000.0;
+/// Cost per pixel of line running through a candidate
+const LINE_COST: f32 = 8.0;
+
+/// Choose a centre for every label. A label without candidates gets `None`.
+pub fn place_labels(
+    boxes: &[Rect],
+    lines: &[Vec<(f32, f32)>],
+    labels: &[LabelRequest],
+) -> Vec<Option<(f32, f32)>> {
+    let mut placed: Vec<Option<Rect>> = vec![None; labels.len()];
+    for _ in 0..2 {
+        for (i, label) in labels.iter().enumerate() {
+            let others: Vec<Rect> = placed
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .filter_map(|(_, rect)| *rect)
+                .collect();
+            placed[i] = label
+                .candidates
+                .iter()
+                .map(|&(centre, cost)| {
+                    let rect = centred(centre, label.width, label.height);
+                    (rect, cost + score(rect, boxes, lines, &others))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(rect, _)| rect);
+        }
+    }
+    placed
+        .into_iter()
+        .map(|rect| rect.map(|r| (r.x + r.width / 2.0, r.y + r.height / 2.0)))
+        .collect()
+}
+
+fn centred((cx, cy): (f32, f32), width: f32, height: f32) -> Rect {
+    Rect::new(cx - width / 2.0, cy - height / 2.0, width, height)
+}
+
+/// Collision cost of a label at `rect`
+fn score(rect: Rect, boxes: &[Rect], lines: &[Vec<(f32, f32)>], labels: &[Rect]) -> f32 {
+    let area = (rect.width * rect.height).max(1.0);
+    let covered: f32 = boxes
+        .iter()
+        .chain(labels)
+        .map(|&other| overlap(rect, other))
+        .sum();
+    let crossed: f32 = lines
+        .iter()
+        .flat_map(|line| line.windows(2))
+        .map(|segment| clipped_length(rect, segment[0], segment[1]))
+        .sum();
+    // Labels running off the top or left edge are cut off
+    let outside = (-rect.x).max(0.0) * rect.height + (-rect.y).max(0.0) * rect.width;
+    OVERLAP_COST * (covered + outside) / area + LINE_COST * crossed
+}
+
+fn overlap(a: Rect, b: Rect) -> f32 {
+    let w = a.right().min(b.right()) - a.x.max(b.x);
+    let h = a.bottom().min(b.bottom()) - a.y.max(b.y);
+    w.max(0.0) * h.max(0.0)
+}
+
+/// Length of the axis-aligned segment `a`-`b` inside `rect`
+fn clipped_length(rect: Rect, a: (f32, f32), b: (f32, f32)) -> f32 {
+    if a.1 == b.1 {
+        if a.1 <= rect.y || a.1 >= rect.bottom() {
+            return 0.0;
+        }
+        (a.0.max(b.0).min(rect.right()) - a.0.min(b.0).max(rect.x)).max(0.0)
+    } else if a.0 == b.0 {
+        if a.0 <= rect.x || a.0 >= rect.right() {
+            return 0.0;
+        }
+        (a.1.max(b.1).min(rect.bottom()) - a.1.min(b.1).max(rect.y)).max(0.0)
+    } else {
+        // Diagonal fallback lines: count the whole segment if either end is
+        // inside, which is good enough to steer labels away
+        let inside = |p: (f32, f32)| {
+            p.0 > rect.x && p.0 < rect.right() && p.1 > rect.y && p.1 < rect.bottom()
+        };
+        if inside(a) || inside(b) {
+            ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(candidates: &[(f32, f32)]) -> LabelRequest {
+        LabelRequest {
+            width: 40.0,
+            height: 12.0,
+            candidates: candidates
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| (c, i as f32))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_prefers_cheapest_free_candidate() {
+        let boxes = [Rect::new(0.0, 0.0, 100.0, 100.0)];
+        let lines = vec![vec![(150.0, 0.0), (150.0, 200.0)]];
+        // Inside the box, across the line, then free
+        let labels = [label(&[(50.0, 50.0), (150.0, 50.0), (220.0, 50.0)])];
+        assert_eq!(place_labels(&boxes, &lines, &labels), [Some((220.0, 50.0))]);
+    }
+
+    #[test]
+    fn test_labels_avoid_each_other() {
+        let labels = [
+            label(&[(50.0, 50.0), (50.0, 80.0)]),
+            label(&[(50.0, 50.0), (50.0, 20.0)]),
+        ];
+        let placed = place_labels(&[], &[], &labels);
+        assert_ne!(placed[0], placed[1]);
+        assert_eq!(placed[0], Some((50.0, 50.0)));
+    }
+}
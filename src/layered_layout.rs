@@ -0,0 +1,890 @@
+//! Layered (Sugiyama-style) placement of class diagram boxes.
+//!
+//! The layout runs in the classic phases:
+//!
+//! 1. cycle breaking, so the hierarchy edges form a DAG
+//! 2. longest-path layering, with sources pulled down next to their children
+//! 3. dummy nodes on edges that span several layers
+//! 4. barycenter sweeps to reduce edge crossings
+//! 5. Brandes–Köpf coordinate assignment, balancing its four alignments
+//!
+//! Boxes that have to share a layer (`-left-`/`-right-` hints and `together`
+//! groups) are merged into one block that is ordered and placed as a unit.
+//! Boxes without any relationship are set out in rows below the layers.
+//!
+//! Pinned blocks keep their corners. The free layers are fitted between the
+//! pinned blocks of the layers above and below them, and free blocks sharing
+//! a layer with pinned ones are compacted around them.
+
+use crate::class_diagram::{Direction, RelationType};
+use crate::common::DiagramStyle;
+use crate::layout_engine::LayoutEdge;
+use std::collections::HashSet;
+
+/// Number of barycenter sweeps tried before keeping the best ordering
+const SWEEPS: usize = 24;
+
+/// A unit of the layered graph: a block of boxes sharing a layer, or a dummy
+/// node standing in for an edge passing through a layer
+struct Unit {
+    /// Boxes in the block, left to right; empty for dummies
+    members: Vec<usize>,
+    width: f32,
+    height: f32,
+}
+
+impl Unit {
+    fn is_dummy(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+/// Place boxes of the given sizes; returns the top-left corner of each,
+/// relative to the origin, or to the pins if there are any.
+///
+/// `groups` are sets of boxes kept next to each other in one layer.
+pub fn layered_layout(
+    sizes: &[(f32, f32)],
+    edges: &[LayoutEdge],
+    groups: &[Vec<usize>],
+    pinned: &[Option<(f32, f32)>],
+    style: &DiagramStyle,
+) -> Vec<(f32, f32)> {
+    let mut positions = vec![(0.0, 0.0); sizes.len()];
+    if sizes.is_empty() {
+        return positions;
+    }
+
+    let (mut units, block_of) = build_blocks(sizes, edges, groups, style);
+    let block_count = units.len();
+    let dag = break_cycles(block_count, &vertical_edges(edges, &block_of));
+
+    let connected: Vec<bool> = (0..block_count)
+        .map(|b| dag.iter().any(|&(u, v)| u == b || v == b))
+        .collect();
+    let layer = assign_layers(block_count, &dag);
+
+    // Split long edges with dummies so every edge joins adjacent layers
+    let layer_count = (0..block_count)
+        .filter(|&b| connected[b])
+        .map(|b| layer[b] + 1)
+        .max()
+        .unwrap_or(0);
+    let mut layers: Vec<Vec<usize>> = vec![Vec::new(); layer_count];
+    for b in (0..block_count).filter(|&b| connected[b]) {
+        layers[layer[b]].push(b);
+    }
+    let mut up: Vec<Vec<usize>> = vec![Vec::new(); block_count];
+    let mut down: Vec<Vec<usize>> = vec![Vec::new(); block_count];
+    for &(u, v) in &dag {
+        let mut prev = u;
+        for layer in layers.iter_mut().take(layer[v]).skip(layer[u] + 1) {
+            let dummy = units.len();
+            units.push(Unit {
+                members: Vec::new(),
+                width: 0.0,
+                height: 0.0,
+            });
+            up.push(Vec::new());
+            down.push(Vec::new());
+            layer.push(dummy);
+            down[prev].push(dummy);
+            up[dummy].push(prev);
+            prev = dummy;
+        }
+        down[prev].push(v);
+        up[v].push(prev);
+    }
+
+    reduce_crossings(&mut layers, &up, &down);
+    let centres = brandes_kopf(&layers, &up, &down, &units, style);
+
+    let pins: Vec<Option<(f32, f32)>> = units
+        .iter()
+        .map(|unit| block_pin(unit, sizes, pinned, style))
+        .collect();
+    let (mut y, left, layered_width) = if pins.iter().any(Option::is_some) {
+        place_pinned_layers(
+            &layers,
+            &units,
+            &centres,
+            &pins,
+            sizes,
+            style,
+            &mut positions,
+        )
+    } else {
+        // Lay the layers out top to bottom; boxes hang from the top of their layer
+        let left = layers
+            .iter()
+            .flatten()
+            .map(|&u| centres[u] - units[u].width / 2.0)
+            .fold(f32::INFINITY, f32::min);
+        let mut y = 0.0;
+        let mut layered_width: f32 = 0.0;
+        for layer in &layers {
+            let mut height: f32 = 0.0;
+            for &u in layer {
+                let x = centres[u] - units[u].width / 2.0 - left;
+                place_block(&units[u], x, y, sizes, style, &mut positions);
+                layered_width = layered_width.max(x + units[u].width);
+                height = height.max(units[u].height);
+            }
+            y += height + style.spacing_y;
+        }
+        (y, 0.0, layered_width)
+    };
+
+    // Unrelated pinned blocks stay put, the others fill rows underneath
+    let isolated: Vec<usize> = (0..block_count)
+        .filter(|&b| !connected[b] && pins[b].is_none())
+        .collect();
+    for b in (0..block_count).filter(|&b| !connected[b]) {
+        if let Some((px, py)) = pins[b] {
+            place_block(&units[b], px, py, sizes, style, &mut positions);
+            y = y.max(py + units[b].height + style.spacing_y);
+        }
+    }
+    if !isolated.is_empty() {
+        let average = isolated.iter().map(|&b| units[b].width).sum::<f32>() / isolated.len() as f32;
+        let columns = (isolated.len() as f32).sqrt().ceil().max(4.0);
+        let wrap = layered_width.max(columns * (average + style.spacing_x));
+        let (mut x, mut row_height) = (0.0, 0.0f32);
+        for b in isolated {
+            let unit = &units[b];
+            if x > 0.0 && x + unit.width > wrap {
+                x = 0.0;
+                y += row_height + style.spacing_y;
+                row_height = 0.0;
+            }
+            place_block(unit, left + x, y, sizes, style, &mut positions);
+            x += unit.width + style.spacing_x;
+            row_height = row_height.max(unit.height);
+        }
+    }
+
+    // Other pinned members of a block may be pinned apart from it
+    for (position, pin) in positions.iter_mut().zip(pinned) {
+        if let Some(pin) = *pin {
+            *position = pin;
+        }
+    }
+    positions
+}
+
+/// Top-left corner that puts the first pinned box of a block at its pin
+fn block_pin(
+    unit: &Unit,
+    sizes: &[(f32, f32)],
+    pinned: &[Option<(f32, f32)>],
+    style: &DiagramStyle,
+) -> Option<(f32, f32)> {
+    let mut offset = 0.0;
+    for &m in &unit.members {
+        if let Some(Some((x, y))) = pinned.get(m) {
+            return Some((x - offset, *y));
+        }
+        offset += sizes[m].0 + style.spacing_x;
+    }
+    None
+}
+
+/// Lay the layers out around pinned blocks. Each free layer goes below the
+/// pinned blocks of the layers above it and above those of the layers below,
+/// and free blocks keep clear of the pinned ones in their own layer. Returns
+/// where rows below the layers start, and the left edge and width of the
+/// layers.
+fn place_pinned_layers(
+    layers: &[Vec<usize>],
+    units: &[Unit],
+    centres: &[f32],
+    pins: &[Option<(f32, f32)>],
+    sizes: &[(f32, f32)],
+    style: &DiagramStyle,
+    positions: &mut [(f32, f32)],
+) -> (f32, f32, f32) {
+    let mut layer_of = vec![0; units.len()];
+    let mut tops = Vec::with_capacity(layers.len());
+    let mut y = 0.0;
+    for (k, layer) in layers.iter().enumerate() {
+        tops.push(y);
+        let height = layer.iter().map(|&u| units[u].height).fold(0.0, f32::max);
+        y += height + style.spacing_y;
+        for &u in layer {
+            layer_of[u] = k;
+        }
+    }
+
+    // Move the plain layout so pinned blocks are, on average, where it put them
+    let fixed: Vec<(usize, (f32, f32))> = layers
+        .iter()
+        .flatten()
+        .filter_map(|&u| pins[u].map(|pin| (u, pin)))
+        .collect();
+    let count = fixed.len().max(1) as f32;
+    let shift_x = fixed
+        .iter()
+        .map(|&(u, (px, _))| px + units[u].width / 2.0 - centres[u])
+        .sum::<f32>()
+        / count;
+    let shift_y = fixed
+        .iter()
+        .map(|&(u, (_, py))| py - tops[layer_of[u]])
+        .sum::<f32>()
+        / count;
+    tops.iter_mut().for_each(|top| *top += shift_y);
+
+    let free_heights: Vec<f32> = layers
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|&&u| pins[u].is_none())
+                .map(|&u| units[u].height)
+                .fold(0.0, f32::max)
+        })
+        .collect();
+    let mut floor = f32::NEG_INFINITY;
+    for (k, layer) in layers.iter().enumerate() {
+        tops[k] = tops[k].max(floor);
+        floor = floor.max(tops[k] + free_heights[k] + style.spacing_y);
+        for &u in layer {
+            if let Some((_, py)) = pins[u] {
+                floor = floor.max(py + units[u].height + style.spacing_y);
+            }
+        }
+    }
+    let mut ceiling = f32::INFINITY;
+    for (k, layer) in layers.iter().enumerate().rev() {
+        tops[k] = tops[k].min(ceiling - free_heights[k] - style.spacing_y);
+        ceiling = ceiling.min(tops[k]);
+        for &u in layer {
+            if let Some((_, py)) = pins[u] {
+                ceiling = ceiling.min(py);
+            }
+        }
+    }
+
+    let gap = |a: usize, b: usize| {
+        if units[a].is_dummy() || units[b].is_dummy() {
+            style.spacing_x / 2.0
+        } else {
+            style.spacing_x
+        }
+    };
+    let (mut left, mut right, mut bottom) = (f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for (k, layer) in layers.iter().enumerate() {
+        let mut xs: Vec<(usize, f32)> = layer
+            .iter()
+            .map(|&u| match pins[u] {
+                Some((px, _)) => (u, px),
+                None => (u, centres[u] + shift_x - units[u].width / 2.0),
+            })
+            .collect();
+        xs.sort_by(|a, b| a.1.total_cmp(&b.1));
+        // Free blocks give way to the right of what precedes them, then to
+        // the left of what follows them
+        for i in 1..xs.len() {
+            let ((p, at), u) = (xs[i - 1], xs[i].0);
+            if pins[u].is_none() {
+                xs[i].1 = xs[i].1.max(at + units[p].width + gap(p, u));
+            }
+        }
+        for i in (1..xs.len()).rev() {
+            let ((n, at), u) = (xs[i], xs[i - 1].0);
+            if pins[u].is_none() {
+                xs[i - 1].1 = xs[i - 1].1.min(at - gap(u, n) - units[u].width);
+            }
+        }
+        for (u, x) in xs {
+            let y = pins[u].map_or(tops[k], |(_, py)| py);
+            place_block(&units[u], x, y, sizes, style, positions);
+            if !units[u].is_dummy() {
+                left = left.min(x);
+                right = right.max(x + units[u].width);
+                bottom = bottom.max(y + units[u].height);
+            }
+        }
+    }
+    if left > right {
+        return (0.0, 0.0, 0.0);
+    }
+    (bottom + style.spacing_y, left, right - left)
+}
+
+/// Set the boxes of a block side by side from its top-left corner
+fn place_block(
+    unit: &Unit,
+    x: f32,
+    y: f32,
+    sizes: &[(f32, f32)],
+    style: &DiagramStyle,
+    positions: &mut [(f32, f32)],
+) {
+    let mut x = x;
+    for &m in &unit.members {
+        positions[m] = (x, y);
+        x += sizes[m].0 + style.spacing_x;
+    }
+}
+
+/// Merge boxes that must share a layer into blocks, ordered left to right by
+/// their hints. Returns the blocks and the block of every box.
+fn build_blocks(
+    sizes: &[(f32, f32)],
+    edges: &[LayoutEdge],
+    groups: &[Vec<usize>],
+    style: &DiagramStyle,
+) -> (Vec<Unit>, Vec<usize>) {
+    let n = sizes.len();
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn find(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+    let mut union = |a: usize, b: usize| {
+        let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+        if ra != rb {
+            parent[ra.max(rb)] = ra.min(rb);
+        }
+    };
+    for edge in edges {
+        if matches!(edge.direction, Some(Direction::Left | Direction::Right)) {
+            union(edge.from, edge.to);
+        }
+    }
+    for group in groups {
+        for pair in group.windows(2) {
+            union(pair[0], pair[1]);
+        }
+    }
+
+    let mut units = Vec::new();
+    let mut block_of = vec![0; n];
+    let mut block_of_root = vec![usize::MAX; n];
+    for node in 0..n {
+        let root = find(&mut parent, node);
+        if block_of_root[root] == usize::MAX {
+            block_of_root[root] = units.len();
+            units.push(Unit {
+                members: Vec::new(),
+                width: 0.0,
+                height: 0.0,
+            });
+        }
+        block_of[node] = block_of_root[root];
+        units[block_of[node]].members.push(node);
+    }
+
+    for unit in &mut units {
+        order_block(&mut unit.members, edges, groups);
+        unit.width = unit.members.iter().map(|&m| sizes[m].0).sum::<f32>()
+            + style.spacing_x * (unit.members.len() - 1) as f32;
+        unit.height = unit.members.iter().map(|&m| sizes[m].1).fold(0.0, f32::max);
+    }
+    (units, block_of)
+}
+
+/// Order the boxes of one block: `left`/`right` hints first, then each
+/// together-group gathered at the place of its first member
+fn order_block(members: &mut Vec<usize>, edges: &[LayoutEdge], groups: &[Vec<usize>]) {
+    // `a` before `b` pairs, sorted topologically, earliest member first
+    let before: Vec<(usize, usize)> = edges
+        .iter()
+        .filter(|e| members.contains(&e.from) && members.contains(&e.to))
+        .filter_map(|e| match e.direction {
+            Some(Direction::Right) => Some((e.from, e.to)),
+            Some(Direction::Left) => Some((e.to, e.from)),
+            _ => None,
+        })
+        .collect();
+    let mut remaining = std::mem::take(members);
+    while !remaining.is_empty() {
+        let free = remaining
+            .iter()
+            .position(|&m| {
+                !before
+                    .iter()
+                    .any(|&(a, b)| b == m && remaining.contains(&a))
+            })
+            // Contradictory hints: keep the current order
+            .unwrap_or(0);
+        members.push(remaining.remove(free));
+    }
+
+    for group in groups {
+        let Some(first) = members.iter().position(|m| group.contains(m)) else {
+            continue;
+        };
+        let (inside, rest): (Vec<usize>, Vec<usize>) =
+            members[first..].iter().partition(|m| group.contains(m));
+        members.truncate(first);
+        members.extend(inside);
+        members.extend(rest);
+    }
+}
+
+/// Edges between blocks pointing downwards, hierarchy edges first. Parents
+/// sit above their subclasses, wholes above their parts, and `up`/`down`
+/// hints say which end goes on top.
+pub(crate) fn vertical_edges(
+    edges: &[LayoutEdge],
+    block_of: &[usize],
+) -> Vec<(usize, usize, bool)> {
+    let mut result: Vec<(usize, usize, bool)> = Vec::new();
+    for edge in edges {
+        let (from, to) = (block_of[edge.from], block_of[edge.to]);
+        if from == to {
+            continue;
+        }
+        let (upper, lower, strong) = match (edge.direction, edge.rel_type) {
+            (Some(Direction::Down), _) => (from, to, true),
+            (Some(Direction::Up), _) => (to, from, true),
+            (Some(_), _) => continue,
+            (None, RelationType::Inheritance | RelationType::Realization) => (to, from, true),
+            (
+                None,
+                RelationType::Composition | RelationType::Aggregation | RelationType::Nested,
+            ) => (from, to, true),
+            (None, _) => (from, to, false),
+        };
+        if !result
+            .iter()
+            .any(|&(u, l, _)| (u, l) == (upper, lower) || (u, l) == (lower, upper))
+        {
+            result.push((upper, lower, strong));
+        }
+    }
+    result.sort_by_key(|&(_, _, strong)| !strong);
+    result
+}
+
+/// Accept edges one by one, reversing any that would close a cycle. Strong
+/// (hierarchy) edges go first so weaker ones give way.
+pub(crate) fn break_cycles(count: usize, edges: &[(usize, usize, bool)]) -> Vec<(usize, usize)> {
+    let mut out: Vec<Vec<usize>> = vec![Vec::new(); count];
+    let mut dag = Vec::new();
+    for &(u, v, _) in edges {
+        let edge = if reaches(&out, v, u) { (v, u) } else { (u, v) };
+        out[edge.0].push(edge.1);
+        dag.push(edge);
+    }
+    dag
+}
+
+fn reaches(out: &[Vec<usize>], from: usize, to: usize) -> bool {
+    let mut seen = vec![false; out.len()];
+    let mut stack = vec![from];
+    while let Some(node) = stack.pop() {
+        if node == to {
+            return true;
+        }
+        if !std::mem::replace(&mut seen[node], true) {
+            stack.extend(&out[node]);
+        }
+    }
+    false
+}
+
+/// Longest-path layering in topological order, then sources moved down to
+/// sit just above their highest child
+pub(crate) fn assign_layers(count: usize, dag: &[(usize, usize)]) -> Vec<usize> {
+    let mut indegree = vec![0; count];
+    for &(_, v) in dag {
+        indegree[v] += 1;
+    }
+    let mut remaining = indegree.clone();
+    let mut ready: Vec<usize> = (0..count).filter(|&n| indegree[n] == 0).collect();
+    let mut topo = Vec::with_capacity(count);
+    let mut layer = vec![0; count];
+    while !ready.is_empty() {
+        // Smallest index first keeps the result independent of edge order
+        let next = ready.remove(0);
+        topo.push(next);
+        for &(u, v) in dag.iter().filter(|&&(u, _)| u == next) {
+            layer[v] = layer[v].max(layer[u] + 1);
+            remaining[v] -= 1;
+            if remaining[v] == 0 {
+                let at = ready.partition_point(|&r| r < v);
+                ready.insert(at, v);
+            }
+        }
+    }
+
+    for &node in topo.iter().rev() {
+        if indegree[node] > 0 {
+            continue;
+        }
+        let lowest = dag
+            .iter()
+            .filter(|&&(u, _)| u == node)
+            .map(|&(_, v)| layer[v])
+            .min();
+        if let Some(child) = lowest {
+            layer[node] = child - 1;
+        }
+    }
+    layer
+}
+
+/// Reorder layers with alternating barycenter sweeps, keeping the ordering
+/// with the fewest crossings
+fn reduce_crossings(layers: &mut [Vec<usize>], up: &[Vec<usize>], down: &[Vec<usize>]) {
+    let mut best = layers.to_vec();
+    let mut best_crossings = count_crossings(layers, down);
+    for sweep in 0..SWEEPS {
+        if best_crossings == 0 {
+            break;
+        }
+        if sweep % 2 == 0 {
+            for i in 1..layers.len() {
+                let (above, rest) = layers.split_at_mut(i);
+                sort_by_barycenter(&mut rest[0], &above[i - 1], up);
+            }
+        } else {
+            for i in (0..layers.len().saturating_sub(1)).rev() {
+                let (rest, below) = layers.split_at_mut(i + 1);
+                sort_by_barycenter(&mut rest[i], &below[0], down);
+            }
+        }
+        let crossings = count_crossings(layers, down);
+        if crossings < best_crossings {
+            best_crossings = crossings;
+            best = layers.to_vec();
+        }
+    }
+    layers.clone_from_slice(&best);
+}
+
+/// Sort a layer by the mean position of each unit's neighbours in the fixed
+/// layer; units without neighbours there keep their place
+fn sort_by_barycenter(layer: &mut [usize], fixed: &[usize], neighbours: &[Vec<usize>]) {
+    let position = |n: usize| fixed.iter().position(|&f| f == n);
+    let mut keyed: Vec<(f32, usize)> = layer
+        .iter()
+        .enumerate()
+        .map(|(i, &u)| {
+            let positions: Vec<usize> = neighbours[u].iter().filter_map(|&n| position(n)).collect();
+            let key = if positions.is_empty() {
+                i as f32
+            } else {
+                positions.iter().sum::<usize>() as f32 / positions.len() as f32
+            };
+            (key, u)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (slot, (_, u)) in layer.iter_mut().zip(keyed) {
+        *slot = u;
+    }
+}
+
+/// Number of edge crossings between all pairs of adjacent layers
+fn count_crossings(layers: &[Vec<usize>], down: &[Vec<usize>]) -> usize {
+    let mut total = 0;
+    for pair in layers.windows(2) {
+        let below = |n: usize| pair[1].iter().position(|&b| b == n);
+        let mut ends: Vec<(usize, usize)> = Vec::new();
+        for (i, &u) in pair[0].iter().enumerate() {
+            ends.extend(down[u].iter().filter_map(|&v| below(v)).map(|j| (i, j)));
+        }
+        for (a, &(i1, j1)) in ends.iter().enumerate() {
+            total += ends[a + 1..]
+                .iter()
+                .filter(|&&(i2, j2)| (i1 < i2 && j1 > j2) || (i1 > i2 && j1 < j2))
+                .count();
+        }
+    }
+    total
+}
+
+/// Horizontal centre of every unit by Brandes–Köpf: four alignments (up/down
+/// by left/right), each compacted, then balanced by taking the average median.
+fn brandes_kopf(
+    layers: &[Vec<usize>],
+    up: &[Vec<usize>],
+    down: &[Vec<usize>],
+    units: &[Unit],
+    style: &DiagramStyle,
+) -> Vec<f32> {
+    let count = units.len();
+    let conflicts = type1_conflicts(layers, up, units);
+
+    let mut variants: Vec<(Vec<f32>, bool)> = Vec::new();
+    for top_down in [true, false] {
+        for left_to_right in [true, false] {
+            let mut order: Vec<Vec<usize>> = layers.to_vec();
+            if !top_down {
+                order.reverse();
+            }
+            if !left_to_right {
+                order.iter_mut().for_each(|layer| layer.reverse());
+            }
+            let preds = if top_down { up } else { down };
+            let mut x = align_and_compact(&order, preds, &conflicts, units, style);
+            if !left_to_right {
+                x.iter_mut().for_each(|x| *x = -*x);
+            }
+            variants.push((x, left_to_right));
+        }
+    }
+
+    // Align every variant to the narrowest one: left variants by their left
+    // edge, right variants by their right edge
+    let extent = |x: &[f32]| {
+        let placed = layers.iter().flatten();
+        let min = placed.clone().map(|&u| x[u]).fold(f32::INFINITY, f32::min);
+        let max = placed.map(|&u| x[u]).fold(f32::NEG_INFINITY, f32::max);
+        (min, max)
+    };
+    let Some((narrowest, _)) = variants.iter().min_by(|a, b| {
+        let (a0, a1) = extent(&a.0);
+        let (b0, b1) = extent(&b.0);
+        (a1 - a0).total_cmp(&(b1 - b0))
+    }) else {
+        return vec![0.0; count];
+    };
+    let (target_min, target_max) = extent(narrowest);
+    for (x, left_to_right) in &mut variants {
+        let (min, max) = extent(x);
+        let shift = if *left_to_right {
+            target_min - min
+        } else {
+            target_max - max
+        };
+        x.iter_mut().for_each(|x| *x += shift);
+    }
+
+    (0..count)
+        .map(|u| {
+            let mut xs: Vec<f32> = variants.iter().map(|(x, _)| x[u]).collect();
+            xs.sort_by(f32::total_cmp);
+            (xs[1] + xs[2]) / 2.0
+        })
+        .collect()
+}
+
+/// Mark edges that cross an inner segment (between two dummies), so the
+/// alignment keeps long edges straight instead
+fn type1_conflicts(
+    layers: &[Vec<usize>],
+    up: &[Vec<usize>],
+    units: &[Unit],
+) -> HashSet<(usize, usize)> {
+    let mut marked = HashSet::new();
+    for pair in layers.windows(2) {
+        let (upper, lower) = (&pair[0], &pair[1]);
+        if upper.is_empty() {
+            continue;
+        }
+        let position = |n: usize| upper.iter().position(|&u| u == n).unwrap_or(0);
+        let (mut k0, mut l) = (0, 0);
+        for (l1, &v) in lower.iter().enumerate() {
+            let inner = if units[v].is_dummy() {
+                up[v].iter().copied().find(|&u| units[u].is_dummy())
+            } else {
+                None
+            };
+            if l1 + 1 == lower.len() || inner.is_some() {
+                let k1 = inner.map_or(upper.len() - 1, position);
+                while l <= l1 {
+                    let w = lower[l];
+                    for &u in &up[w] {
+                        let k = position(u);
+                        if k < k0 || k > k1 {
+                            marked.insert((u, w));
+                        }
+                    }
+                    l += 1;
+                }
+                k0 = k1;
+            }
+        }
+    }
+    marked
+}
+
+/// One Brandes–Köpf pass over layers given in processing order: align each
+/// unit with a median predecessor, then place the aligned blocks as far to
+/// the left as their separation allows. Returns centre coordinates.
+fn align_and_compact(
+    order: &[Vec<usize>],
+    preds: &[Vec<usize>],
+    conflicts: &HashSet<(usize, usize)>,
+    units: &[Unit],
+    style: &DiagramStyle,
+) -> Vec<f32> {
+    let count = units.len();
+    let mut pos = vec![0; count];
+    for layer in order {
+        for (i, &u) in layer.iter().enumerate() {
+            pos[u] = i;
+        }
+    }
+    let marked = |a: usize, b: usize| conflicts.contains(&(a, b)) || conflicts.contains(&(b, a));
+
+    let mut root: Vec<usize> = (0..count).collect();
+    let mut align: Vec<usize> = (0..count).collect();
+    for layer in order.iter().skip(1) {
+        let mut r: isize = -1;
+        for &v in layer {
+            let mut ups = preds[v].clone();
+            if ups.is_empty() {
+                continue;
+            }
+            ups.sort_by_key(|&u| pos[u]);
+            let d = ups.len();
+            let mut medians = vec![(d - 1) / 2];
+            // `usize::is_multiple_of` would need Rust 1.87
+            #[allow(clippy::manual_is_multiple_of)]
+            if d % 2 == 0 {
+                medians.push(d / 2);
+            }
+            for m in medians {
+                let u = ups[m];
+                if align[v] == v && !marked(u, v) && r < pos[u] as isize {
+                    align[u] = v;
+                    root[v] = root[u];
+                    align[v] = root[v];
+                    r = pos[u] as isize;
+                }
+            }
+        }
+    }
+
+    // Left neighbour of every unit in its layer
+    let mut left = vec![None; count];
+    for layer in order {
+        for pair in layer.windows(2) {
+            left[pair[1]] = Some(pair[0]);
+        }
+    }
+    let separation = |a: usize, b: usize| {
+        let gap = if units[a].is_dummy() || units[b].is_dummy() {
+            style.spacing_x / 2.0
+        } else {
+            style.spacing_x
+        };
+        (units[a].width + units[b].width) / 2.0 + gap
+    };
+
+    let mut x: Vec<Option<f32>> = vec![None; count];
+    fn place(
+        v: usize,
+        x: &mut Vec<Option<f32>>,
+        root: &[usize],
+        align: &[usize],
+        left: &[Option<usize>],
+        separation: &dyn Fn(usize, usize) -> f32,
+    ) {
+        if x[v].is_some() {
+            return;
+        }
+        let mut at: f32 = 0.0;
+        let mut w = v;
+        loop {
+            if let Some(p) = left[w] {
+                let u = root[p];
+                place(u, x, root, align, left, separation);
+                at = at.max(x[u].unwrap_or(0.0) + separation(p, w));
+            }
+            w = align[w];
+            if w == v {
+                break;
+            }
+        }
+        x[v] = Some(at);
+    }
+    for layer in order {
+        for &v in layer {
+            if root[v] == v {
+                place(v, &mut x, &root, &align, &left, &separation);
+            }
+        }
+    }
+    (0..count).map(|v| x[root[v]].unwrap_or(0.0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(from: usize, to: usize, rel_type: RelationType) -> LayoutEdge {
+        LayoutEdge {
+            from,
+            to,
+            rel_type,
+            direction: None,
+        }
+    }
+
+    #[test]
+    fn test_cycles_are_broken() {
+        let edges = [(0, 1, true), (1, 2, true), (2, 0, false)];
+        let dag = break_cycles(3, &edges);
+        assert_eq!(dag, [(0, 1), (1, 2), (0, 2)]);
+        assert_eq!(assign_layers(3, &dag), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_sources_sit_above_their_children() {
+        // 0 -> 1 -> 2 -> 3, and 4 only points at 3
+        let dag = [(0, 1), (1, 2), (2, 3), (4, 3)];
+        assert_eq!(assign_layers(5, &dag), [0, 1, 2, 3, 2]);
+    }
+
+    #[test]
+    fn test_barycenter_removes_crossings() {
+        // Two parents whose children start out in swapped order
+        let mut layers = vec![vec![0, 1], vec![3, 2]];
+        let up = vec![vec![], vec![], vec![0], vec![1]];
+        let down = vec![vec![2], vec![3], vec![], vec![]];
+        assert_eq!(count_crossings(&layers, &down), 1);
+        reduce_crossings(&mut layers, &up, &down);
+        assert_eq!(count_crossings(&layers, &down), 0);
+    }
+
+    #[test]
+    fn test_parent_centred_over_children() {
+        let style = DiagramStyle::default();
+        let sizes = [(100.0, 50.0); 4];
+        let edges = [
+            edge(1, 0, RelationType::Inheritance),
+            edge(2, 0, RelationType::Inheritance),
+            edge(3, 0, RelationType::Inheritance),
+        ];
+        let positions = layered_layout(&sizes, &edges, &[], &[], &style);
+        let centre = |i: usize| positions[i].0 + sizes[i].0 / 2.0;
+        assert!(positions[0].1 < positions[1].1);
+        assert_eq!(centre(0), centre(2));
+        assert!(centre(1) < centre(2) && centre(2) < centre(3));
+        for pair in [(1, 2), (2, 3)] {
+            assert!(positions[pair.1].0 >= positions[pair.0].0 + 100.0 + style.spacing_x);
+        }
+    }
+
+    #[test]
+    fn test_long_edges_get_dummies_that_do_not_overlap() {
+        let style = DiagramStyle::default();
+        let sizes = [(80.0, 40.0); 5];
+        // 0 -> 1 -> 2 -> 3 and a long edge 0 -> 3 beside a box 4 under 0
+        let edges = [
+            edge(0, 1, RelationType::Composition),
+            edge(1, 2, RelationType::Composition),
+            edge(2, 3, RelationType::Composition),
+            edge(0, 3, RelationType::Composition),
+            edge(0, 4, RelationType::Composition),
+        ];
+        let positions = layered_layout(&sizes, &edges, &[], &[], &style);
+        for a in 0..5 {
+            for b in a + 1..5 {
+                let (pa, pb) = (positions[a], positions[b]);
+                let apart = pa.0 + 80.0 <= pb.0 || pb.0 + 80.0 <= pa.0 || pa.1 != pb.1;
+                assert!(apart, "{} and {} overlap", a, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! Pluggable placement of class diagram boxes.
+//!
+//! A [`LayoutEngine`] gets the measured box sizes, the relationships
+//! between them and any pinned positions, and returns a top-left corner for
+//! every box. The diagram takes care of everything around it: left-to-right
+//! diagrams are handed to the engine transposed, and results are moved to
+//! start at the origin. Engines that ignore the pins get pinned boxes put in
+//! place afterwards, with the free ones fitted around them. An engine
+//! therefore only has to solve the plain top-to-bottom case.
+//!
+//! Three engines ship with the crate:
+//!
+//! - [`LayeredLayout`]: hierarchy in layers, parents above their children
+//! - [`ForceLayout`]: springs along relationships, boxes repelling each other
+//! - [`GridLayout`]: rows and columns, parents first
+
+use crate::class_diagram::{Direction, RelationType};
+use crate::common::DiagramStyle;
+use crate::layered_layout::{assign_layers, break_cycles, layered_layout, vertical_edges};
+use std::f32::consts::TAU;
+
+/// A relationship as the layout sees it, indexing into the box list
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutEdge {
+    pub from: usize,
+    pub to: usize,
+    pub rel_type: RelationType,
+    /// Where `to` goes relative to `from` (`-up->`)
+    pub direction: Option<Direction>,
+}
+
+/// What an engine lays out: one container's boxes and their relationships
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutGraph<'a> {
+    /// Width and height of every box
+    pub sizes: &'a [(f32, f32)],
+    /// Relationships between boxes, by index into `sizes`
+    pub edges: &'a [LayoutEdge],
+    /// Sets of boxes to keep next to each other (`together { A B }`)
+    pub groups: &'a [Vec<usize>],
+    /// Fixed top-left corners, by index into `sizes`; `None` or missing
+    /// entries are free
+    pub pinned: &'a [Option<(f32, f32)>],
+}
+
+/// An algorithm placing class diagram boxes.
+///
+/// `place` returns the top-left corner of every box in `graph.sizes`, in the
+/// same order. Without pins only relative positions matter; missing entries
+/// are put at the origin. An engine may honour `graph.pinned` by returning
+/// pinned boxes at their pins, in the pins' coordinates.
+pub trait LayoutEngine {
+    fn place(&self, graph: &LayoutGraph<'_>, style: &DiagramStyle) -> Vec<(f32, f32)>;
+}
+
+/// Sugiyama-style layers with crossing reduction; the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LayeredLayout;
+
+impl LayoutEngine for LayeredLayout {
+    fn place(&self, graph: &LayoutGraph<'_>, style: &DiagramStyle) -> Vec<(f32, f32)> {
+        layered_layout(graph.sizes, graph.edges, graph.groups, graph.pinned, style)
+    }
+}
+
+/// Force-directed placement: related boxes attract, all boxes repel, and
+/// hierarchy edges pull children under their parents. Starts from a circle
+/// in declaration order, so the result is repeatable.
+#[derive(Debug, Clone, Copy)]
+pub struct ForceLayout {
+    /// Simulation steps before overlaps are pushed apart
+    pub iterations: usize,
+}
+
+impl Default for ForceLayout {
+    fn default() -> Self {
+        Self { iterations: 300 }
+    }
+}
+
+impl LayoutEngine for ForceLayout {
+    fn place(&self, graph: &LayoutGraph<'_>, style: &DiagramStyle) -> Vec<(f32, f32)> {
+        let sizes = graph.sizes;
+        let n = sizes.len();
+        if n == 0 {
+            return Vec::new();
+        }
+        // Ideal distance between the centres of related boxes
+        let average = sizes.iter().map(|&(w, h)| w.max(h)).sum::<f32>() / n as f32;
+        let ideal = average + style.spacing_x.max(style.spacing_y);
+
+        let radius = ideal * n as f32 / TAU;
+        let mut centres: Vec<(f32, f32)> = (0..n)
+            .map(|i| {
+                let angle = TAU * i as f32 / n as f32;
+                (radius * angle.cos(), radius * angle.sin())
+            })
+            .collect();
+        let mut springs: Vec<(usize, usize)> = graph
+            .edges
+            .iter()
+            .filter(|e| e.from != e.to && e.from < n && e.to < n)
+            .map(|e| (e.from, e.to))
+            .collect();
+        for group in graph.groups {
+            springs.extend(group.windows(2).map(|pair| (pair[0], pair[1])));
+        }
+        let identity: Vec<usize> = (0..n).collect();
+        let strong: Vec<(usize, usize, bool)> = vertical_edges(graph.edges, &identity)
+            .into_iter()
+            .filter(|&(_, _, strong)| strong)
+            .collect();
+        let tiers = break_cycles(n, &strong);
+
+        for step in 0..self.iterations {
+            let mut push = vec![(0.0f32, 0.0f32); n];
+            for i in 0..n {
+                for j in i + 1..n {
+                    let (dx, dy) = (centres[i].0 - centres[j].0, centres[i].1 - centres[j].1);
+                    let distance = (dx * dx + dy * dy).sqrt().max(1.0);
+                    let force = ideal * ideal / distance / distance;
+                    push[i].0 += dx * force;
+                    push[i].1 += dy * force;
+                    push[j].0 -= dx * force;
+                    push[j].1 -= dy * force;
+                }
+            }
+            for &(a, b) in &springs {
+                let (dx, dy) = (centres[b].0 - centres[a].0, centres[b].1 - centres[a].1);
+                let force = (dx * dx + dy * dy).sqrt() / ideal;
+                push[a].0 += dx * force;
+                push[a].1 += dy * force;
+                push[b].0 -= dx * force;
+                push[b].1 -= dy * force;
+            }
+            for &(upper, lower) in &tiers {
+                let short = ideal - (centres[lower].1 - centres[upper].1);
+                if short > 0.0 {
+                    push[upper].1 -= short;
+                    push[lower].1 += short;
+                }
+            }
+            // Cool down linearly so the last steps only fine-tune
+            let limit = ideal * (1.0 - step as f32 / self.iterations as f32);
+            for (centre, (px, py)) in centres.iter_mut().zip(push) {
+                let length = (px * px + py * py).sqrt();
+                if length > 0.0 {
+                    let scale = length.min(limit) / length;
+                    centre.0 += px * scale;
+                    centre.1 += py * scale;
+                }
+            }
+        }
+
+        // Settle hierarchy edges the forces left level or upside down; the
+        // edges form a DAG, so one pass per box is enough
+        for _ in 0..n {
+            for &(upper, lower) in &tiers {
+                let below = centres[upper].1 + (sizes[upper].1 + sizes[lower].1) / 2.0;
+                centres[lower].1 = centres[lower].1.max(below + style.spacing_y);
+            }
+        }
+
+        let mut positions: Vec<(f32, f32)> = centres
+            .iter()
+            .zip(sizes)
+            .map(|(&(cx, cy), &(w, h))| (cx - w / 2.0, cy - h / 2.0))
+            .collect();
+        separate(&mut positions, sizes, style);
+        // Line boxes up on a raster: tidier, and far fewer distinct grid
+        // lines for the edge router. Rounding moves a box by at most half a
+        // step, so boxes stay at least half the spacing apart.
+        let raster = RASTER.min(style.spacing_x.min(style.spacing_y) / 2.0);
+        if raster >= 1.0 {
+            for p in &mut positions {
+                *p = (
+                    (p.0 / raster).round() * raster,
+                    (p.1 / raster).round() * raster,
+                );
+            }
+        }
+        positions
+    }
+}
+
+/// Step that force-directed positions are rounded to, at most
+const RASTER: f32 = 20.0;
+
+/// Push overlapping boxes apart along the axis where they overlap least,
+/// until every pair keeps the style's spacing
+fn separate(positions: &mut [(f32, f32)], sizes: &[(f32, f32)], style: &DiagramStyle) {
+    let n = positions.len();
+    for _ in 0..n.max(1) * 8 {
+        let mut moved = false;
+        for i in 0..n {
+            for j in i + 1..n {
+                let centre = |k: usize| {
+                    (
+                        positions[k].0 + sizes[k].0 / 2.0,
+                        positions[k].1 + sizes[k].1 / 2.0,
+                    )
+                };
+                let (a, b) = (centre(i), centre(j));
+                let over_x = (sizes[i].0 + sizes[j].0) / 2.0 + style.spacing_x - (a.0 - b.0).abs();
+                let over_y = (sizes[i].1 + sizes[j].1) / 2.0 + style.spacing_y - (a.1 - b.1).abs();
+                if over_x <= 0.0 || over_y <= 0.0 {
+                    continue;
+                }
+                moved = true;
+                if over_x / (sizes[i].0 + sizes[j].0) < over_y / (sizes[i].1 + sizes[j].1) {
+                    let sign = if a.0 <= b.0 { 1.0 } else { -1.0 };
+                    positions[i].0 -= sign * over_x / 2.0;
+                    positions[j].0 += sign * over_x / 2.0;
+                } else {
+                    let sign = if a.1 <= b.1 { 1.0 } else { -1.0 };
+                    positions[i].1 -= sign * over_y / 2.0;
+                    positions[j].1 += sign * over_y / 2.0;
+                }
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+}
+
+/// Rows and columns filled in hierarchy order, declaration order within a
+/// level. Every level starts a new row, so parents sit above their children.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GridLayout {
+    /// Boxes per row; about the square root of the box count when `None`
+    pub columns: Option<usize>,
+}
+
+impl LayoutEngine for GridLayout {
+    fn place(&self, graph: &LayoutGraph<'_>, style: &DiagramStyle) -> Vec<(f32, f32)> {
+        let sizes = graph.sizes;
+        let n = sizes.len();
+        if n == 0 {
+            return Vec::new();
+        }
+        let identity: Vec<usize> = (0..n).collect();
+        let dag = break_cycles(n, &vertical_edges(graph.edges, &identity));
+        let layer = assign_layers(n, &dag);
+        let mut order = identity;
+        order.sort_by_key(|&i| layer[i]);
+
+        let columns = self
+            .columns
+            .unwrap_or_else(|| (n as f32).sqrt().ceil() as usize)
+            .clamp(1, n);
+        let mut cells = Vec::with_capacity(n);
+        let (mut row, mut column) = (0, 0);
+        for (k, &i) in order.iter().enumerate() {
+            let new_level = k > 0 && layer[i] != layer[order[k - 1]];
+            if k > 0 && (column == columns || new_level) {
+                row += 1;
+                column = 0;
+            }
+            cells.push((i, column, row));
+            column += 1;
+        }
+        let mut widths = vec![0.0f32; columns];
+        let mut heights = vec![0.0f32; row + 1];
+        for &(i, column, row) in &cells {
+            widths[column] = widths[column].max(sizes[i].0);
+            heights[row] = heights[row].max(sizes[i].1);
+        }
+        let offsets = |lengths: &[f32], gap: f32| {
+            let mut at = 0.0;
+            lengths
+                .iter()
+                .map(|&length| {
+                    let start = at;
+                    at += length + gap;
+                    start
+                })
+                .collect::<Vec<f32>>()
+        };
+        let (xs, ys) = (
+            offsets(&widths, style.spacing_x),
+            offsets(&heights, style.spacing_y),
+        );
+
+        let mut positions = vec![(0.0, 0.0); n];
+        for (i, column, row) in cells {
+            // Centred in its column, hanging from the top of its row
+            positions[i] = (xs[column] + (widths[column] - sizes[i].0) / 2.0, ys[row]);
+        }
+        positions
+    }
+}
+
+/// Run `engine` on `graph` and apply the pins: without any, results are
+/// moved to start at the origin; otherwise pinned boxes are put in place with
+/// the rest fitted around them, unless the engine already did so
+pub(crate) fn arrange(
+    engine: &dyn LayoutEngine,
+    graph: &LayoutGraph<'_>,
+    style: &DiagramStyle,
+) -> Vec<(f32, f32)> {
+    let n = graph.sizes.len();
+    let mut positions = engine.place(graph, style);
+    positions.resize(n, (0.0, 0.0));
+    for p in &mut positions {
+        if !p.0.is_finite() || !p.1.is_finite() {
+            *p = (0.0, 0.0);
+        }
+    }
+    if graph.pinned.iter().take(n).any(Option::is_some) {
+        settle_pins(&mut positions, graph.sizes, graph.pinned, style);
+        return positions;
+    }
+    let left = positions.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+    let top = positions.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    if n > 0 {
+        for p in &mut positions {
+            *p = (p.0 - left, p.1 - top);
+        }
+    }
+    positions
+}
+
+/// Put pinned boxes at their pins and fit the free ones around them
+fn settle_pins(
+    positions: &mut [(f32, f32)],
+    sizes: &[(f32, f32)],
+    pinned: &[Option<(f32, f32)>],
+    style: &DiagramStyle,
+) {
+    let pins: Vec<(usize, (f32, f32))> = pinned
+        .iter()
+        .enumerate()
+        .filter_map(|(i, pin)| pin.map(|p| (i, p)))
+        .filter(|&(i, _)| i < positions.len())
+        .collect();
+    if pins.is_empty() {
+        return;
+    }
+
+    // Shift the free layout so the pinned boxes are, on average, where the
+    // layout itself put them
+    let count = pins.len() as f32;
+    let shift_x = pins.iter().map(|&(i, p)| p.0 - positions[i].0).sum::<f32>() / count;
+    let shift_y = pins.iter().map(|&(i, p)| p.1 - positions[i].1).sum::<f32>() / count;
+
+    let mut settled: Vec<usize> = Vec::with_capacity(positions.len());
+    for &(i, pin) in &pins {
+        positions[i] = pin;
+        settled.push(i);
+    }
+    let overlaps = |(x, y): (f32, f32), (w, h): (f32, f32), other: (f32, f32), size: (f32, f32)| {
+        x < other.0 + size.0 + style.spacing_x
+            && other.0 < x + w + style.spacing_x
+            && y < other.1 + size.1 + style.spacing_y
+            && other.1 < y + h + style.spacing_y
+    };
+    for i in 0..positions.len() {
+        if pinned.get(i).copied().flatten().is_some() {
+            continue;
+        }
+        let mut at = (positions[i].0 + shift_x, positions[i].1 + shift_y);
+        // Every step moves below the box in the way, so this ends
+        while let Some(&j) = settled
+            .iter()
+            .find(|&&j| overlaps(at, sizes[i], positions[j], sizes[j]))
+        {
+            at.1 = positions[j].1 + sizes[j].1 + style.spacing_y;
+        }
+        positions[i] = at;
+        settled.push(i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(from: usize, to: usize, rel_type: RelationType) -> LayoutEdge {
+        LayoutEdge {
+            from,
+            to,
+            rel_type,
+            direction: None,
+        }
+    }
+
+    fn family() -> ([(f32, f32); 4], [LayoutEdge; 3]) {
+        (
+            [(100.0, 50.0); 4],
+            [
+                edge(1, 0, RelationType::Inheritance),
+                edge(2, 0, RelationType::Inheritance),
+                edge(3, 0, RelationType::Inheritance),
+            ],
+        )
+    }
+
+    fn assert_apart(positions: &[(f32, f32)], sizes: &[(f32, f32)]) {
+        for a in 0..positions.len() {
+            for b in a + 1..positions.len() {
+                let (pa, pb) = (positions[a], positions[b]);
+                let apart = pa.0 + sizes[a].0 <= pb.0
+                    || pb.0 + sizes[b].0 <= pa.0
+                    || pa.1 + sizes[a].1 <= pb.1
+                    || pb.1 + sizes[b].1 <= pa.1;
+                assert!(apart, "{} and {} overlap", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pinned_boxes_stay_put() {
+        let style = DiagramStyle::default();
+        let (sizes, edges) = family();
+        let graph = LayoutGraph {
+            sizes: &sizes,
+            edges: &edges,
+            groups: &[],
+            pinned: &[],
+        };
+        let free = arrange(&LayeredLayout, &graph, &style);
+        // Pin the parent where its first child would go
+        let pinned = [Some(free[1]), None, None, Some((500.0, 0.0))];
+        let engines: [&dyn LayoutEngine; 3] = [
+            &LayeredLayout,
+            &ForceLayout::default(),
+            &GridLayout::default(),
+        ];
+        for engine in engines {
+            let positions = arrange(
+                engine,
+                &LayoutGraph {
+                    pinned: &pinned,
+                    ..graph
+                },
+                &style,
+            );
+            assert_eq!(positions[0], free[1]);
+            assert_eq!(positions[3], (500.0, 0.0));
+            assert_apart(&positions, &sizes);
+        }
+    }
+
+    #[test]
+    fn test_pinned_box_inside_hierarchy() {
+        let style = DiagramStyle::default();
+        // 0 above 1 above 2 and 3, with 1 pinned to the top edge
+        let sizes = [(100.0, 50.0); 4];
+        let edges = [
+            edge(1, 0, RelationType::Inheritance),
+            edge(2, 1, RelationType::Inheritance),
+            edge(3, 1, RelationType::Inheritance),
+        ];
+        let pinned = [None, Some((300.0, 0.0)), None, None];
+        let graph = LayoutGraph {
+            sizes: &sizes,
+            edges: &edges,
+            groups: &[],
+            pinned: &pinned,
+        };
+        let positions = arrange(&LayeredLayout, &graph, &style);
+        assert_eq!(positions[1], (300.0, 0.0));
+        assert_apart(&positions, &sizes);
+        // Free parents stay above their children, pinned or not
+        assert!(positions[0].1 + 50.0 + style.spacing_y <= positions[1].1);
+        for child in [2, 3] {
+            assert!(positions[1].1 + 50.0 + style.spacing_y <= positions[child].1);
+        }
+        // and the layout stays around the pin
+        assert!((positions[0].0 - 300.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_engines_keep_boxes_apart() {
+        let style = DiagramStyle::default();
+        let (sizes, edges) = family();
+        let graph = LayoutGraph {
+            sizes: &sizes,
+            edges: &edges,
+            groups: &[],
+            pinned: &[],
+        };
+        let engines: [&dyn LayoutEngine; 3] = [
+            &LayeredLayout,
+            &ForceLayout::default(),
+            &GridLayout::default(),
+        ];
+        for engine in engines {
+            let positions = arrange(engine, &graph, &style);
+            assert_apart(&positions, &sizes);
+            // Parents above their children, starting at the origin
+            assert!((1..4).all(|child| positions[0].1 < positions[child].1));
+            assert_eq!(positions.iter().map(|p| p.1).fold(f32::MAX, f32::min), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_grid_rows() {
+        let style = DiagramStyle::default();
+        let sizes = [(100.0, 50.0), (60.0, 30.0), (100.0, 50.0)];
+        let graph = LayoutGraph {
+            sizes: &sizes,
+            edges: &[],
+            groups: &[],
+            pinned: &[],
+        };
+        let grid = GridLayout { columns: Some(2) };
+        let positions = grid.place(&graph, &style);
+        assert_eq!(positions[0], (0.0, 0.0));
+        assert_eq!(positions[1], (100.0 + style.spacing_x, 0.0));
+        assert_eq!(positions[2], (0.0, 50.0 + style.spacing_y));
+    }
+}
@@ -6,7 +6,12 @@
 //! ## Supported Diagram Types
 //!
 //! - **Sequence Diagrams**: participants, messages, alt/else blocks, notes, dividers
-//! - **Class Diagrams**: classes, interfaces, fields, methods, relationships
+//! - **Class Diagrams**: classes, interfaces, Rust structs/traits/enums, fields, methods,
+//!   relationships
+//!
+//! Sequence diagrams can also be generated from `tracing` / OpenTelemetry span
+//! logs with [`sequence_from_spans`], or recorded while tests run with a
+//! [`SequenceRecorder`].
 //!
 //! ## Quick Start
 //!
@@ -43,6 +48,23 @@
 //! 2. External style file (via `.with_style_file()`)
 //! 3. Inline styles in `.pilluml` file (`@start_style`/`@end_style`)
 //!
+//! ## Pinned Class Positions
+//!
+//! Classes can be pinned in the source (`class Foo @ 120,40`) or through a
+//! sidecar layout file mapping class names to positions. The rest are laid
+//! out around them, parents still above their children; if that needs room
+//! above or left of the canvas, the whole diagram moves over. Export a tuned
+//! layout once and keep it:
+//!
+//! ```rust,ignore
+//! let layout = create_diagram(source).export_layout().unwrap();
+//! std::fs::write("model.layout.json", layout)?;
+//!
+//! let svg = create_diagram(source)
+//!     .with_layout_file("model.layout.json")
+//!     .render();
+//! ```
+//!
 //! ## Custom Styling with CSS
 //!
 //! You can override default styles directly in your `.pilluml` file using
@@ -60,15 +82,33 @@
 //! ```
 
 mod class_diagram;
+mod class_lexer;
 mod common;
+mod edge_router;
+mod json;
+mod label_placement;
+mod layered_layout;
+mod layout_engine;
+mod recorder;
 mod sequence_diagram;
+mod span_import;
 
 use std::fs;
 use std::path::Path;
 
-pub use class_diagram::{ClassDef, ClassDiagram, RelationType};
-pub use common::{DiagramStyle, DiagramType, DEFAULT_STYLES_CSS};
-pub use sequence_diagram::{ArrowStyle, Message, Participant, SequenceDiagram};
+pub use class_diagram::{
+    ClassDef, ClassDiagram, Direction, EndMarker, EnumLiteral, LabelDirection, LayoutFileError,
+    Note, NoteSide, NoteTarget, Package, RelationEnd, RelationType, Relationship, TypeParam,
+    VariantData,
+};
+pub use class_lexer::ParseError;
+pub use common::{DiagramStyle, DiagramType, LayoutDirection, DEFAULT_STYLES_CSS};
+pub use layout_engine::{
+    ForceLayout, GridLayout, LayeredLayout, LayoutEdge, LayoutEngine, LayoutGraph,
+};
+pub use recorder::SequenceRecorder;
+pub use sequence_diagram::{ArrowStyle, Element, Message, Participant, SequenceDiagram};
+pub use span_import::{sequence_from_spans, SpanImportError};
 
 // ============================================================================
 // Builder Pattern API
@@ -84,6 +124,8 @@ pub struct DiagramBuilder<'a> {
     source: &'a str,
     style: DiagramStyle,
     external_css: Vec<String>,
+    layout: Option<String>,
+    engine: Box<dyn LayoutEngine>,
 }
 
 impl<'a> DiagramBuilder<'a> {
@@ -93,6 +135,8 @@ impl<'a> DiagramBuilder<'a> {
             source,
             style: DiagramStyle::default(),
             external_css: Vec::new(),
+            layout: None,
+            engine: Box::new(LayeredLayout),
         }
     }
 
@@ -141,6 +185,56 @@ impl<'a> DiagramBuilder<'a> {
         self
     }
 
+    /// Pin class positions from a layout file, as written by
+    /// [`export_layout`](Self::export_layout). Ignored for sequence diagrams.
+    pub fn with_layout_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(json) => self.layout = Some(json),
+            Err(e) => eprintln!("Warning: Could not read layout file: {}", e),
+        }
+        self
+    }
+
+    /// Pin class positions from layout JSON mapping class names to
+    /// `{"x": .., "y": ..}`. Ignored for sequence diagrams.
+    pub fn with_layout(mut self, json: &str) -> Self {
+        self.layout = Some(json.to_string());
+        self
+    }
+
+    /// Place classes with another layout algorithm, such as [`ForceLayout`],
+    /// [`GridLayout`] or your own. The default is [`LayeredLayout`]. Ignored
+    /// for sequence diagrams.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// let svg = create_diagram(source)
+    ///     .with_layout_engine(GridLayout { columns: Some(3) })
+    ///     .render();
+    /// ```
+    pub fn with_layout_engine(mut self, engine: impl LayoutEngine + 'static) -> Self {
+        self.engine = Box::new(engine);
+        self
+    }
+
+    /// Computed class positions as layout JSON, to save and pass back through
+    /// [`with_layout_file`](Self::with_layout_file). `None` for sequence diagrams.
+    pub fn export_layout(&self) -> Option<String> {
+        match detect_diagram_type(self.source) {
+            DiagramType::Sequence => None,
+            DiagramType::Class => Some(
+                class_diagram::laid_out(
+                    self.source,
+                    &self.style,
+                    self.layout.as_deref(),
+                    self.engine.as_ref(),
+                )
+                .layout_json(),
+            ),
+        }
+    }
+
     /// Render the diagram to SVG
     pub fn render(self) -> String {
         // Combine all external CSS into one string
@@ -156,10 +250,12 @@ impl<'a> DiagramBuilder<'a> {
                 &self.style,
                 combined_css.as_deref(),
             ),
-            DiagramType::Class => class_diagram::render_with_file_css(
+            DiagramType::Class => class_diagram::render_with_layout(
                 self.source,
                 &self.style,
                 combined_css.as_deref(),
+                self.layout.as_deref(),
+                self.engine.as_ref(),
             ),
         }
     }
@@ -237,6 +333,58 @@ mod tests {
         assert!(svg.contains("Engine"));
     }
 
+    #[test]
+    fn test_layout_export_round_trip() {
+        let source = "@start_uml\nclass Base\nBase <|-- A\nBase <|-- B\n@end_uml";
+        let layout = create_diagram(source).export_layout().unwrap();
+        let moved = layout.replace("\"Base\": {\"x\": ", "\"Base\": {\"x\": 1");
+        assert_ne!(moved, layout);
+        let pinned = create_diagram(source).with_layout(&moved);
+        assert_eq!(pinned.export_layout().unwrap(), moved);
+        assert_ne!(pinned.render(), render_diagram(source));
+        assert!(create_diagram("@start_uml\nA -> B: hi\n@end_uml")
+            .export_layout()
+            .is_none());
+    }
+
+    #[test]
+    fn test_layout_engines() {
+        // A user engine: everything in one row
+        struct Row;
+        impl LayoutEngine for Row {
+            fn place(&self, graph: &LayoutGraph<'_>, style: &DiagramStyle) -> Vec<(f32, f32)> {
+                let mut x = 0.0;
+                graph
+                    .sizes
+                    .iter()
+                    .map(|&(w, _)| {
+                        let at = (x, 0.0);
+                        x += w + style.spacing_x;
+                        at
+                    })
+                    .collect()
+            }
+        }
+        let source = "@start_uml\nclass Base\nBase <|-- A\nBase <|-- B\nA --> C\n@end_uml";
+        let layered = render_diagram(source);
+        let row = create_diagram(source).with_layout_engine(Row);
+        let layout = row.export_layout().unwrap();
+        assert!(layout.contains("\"Base\": {\"x\": 30, \"y\": 30}"));
+        assert_eq!(layout.matches("\"y\": 30}").count(), 4);
+        for svg in [
+            row.render(),
+            create_diagram(source)
+                .with_layout_engine(ForceLayout::default())
+                .render(),
+            create_diagram(source)
+                .with_layout_engine(GridLayout { columns: Some(1) })
+                .render(),
+        ] {
+            assert!(svg.contains("<svg") && svg.contains("Base"));
+            assert_ne!(svg, layered);
+        }
+    }
+
     #[test]
     fn test_custom_css() {
         let source = "@start_style\n.participant { fill: #ff0000; }\n@end_style\n@start_uml\nA -> B: test\n@end_uml";
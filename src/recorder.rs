@@ -0,0 +1,120 @@
+//! Runtime recording of interactions into sequence diagrams.
+//!
+//! A [`SequenceRecorder`] collects calls and replies while code runs (typically
+//! inside integration tests) and turns them into a [`SequenceDiagram`] or SVG
+//! at the end, so the diagrams are produced by the test suite itself.
+
+use crate::common::DiagramStyle;
+use crate::sequence_diagram::{ArrowStyle, Element, SequenceDiagram};
+use std::sync::{Mutex, MutexGuard};
+
+/// Thread-safe collector of sequence diagram interactions
+///
+/// All methods take `&self`, so a recorder can be shared between threads
+/// through a reference or an `Arc`. Interactions are kept in the order in
+/// which they were recorded.
+///
+/// # Example
+///
+/// ```rust
+/// use pill_uml::SequenceRecorder;
+///
+/// let rec = SequenceRecorder::new();
+/// rec.call("Client", "Server", "GET /x");
+/// rec.reply("Server", "Client", "200 OK");
+///
+/// let svg = rec.to_svg();
+/// assert!(svg.contains("GET /x"));
+/// ```
+#[derive(Debug, Default)]
+pub struct SequenceRecorder {
+    diagram: Mutex<SequenceDiagram>,
+}
+
+impl SequenceRecorder {
+    /// Create an empty recorder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a participant up front to fix its position in the diagram
+    pub fn participant(&self, name: &str) {
+        self.lock().add_participant(name);
+    }
+
+    /// Record a call (solid arrow)
+    pub fn call(&self, from: &str, to: &str, text: &str) {
+        self.message(from, to, text, ArrowStyle::Solid);
+    }
+
+    /// Record a reply (dashed arrow)
+    pub fn reply(&self, from: &str, to: &str, text: &str) {
+        self.message(from, to, text, ArrowStyle::Dashed);
+    }
+
+    /// Record a message with an explicit arrow style
+    pub fn message(&self, from: &str, to: &str, text: &str, style: ArrowStyle) {
+        self.lock().add_message(from, to, text, style);
+    }
+
+    /// Record a divider, e.g. to separate test phases
+    pub fn divider(&self, text: &str) {
+        self.lock()
+            .elements
+            .push(Element::Divider(text.to_string()));
+    }
+
+    /// Forget everything recorded so far
+    pub fn clear(&self) {
+        *self.lock() = SequenceDiagram::new();
+    }
+
+    /// Snapshot of the interactions recorded so far
+    pub fn diagram(&self) -> SequenceDiagram {
+        self.lock().clone()
+    }
+
+    /// Render the recorded interactions with default styling
+    pub fn to_svg(&self) -> String {
+        self.to_svg_styled(&DiagramStyle::default())
+    }
+
+    /// Render the recorded interactions with a custom DiagramStyle
+    pub fn to_svg_styled(&self, style: &DiagramStyle) -> String {
+        self.diagram().to_svg(style)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SequenceDiagram> {
+        // A panicking test must not hide what was recorded before it
+        self.diagram
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_from_threads() {
+        let rec = SequenceRecorder::new();
+        rec.participant("Client");
+        std::thread::scope(|s| {
+            for i in 0..4 {
+                let rec = &rec;
+                s.spawn(move || rec.call("Client", "Server", &format!("req {}", i)));
+            }
+        });
+        rec.reply("Server", "Client", "done");
+
+        let diagram = rec.diagram();
+        assert_eq!(diagram.participants.len(), 2);
+        assert_eq!(diagram.participants[0].name, "Client");
+        assert_eq!(diagram.elements.len(), 5);
+        assert!(rec.to_svg().contains("done"));
+
+        rec.clear();
+        assert!(rec.diagram().elements.is_empty());
+    }
+}
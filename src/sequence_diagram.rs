@@ -4,11 +4,13 @@
 //! - Participants and actors
 //! - Messages (solid, dashed, open arrows)
 //! - Self-messages
+//! - Parallel messages (`& A -> C`) and sloped messages with transit delay (`A ->(10) B`)
+//! - Message anchors (`{start} A -> B`) and duration constraints (`{start} <-> {end} : 200ms`)
 //! - Alt/else blocks
 //! - Dividers
 //! - Notes
 
-use crate::common::{DiagramStyle, SvgBuilder};
+use crate::common::{DiagramStyle, Rect, SvgBuilder};
 use std::collections::HashMap;
 
 // ============================================================================
@@ -40,6 +42,20 @@ pub struct Message {
     pub to: String,
     pub text: String,
     pub style: ArrowStyle,
+    /// Shares the vertical slot of the previous message (`&` prefix)
+    pub parallel: bool,
+    /// Transit delay in pixels; the arrow is drawn sloped by this amount (`->(10)`)
+    pub delay: Option<f32>,
+    /// Anchor name used by duration constraints (`{start} A -> B`)
+    pub anchor: Option<String>,
+}
+
+/// A duration constraint between two anchored messages
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    pub from: String,
+    pub to: String,
+    pub label: String,
 }
 
 /// Elements in a sequence diagram
@@ -51,15 +67,72 @@ pub enum Element {
     ElseBranch(Option<String>),
     AltEnd,
     Note { on: String, text: String },
+    Constraint(Constraint),
+}
+
+/// Vertical extent of an element on the diagram timeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSlot {
+    pub start_y: f32,
+    pub end_y: f32,
+}
+
+impl TimeSlot {
+    /// A zero-length slot at `y`
+    fn at(y: f32) -> Self {
+        Self {
+            start_y: y,
+            end_y: y,
+        }
+    }
 }
 
 /// Parsed sequence diagram
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SequenceDiagram {
     pub participants: Vec<Participant>,
     pub elements: Vec<Element>,
 }
 
+impl SequenceDiagram {
+    /// Create an empty diagram, for building one in code instead of parsing source
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a participant unless one with this name already exists
+    pub fn add_participant(&mut self, name: &str) {
+        if !self.participants.iter().any(|p| p.name == name) {
+            self.participants.push(Participant {
+                name: name.to_string(),
+                order: self.participants.len() as i32,
+                x: 0.0,
+                width: 0.0,
+            });
+        }
+    }
+
+    /// Append a message, adding its participants on first use
+    pub fn add_message(&mut self, from: &str, to: &str, text: &str, style: ArrowStyle) {
+        self.add_participant(from);
+        self.add_participant(to);
+        self.elements.push(Element::Message(Message {
+            from: from.to_string(),
+            to: to.to_string(),
+            text: text.to_string(),
+            style,
+            parallel: false,
+            delay: None,
+            anchor: None,
+        }));
+    }
+
+    /// Render the diagram to SVG
+    pub fn to_svg(&self, style: &DiagramStyle) -> String {
+        render_diagram(self.clone(), style, None, None)
+    }
+}
+
 // ============================================================================
 // Parser
 // ============================================================================
@@ -113,12 +186,12 @@ impl Parser {
 
     fn parse_line(&mut self, line: &str) {
         // Participant declaration
-        if line.starts_with("participant ") {
-            self.parse_participant(&line[12..]);
+        if let Some(rest) = line.strip_prefix("participant ") {
+            self.parse_participant(rest);
             return;
         }
-        if line.starts_with("actor ") {
-            self.parse_participant(&line[6..]);
+        if let Some(rest) = line.strip_prefix("actor ") {
+            self.parse_participant(rest);
             return;
         }
 
@@ -130,9 +203,9 @@ impl Parser {
         }
 
         // Alt/else/end
-        if line.starts_with("alt ") {
+        if let Some(cond) = line.strip_prefix("alt ") {
             self.elements
-                .push(Element::AltStart(line[4..].trim().to_string()));
+                .push(Element::AltStart(cond.trim().to_string()));
             return;
         }
         if line == "else" || line.starts_with("else ") {
@@ -149,8 +222,41 @@ impl Parser {
             return;
         }
 
+        // Anchor: {name} A -> B: text, or constraint: {start} <-> {end} : label
+        let (anchor, line) = match parse_anchor(line) {
+            Some((name, rest)) => (Some(name), rest),
+            None => (None, line),
+        };
+        if let Some(from) = &anchor {
+            if let Some(rest) = line.strip_prefix("<->") {
+                self.try_parse_constraint(from, rest.trim());
+                return;
+            }
+        }
+
+        // Parallel message: & A -> C: text
+        if let Some(rest) = line.strip_prefix('&') {
+            self.try_parse_message(rest.trim(), true, anchor);
+            return;
+        }
+
         // Message: A -> B: text
-        self.try_parse_message(line);
+        self.try_parse_message(line, false, anchor);
+    }
+
+    fn try_parse_constraint(&mut self, from: &str, rest: &str) {
+        let Some((to, rest)) = parse_anchor(rest) else {
+            return;
+        };
+        let label = rest
+            .strip_prefix(':')
+            .map(|l| l.trim().to_string())
+            .unwrap_or_default();
+        self.elements.push(Element::Constraint(Constraint {
+            from: from.to_string(),
+            to,
+            label,
+        }));
     }
 
     fn parse_participant(&mut self, rest: &str) {
@@ -185,7 +291,7 @@ impl Parser {
         }
     }
 
-    fn try_parse_message(&mut self, line: &str) {
+    fn try_parse_message(&mut self, line: &str, parallel: bool, anchor: Option<String>) {
         // Arrow patterns: ->>, -->, ->, -->>
         let patterns = [
             ("-->>", ArrowStyle::DashedOpen),
@@ -199,6 +305,9 @@ impl Parser {
                 let from = line[..pos].trim();
                 let rest = &line[pos + pattern.len()..];
 
+                // Optional transit delay directly after the arrow: ->(10)
+                let (delay, rest) = parse_delay(rest);
+
                 // Split on colon for message text
                 let (to, text) = if let Some(colon) = rest.find(':') {
                     (rest[..colon].trim(), rest[colon + 1..].trim())
@@ -216,6 +325,9 @@ impl Parser {
                         to: to.to_string(),
                         text: text.to_string(),
                         style,
+                        parallel,
+                        delay,
+                        anchor,
                     }));
                 }
                 return;
@@ -239,70 +351,391 @@ impl Parser {
     }
 }
 
+/// Split a leading `{name}` anchor off a line
+fn parse_anchor(line: &str) -> Option<(String, &str)> {
+    let inner = line.strip_prefix('{')?;
+    let close = inner.find('}')?;
+    let name = inner[..close].trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), inner[close + 1..].trim()))
+}
+
+/// Split an optional `(N)` delay off the text following an arrow
+fn parse_delay(rest: &str) -> (Option<f32>, &str) {
+    if let Some(inner) = rest.strip_prefix('(') {
+        if let Some(close) = inner.find(')') {
+            if let Ok(delay) = inner[..close].trim().parse::<f32>() {
+                return (Some(delay.max(0.0)), &inner[close + 1..]);
+            }
+        }
+    }
+    (None, rest)
+}
+
 // ============================================================================
 // Layout
 // ============================================================================
 
+/// Height of the participant boxes at the top and bottom of the diagram
+const PARTICIPANT_HEIGHT: f32 = 35.0;
+/// Space on either side of a participant's name inside its box
+const PARTICIPANT_PADDING: f32 = 20.0;
+const MIN_PARTICIPANT_WIDTH: f32 = 80.0;
+/// Distance between the left edges of neighbouring participants
+const PARTICIPANT_SPACING: f32 = 150.0;
+/// Space reserved above an arrow for its label
+const LABEL_HEIGHT: f32 = 18.0;
+/// Vertical gap between consecutive elements
+const ELEMENT_GAP: f32 = 18.0;
+/// Half the height of an arrow head, which overhangs the arrow line
+const ARROW_OVERHANG: f32 = 4.0;
+const SELF_LOOP_WIDTH: f32 = 30.0;
+const SELF_LOOP_HEIGHT: f32 = 20.0;
+const DIVIDER_HEIGHT: f32 = 20.0;
+/// Height of a frame header (the `alt` tab and the `[condition]` text)
+const FRAME_HEADER_HEIGHT: f32 = 20.0;
+/// Gap between a frame header and the first element below it
+const FRAME_PADDING: f32 = 6.0;
+/// Horizontal gap between a frame edge and its content, including nested frames
+const FRAME_INSET: f32 = 10.0;
+
+/// Positioned geometry of a single diagram element
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElementBox {
+    /// Where the element sits on the timeline (arrow, divider and frame lines)
+    pub slot: TimeSlot,
+    /// Everything drawn for the element; for alt/else/end this is the enclosing frame
+    pub bounds: Rect,
+}
+
+/// Complete geometry of a sequence diagram, produced by a single layout pass
+#[derive(Debug, Clone)]
+pub struct SequenceLayout {
+    pub width: f32,
+    pub height: f32,
+    /// Top edge of the upper participant boxes
+    pub top_y: f32,
+    /// Top edge of the lower participant boxes
+    pub bottom_y: f32,
+    /// One box per element, in element order
+    pub boxes: Vec<ElementBox>,
+    constraints: Vec<PlacedConstraint>,
+}
+
 impl SequenceDiagram {
-    fn layout(&mut self, style: &DiagramStyle) {
-        let _participant_height = 35.0;
-        let participant_padding = 20.0;
-        let participant_spacing = 150.0;
+    /// Position participants and every element in one pass.
+    ///
+    /// The drawing code only consumes the returned boxes, so the canvas size
+    /// always matches what is actually drawn.
+    fn layout(&mut self, style: &DiagramStyle) -> SequenceLayout {
+        self.layout_participants(style);
+
+        let top_y = style.margin;
+        let mut cursor = top_y + PARTICIPANT_HEIGHT + ELEMENT_GAP;
+        let mut boxes: Vec<ElementBox> = Vec::with_capacity(self.elements.len());
+        let mut last_message: Option<TimeSlot> = None;
+        // Fragment frames that have not seen their `end` yet, innermost last
+        let mut open_frames: Vec<OpenFrame> = Vec::new();
+        let diagram_bounds = get_diagram_bounds(&self.participants, style);
+        // Minimum canvas width needed by elements that span the full width
+        let mut min_width: f32 = 0.0;
+
+        for elem in &self.elements {
+            let element_box = match elem {
+                Element::Message(msg) => {
+                    let start = match last_message {
+                        Some(prev) if msg.parallel => prev.start_y,
+                        _ => cursor + LABEL_HEIGHT,
+                    };
+                    let slot = TimeSlot {
+                        start_y: start,
+                        end_y: start + msg.delay.unwrap_or(0.0),
+                    };
+                    last_message = Some(slot);
+                    let bounds = self.message_bounds(msg, slot, style);
+                    cursor = cursor.max(bounds.bottom() + ELEMENT_GAP);
+                    if let Some(frame) = open_frames.last_mut() {
+                        frame.include(bounds.x, bounds.right());
+                        for name in [&msg.from, &msg.to] {
+                            if let Some(p) = self.participants.iter().find(|p| &p.name == name) {
+                                frame.include(p.x - p.width / 2.0, p.x + p.width / 2.0);
+                            }
+                        }
+                    }
+                    ElementBox { slot, bounds }
+                }
+                Element::Divider(text) => {
+                    // Dividers and frame edges end the run a `&` message can join
+                    last_message = None;
+                    let y = cursor + DIVIDER_HEIGHT / 2.0;
+                    let text_width = text.len() as f32 * style.char_width + 20.0;
+                    min_width = min_width.max(text_width + style.margin * 2.0);
+                    cursor = y + DIVIDER_HEIGHT / 2.0 + ELEMENT_GAP;
+                    // Horizontal extent is fixed up once the canvas width is known
+                    ElementBox {
+                        slot: TimeSlot::at(y),
+                        bounds: Rect::new(0.0, y - DIVIDER_HEIGHT / 2.0, 0.0, DIVIDER_HEIGHT),
+                    }
+                }
+                Element::AltStart(_) | Element::ElseBranch(_) => {
+                    last_message = None;
+                    let y = cursor;
+                    if matches!(elem, Element::AltStart(_)) {
+                        open_frames.push(OpenFrame::default());
+                    }
+                    let (header_width, header_height) = match elem {
+                        Element::AltStart(cond) => (
+                            45.0 + (cond.len() + 2) as f32 * style.char_width,
+                            FRAME_HEADER_HEIGHT,
+                        ),
+                        Element::ElseBranch(Some(cond)) => (
+                            5.0 + (cond.len() + 2) as f32 * style.char_width,
+                            FRAME_HEADER_HEIGHT,
+                        ),
+                        _ => (0.0, 0.0),
+                    };
+                    if let Some(frame) = open_frames.last_mut() {
+                        frame.parts.push(boxes.len());
+                        frame.header_width = frame.header_width.max(header_width);
+                    }
+                    cursor = y + header_height + FRAME_PADDING;
+                    // Horizontal extent is fixed up when the frame closes
+                    ElementBox {
+                        slot: TimeSlot::at(y),
+                        bounds: Rect::new(0.0, y, 0.0, 0.0),
+                    }
+                }
+                Element::AltEnd => {
+                    last_message = None;
+                    let y = cursor;
+                    let frame = open_frames.pop().unwrap_or_default();
+                    let bounds = frame.close(&mut boxes, y, diagram_bounds);
+                    if let Some(parent) = open_frames.last_mut() {
+                        parent.include(bounds.x, bounds.right());
+                    }
+                    cursor = y + ELEMENT_GAP;
+                    ElementBox {
+                        slot: TimeSlot::at(y),
+                        bounds,
+                    }
+                }
+                Element::Note { .. } | Element::Constraint(_) => ElementBox {
+                    slot: TimeSlot::at(cursor),
+                    bounds: Rect::new(diagram_bounds.0, cursor, 0.0, 0.0),
+                },
+            };
+            boxes.push(element_box);
+        }
+
+        // Frames left open by a missing `end` still enclose everything after them
+        while let Some(frame) = open_frames.pop() {
+            let bounds = frame.close(&mut boxes, cursor, diagram_bounds);
+            if let Some(parent) = open_frames.last_mut() {
+                parent.include(bounds.x, bounds.right());
+            }
+        }
+
+        // Labels that overhang the left edge push the whole diagram to the right
+        let min_x = boxes
+            .iter()
+            .filter(|b| b.bounds.width > 0.0)
+            .map(|b| b.bounds.x)
+            .fold(style.margin, f32::min);
+        let shift = style.margin - min_x;
+        if shift > 0.0 {
+            for p in &mut self.participants {
+                p.x += shift;
+            }
+            for b in &mut boxes {
+                b.bounds.x += shift;
+            }
+        }
+
+        let constraints = self.place_constraints(&boxes);
+
+        // Width: participants, element boxes and the constraint margin column
+        let mut right = self.participants_right(style);
+        for b in &boxes {
+            right = right.max(b.bounds.right());
+        }
+        for c in &constraints {
+            let label_width = c.label.len() as f32 * style.char_width;
+            right = right.max(constraint_x(self, c, style) + 6.0 + label_width);
+        }
+        let width = (right + style.margin).max(min_width);
+
+        // Dividers span the full canvas
+        for (elem, b) in self.elements.iter().zip(&mut boxes) {
+            if matches!(elem, Element::Divider(_)) {
+                b.bounds.x = style.margin;
+                b.bounds.width = width - style.margin * 2.0;
+            }
+        }
+
+        let bottom_y = cursor;
+        SequenceLayout {
+            width,
+            height: bottom_y + PARTICIPANT_HEIGHT + style.margin,
+            top_y,
+            bottom_y,
+            boxes,
+            constraints,
+        }
+    }
 
+    fn layout_participants(&mut self, style: &DiagramStyle) {
         // Calculate participant widths
         for p in &mut self.participants {
-            p.width = p.name.len() as f32 * style.char_width + participant_padding * 2.0;
-            p.width = p.width.max(80.0);
+            p.width = p.name.len() as f32 * style.char_width + PARTICIPANT_PADDING * 2.0;
+            p.width = p.width.max(MIN_PARTICIPANT_WIDTH);
         }
 
         // Position participants
         let mut current_x = style.margin;
         for p in &mut self.participants {
             p.x = current_x + p.width / 2.0;
-            current_x += p.width.max(participant_spacing);
+            current_x += p.width.max(PARTICIPANT_SPACING);
         }
     }
 
-    fn calculate_dimensions(&self, style: &DiagramStyle) -> (f32, f32) {
-        let participant_height = 35.0;
-        let message_spacing = 40.0;
-
-        // Width
-        let width = if let Some(last) = self.participants.last() {
-            last.x + last.width / 2.0 + style.margin
-        } else {
-            200.0
+    /// Bounding box of a message arrow together with its label
+    fn message_bounds(&self, msg: &Message, slot: TimeSlot, style: &DiagramStyle) -> Rect {
+        let x_of = |name: &str| {
+            self.participants
+                .iter()
+                .find(|p| p.name == name)
+                .map(|p| p.x)
+                .unwrap_or(style.margin)
         };
+        let (x1, x2) = (x_of(&msg.from), x_of(&msg.to));
+        let text_width = msg.text.len() as f32 * style.char_width;
+        let top = slot.start_y - LABEL_HEIGHT;
+
+        if msg.from == msg.to {
+            let right = x1 + SELF_LOOP_WIDTH + 6.0 + text_width;
+            let bottom = slot.end_y + SELF_LOOP_HEIGHT + ARROW_OVERHANG;
+            Rect::new(x1, top, right - x1, bottom - top)
+        } else {
+            let mid_x = (x1 + x2) / 2.0;
+            let left = x1.min(x2).min(mid_x - text_width / 2.0);
+            let right = x1.max(x2).max(mid_x + text_width / 2.0);
+            let bottom = slot.end_y + ARROW_OVERHANG;
+            Rect::new(left, top, right - left, bottom - top)
+        }
+    }
 
-        // Height: count elements
-        let mut element_count = 0;
-        let mut alt_depth: usize = 0;
+    /// Resolve duration constraints against the anchored message slots.
+    ///
+    /// Constraints whose vertical ranges overlap are spread over separate lanes
+    /// so their dimension arrows never sit on top of each other.
+    fn place_constraints(&self, boxes: &[ElementBox]) -> Vec<PlacedConstraint> {
+        let mut anchors: HashMap<&str, f32> = HashMap::new();
+        for (elem, b) in self.elements.iter().zip(boxes) {
+            if let Element::Message(Message {
+                anchor: Some(name), ..
+            }) = elem
+            {
+                anchors.insert(name.as_str(), b.slot.start_y);
+            }
+        }
 
+        let mut placed: Vec<PlacedConstraint> = Vec::new();
         for elem in &self.elements {
-            match elem {
-                Element::Message(_) | Element::Divider(_) => element_count += 1,
-                Element::AltStart(_) => {
-                    element_count += 1;
-                    alt_depth += 1;
-                }
-                Element::ElseBranch(_) => element_count += 1,
-                Element::AltEnd => {
-                    element_count += 1;
-                    alt_depth = alt_depth.saturating_sub(1);
-                }
-                _ => {}
-            }
+            let Element::Constraint(c) = elem else {
+                continue;
+            };
+            let (Some(&a), Some(&b)) = (anchors.get(c.from.as_str()), anchors.get(c.to.as_str()))
+            else {
+                continue;
+            };
+            let (start_y, end_y) = (a.min(b), a.max(b));
+            let lane = (0..)
+                .find(|&lane| {
+                    !placed
+                        .iter()
+                        .any(|p| p.lane == lane && p.start_y <= end_y && start_y <= p.end_y)
+                })
+                .unwrap_or(0);
+            placed.push(PlacedConstraint {
+                lane,
+                start_y,
+                end_y,
+                label: c.label.clone(),
+            });
         }
+        placed
+    }
 
-        let height = style.margin * 2.0
-            + participant_height * 2.0
-            + element_count as f32 * message_spacing
-            + 40.0;
+    /// Right edge of the participant area; without participants it leaves
+    /// room for one participant box after the margin
+    fn participants_right(&self, style: &DiagramStyle) -> f32 {
+        self.participants
+            .last()
+            .map(|p| p.x + p.width / 2.0)
+            .unwrap_or(style.margin + MIN_PARTICIPANT_WIDTH)
+    }
+}
+
+/// A fragment frame whose horizontal extent is still being collected
+#[derive(Debug, Default)]
+struct OpenFrame {
+    /// Boxes of the frame header and its else branches
+    parts: Vec<usize>,
+    /// Horizontal extent of the content, including nested frames
+    extent: Option<(f32, f32)>,
+    /// Widest header text among the frame parts
+    header_width: f32,
+}
+
+impl OpenFrame {
+    fn include(&mut self, left: f32, right: f32) {
+        self.extent = Some(match self.extent {
+            Some((l, r)) => (l.min(left), r.max(right)),
+            None => (left, right),
+        });
+    }
 
-        (width, height)
+    /// Size the frame to cover only the participants it involves and assign the
+    /// resulting rectangle to every part of it.
+    ///
+    /// Frames without any messages fall back to `diagram_bounds`.
+    fn close(self, boxes: &mut [ElementBox], end_y: f32, diagram_bounds: (f32, f32)) -> Rect {
+        let (left, right) = match self.extent {
+            Some((l, r)) => (l - FRAME_INSET, r + FRAME_INSET),
+            None => diagram_bounds,
+        };
+        let right = right.max(left + self.header_width);
+        let start_y = self
+            .parts
+            .first()
+            .map(|&i| boxes[i].slot.start_y)
+            .unwrap_or(end_y);
+        let bounds = Rect::new(left, start_y, right - left, end_y - start_y);
+        for i in self.parts {
+            boxes[i].bounds = bounds;
+        }
+        bounds
     }
 }
 
+/// A duration constraint resolved to vertical coordinates
+#[derive(Debug, Clone)]
+struct PlacedConstraint {
+    lane: usize,
+    start_y: f32,
+    end_y: f32,
+    label: String,
+}
+
+/// X coordinate of a constraint's dimension line in the right margin
+fn constraint_x(diagram: &SequenceDiagram, c: &PlacedConstraint, style: &DiagramStyle) -> f32 {
+    let margin_gap = 20.0;
+    let lane_spacing = 16.0;
+    diagram.participants_right(style) + margin_gap + c.lane as f32 * lane_spacing
+}
+
 // ============================================================================
 // Renderer
 // ============================================================================
@@ -314,12 +747,21 @@ pub fn render(source: &str, style: &DiagramStyle) -> String {
 
 /// Render diagram with optional file CSS layer
 pub fn render_with_file_css(source: &str, style: &DiagramStyle, file_css: Option<&str>) -> String {
-    let mut diagram = Parser::new().parse(source);
-    diagram.layout(style);
-
-    let (width, height) = diagram.calculate_dimensions(style);
+    let diagram = Parser::new().parse(source);
     let inline_css = crate::common::extract_custom_css(source);
-    let mut svg = SvgBuilder::new(width, height, style, file_css, inline_css.as_deref());
+    render_diagram(diagram, style, file_css, inline_css.as_deref())
+}
+
+/// Lay out and draw a diagram model with the given CSS layers
+fn render_diagram(
+    mut diagram: SequenceDiagram,
+    style: &DiagramStyle,
+    file_css: Option<&str>,
+    inline_css: Option<&str>,
+) -> String {
+    let layout = diagram.layout(style);
+
+    let mut svg = SvgBuilder::new(layout.width, layout.height, style, file_css, inline_css);
 
     // Get shadow properties from CSS
     let shadow_dx = svg.css_prop_or("participant", "shadow-dx", 0.0);
@@ -338,6 +780,9 @@ pub fn render_with_file_css(source: &str, style: &DiagramStyle, file_css: Option
 <marker id="seq-arrow-open" markerWidth="10" markerHeight="7" refX="9" refY="3.5" orient="auto">
 <polyline points="0 0, 10 3.5, 0 7" class="arrow-head-open"/>
 </marker>
+<marker id="seq-constraint-arrow" markerWidth="8" markerHeight="6" refX="8" refY="3" orient="auto-start-reverse">
+<polygon points="0 0, 8 3, 0 6" class="constraint-arrow-head"/>
+</marker>
 "#);
 
     // Shadow filter for participants (if enabled)
@@ -354,99 +799,79 @@ pub fn render_with_file_css(source: &str, style: &DiagramStyle, file_css: Option
     defs.push_str("</defs>");
     svg.push(&defs);
 
-    let participant_height = 35.0;
-    let top_y = style.margin;
-    let bottom_y = height - style.margin - participant_height;
+    let (top_y, bottom_y) = (layout.top_y, layout.bottom_y);
 
     // Draw lifelines
     for p in &diagram.participants {
-        svg.line_class(p.x, top_y + participant_height, p.x, bottom_y, "lifeline");
+        svg.line_class(p.x, top_y + PARTICIPANT_HEIGHT, p.x, bottom_y, "lifeline");
     }
 
     // Draw participant boxes (top and bottom)
     for p in &diagram.participants {
-        draw_participant_box(&mut svg, p, top_y, participant_height, style);
-        draw_participant_box(&mut svg, p, bottom_y, participant_height, style);
+        draw_participant_box(&mut svg, p, top_y, PARTICIPANT_HEIGHT, style);
+        draw_participant_box(&mut svg, p, bottom_y, PARTICIPANT_HEIGHT, style);
     }
 
     // Draw elements
-    let mut current_y = top_y + participant_height + 30.0;
-    let message_spacing = 40.0;
-    let mut alt_stack: Vec<(f32, f32, f32)> = Vec::new(); // (start_y, left_x, right_x)
-
-    for elem in &diagram.elements {
+    // Frames whose `end` has not been drawn yet, innermost last
+    let mut open_frames: Vec<Rect> = Vec::new();
+    for (elem, element_box) in diagram.elements.iter().zip(&layout.boxes) {
+        let current_y = element_box.slot.start_y;
+        let frame = element_box.bounds;
         match elem {
             Element::Message(msg) => {
-                draw_message(&mut svg, &diagram.participants, msg, current_y, style);
-                current_y += message_spacing;
+                draw_message(
+                    &mut svg,
+                    &diagram.participants,
+                    msg,
+                    element_box.slot,
+                    style,
+                );
             }
             Element::Divider(text) => {
-                draw_divider(&mut svg, width, current_y, text, style);
-                current_y += message_spacing;
+                draw_divider(&mut svg, element_box.bounds, text, style);
             }
             Element::AltStart(cond) => {
-                let (left_x, right_x) = get_diagram_bounds(&diagram.participants, style);
-                alt_stack.push((current_y, left_x, right_x));
-
+                open_frames.push(frame);
                 // Draw alt header - offset past the "alt" label box (which is ~40px wide)
                 svg.text_class(
-                    left_x + 45.0,
+                    frame.x + 45.0,
                     current_y + 15.0,
                     &format!("[{}]", cond),
                     "alt-condition-text",
                 );
-                current_y += message_spacing;
             }
             Element::ElseBranch(cond) => {
-                if let Some(&(_, left_x, right_x)) = alt_stack.last() {
-                    // Dashed line for else
-                    svg.line_class(left_x, current_y, right_x, current_y, "alt-divider");
-
-                    if let Some(c) = cond {
-                        svg.text_class(
-                            left_x + 5.0,
-                            current_y + 15.0,
-                            &format!("[{}]", c),
-                            "alt-condition-text diagram-text",
-                        );
-                    }
+                // Dashed line for else
+                svg.line_class(frame.x, current_y, frame.right(), current_y, "alt-divider");
+
+                if let Some(c) = cond {
+                    svg.text_class(
+                        frame.x + 5.0,
+                        current_y + 15.0,
+                        &format!("[{}]", c),
+                        "alt-condition-text diagram-text",
+                    );
                 }
-                // Add extra spacing after else to prevent overlap with next message
-                current_y += message_spacing * 0.5 + 16.0;
             }
             Element::AltEnd => {
-                if let Some((start_y, left_x, right_x)) = alt_stack.pop() {
-                    // Draw alt box
-                    let box_height = current_y - start_y;
-                    svg.push(&format!(
-                        r#"<rect x="{}" y="{}" width="{}" height="{}" class="alt-box"/>"#,
-                        left_x,
-                        start_y,
-                        right_x - left_x,
-                        box_height
-                    ));
-                    // Alt label box - rectangle with cut bottom-right corner
-                    let box_width = 30.0;
-                    let box_height_label = 15.0;
-                    let cut_size = 8.0;
-                    svg.polygon_class(
-                        &[
-                            (left_x, start_y),                                           // top-left
-                            (left_x + box_width, start_y), // top-right
-                            (left_x + box_width, start_y + box_height_label - cut_size), // right edge before cut
-                            (left_x + box_width - cut_size, start_y + box_height_label), // cut corner
-                            (left_x, start_y + box_height_label), // bottom-left
-                        ],
-                        "alt-label-box",
-                    );
-                    svg.text_class(left_x + 5.0, start_y + 11.0, "alt", "alt-label-text");
-                }
-                current_y += message_spacing * 0.5;
+                open_frames.pop();
+                draw_frame(&mut svg, frame);
             }
             _ => {}
         }
     }
 
+    // Frames missing their `end` run to the bottom of the timeline
+    for frame in open_frames.into_iter().rev() {
+        draw_frame(&mut svg, frame);
+    }
+
+    // Duration constraints in the right margin
+    for c in &layout.constraints {
+        draw_constraint(&mut svg, &diagram, c, style);
+    }
+
     svg.finish()
 }
 
@@ -477,9 +902,10 @@ fn draw_message(
     svg: &mut SvgBuilder,
     participants: &[Participant],
     msg: &Message,
-    y: f32,
+    slot: TimeSlot,
     _style: &DiagramStyle,
 ) {
+    let (y, end_y) = (slot.start_y, slot.end_y);
     let from_p = participants.iter().find(|p| p.name == msg.from);
     let to_p = participants.iter().find(|p| p.name == msg.to);
 
@@ -502,13 +928,13 @@ fn draw_message(
 
     if msg.from == msg.to {
         // Self-message
-        let loop_width = 30.0;
-        let loop_height = 20.0;
+        let loop_width = SELF_LOOP_WIDTH;
+        let loop_height = SELF_LOOP_HEIGHT;
         let points = vec![
             (from_p.x, y),
             (from_p.x + loop_width, y),
-            (from_p.x + loop_width, y + loop_height),
-            (from_p.x, y + loop_height),
+            (from_p.x + loop_width, end_y + loop_height),
+            (from_p.x, end_y + loop_height),
         ];
         svg.polyline_class(&points, class, marker);
 
@@ -520,29 +946,83 @@ fn draw_message(
             "message-text",
         );
     } else {
-        // Normal message
+        // Normal message, sloped down to `end_y` when it has a transit delay
         let (x1, x2) = (from_p.x, to_p.x);
-        svg.polyline_class(&[(x1, y), (x2, y)], class, marker);
+        svg.polyline_class(&[(x1, y), (x2, end_y)], class, marker);
 
         // Label
         let mid_x = (x1 + x2) / 2.0;
-        svg.text_class(mid_x, y - 5.0, &msg.text, "message-text");
+        let mid_y = (y + end_y) / 2.0;
+        svg.text_class(mid_x, mid_y - 5.0, &msg.text, "message-text");
     }
 }
 
-fn draw_divider(svg: &mut SvgBuilder, width: f32, y: f32, text: &str, style: &DiagramStyle) {
-    let left = style.margin;
-    let right = width - style.margin;
+fn draw_constraint(
+    svg: &mut SvgBuilder,
+    diagram: &SequenceDiagram,
+    c: &PlacedConstraint,
+    style: &DiagramStyle,
+) {
+    let x = constraint_x(diagram, c, style);
+    let ext_start = diagram.participants_right(style) + 5.0;
+
+    // Extension lines from the participant area to the dimension line
+    svg.line_class(
+        ext_start,
+        c.start_y,
+        x + 5.0,
+        c.start_y,
+        "constraint-extension",
+    );
+    svg.line_class(ext_start, c.end_y, x + 5.0, c.end_y, "constraint-extension");
+
+    // Dimension arrow with heads at both ends
+    svg.push(&format!(
+        r#"<line x1="{}" y1="{}" x2="{}" y2="{}" class="constraint-line" marker-start="url(#seq-constraint-arrow)" marker-end="url(#seq-constraint-arrow)"/>"#,
+        x, c.start_y, x, c.end_y
+    ));
+
+    if !c.label.is_empty() {
+        let mid_y = (c.start_y + c.end_y) / 2.0;
+        svg.text_class(x + 6.0, mid_y + 4.0, &c.label, "constraint-text");
+    }
+}
+
+/// Draw a fragment frame with its `alt` label box
+fn draw_frame(svg: &mut SvgBuilder, frame: Rect) {
+    let (left_x, start_y) = (frame.x, frame.y);
+    // Draw alt box
+    svg.rect_class(frame.x, frame.y, frame.width, frame.height, "alt-box");
+    // Alt label box - rectangle with cut bottom-right corner
+    let box_width = 30.0;
+    let box_height_label = 15.0;
+    let cut_size = 8.0;
+    svg.polygon_class(
+        &[
+            (left_x, start_y),                                           // top-left
+            (left_x + box_width, start_y),                               // top-right
+            (left_x + box_width, start_y + box_height_label - cut_size), // right edge before cut
+            (left_x + box_width - cut_size, start_y + box_height_label), // cut corner
+            (left_x, start_y + box_height_label),                        // bottom-left
+        ],
+        "alt-label-box",
+    );
+    svg.text_class(left_x + 5.0, start_y + 11.0, "alt", "alt-label-text");
+}
+
+fn draw_divider(svg: &mut SvgBuilder, bounds: Rect, text: &str, style: &DiagramStyle) {
+    let y = bounds.y + bounds.height / 2.0;
+    let center_x = bounds.x + bounds.width / 2.0;
 
     // Dashed line
-    svg.line_class(left, y, right, y, "divider-line");
+    svg.line_class(bounds.x, y, bounds.right(), y, "divider-line");
 
     // Text box in center
     let text_width = text.len() as f32 * style.char_width + 20.0;
-    let box_x = (width - text_width) / 2.0;
+    let box_x = center_x - text_width / 2.0;
 
-    svg.rect_class(box_x, y - 10.0, text_width, 20.0, "divider-box");
-    svg.text_class(width / 2.0, y + 4.0, text, "divider-text");
+    svg.rect_class(box_x, bounds.y, text_width, bounds.height, "divider-box");
+    svg.text_class(center_x, y + 4.0, text, "divider-text");
 }
 
 fn get_diagram_bounds(participants: &[Participant], style: &DiagramStyle) -> (f32, f32) {
@@ -569,6 +1049,109 @@ mod tests {
         assert_eq!(diagram.elements.len(), 1);
     }
 
+    #[test]
+    fn test_parallel_and_delayed_messages() {
+        let source = "@start_uml\nA -> B: one\n& A -> C: two\nA ->(15) B: slow\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        let layout = diagram.layout(&DiagramStyle::default());
+        let slots: Vec<TimeSlot> = layout.boxes.iter().map(|b| b.slot).collect();
+        assert_eq!(slots[0].start_y, slots[1].start_y);
+        assert!(slots[2].start_y > slots[1].start_y);
+        assert_eq!(slots[2].end_y - slots[2].start_y, 15.0);
+        if let Element::Message(msg) = &diagram.elements[2] {
+            assert_eq!(msg.to, "B");
+            assert_eq!(msg.delay, Some(15.0));
+        }
+
+        // A `&` message never reaches back across a divider or frame edge
+        let source = "@start_uml\nA -> B: one\n... Next ...\n& A -> C: two\nalt ok\n& B -> C: three\nend\n& C -> A: four\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        let layout = diagram.layout(&DiagramStyle::default());
+        let boxes = &layout.boxes;
+        for (message, before) in [(2, 1), (4, 3), (6, 5)] {
+            assert!(boxes[message].slot.start_y > boxes[before].slot.start_y);
+        }
+    }
+
+    #[test]
+    fn test_duration_constraint() {
+        let source = "@start_uml\n{start} A -> B: req\nB -> B: work\n{end} B --> A: resp\n{start} <-> {end} : 200ms max\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        assert_eq!(diagram.elements.len(), 4);
+        let Element::Constraint(c) = &diagram.elements[3] else {
+            panic!("expected constraint");
+        };
+        assert_eq!((c.from.as_str(), c.to.as_str()), ("start", "end"));
+        assert_eq!(c.label, "200ms max");
+
+        let layout = diagram.layout(&DiagramStyle::default());
+        let placed = &layout.constraints;
+        assert_eq!(placed.len(), 1);
+        assert_eq!(placed[0].start_y, layout.boxes[0].slot.start_y);
+        assert_eq!(placed[0].end_y, layout.boxes[2].slot.start_y);
+    }
+
+    #[test]
+    fn test_layout_bounds_match_canvas() {
+        let source = "@start_uml\nA -> B: hello\nalt ok\nB -> B: a rather long self message label\nelse\n...wait...\nend\nB --> A: done\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        let layout = diagram.layout(&DiagramStyle::default());
+
+        let content_bottom = layout
+            .boxes
+            .iter()
+            .map(|b| b.bounds.bottom())
+            .fold(0.0f32, f32::max);
+        let content_right = layout
+            .boxes
+            .iter()
+            .map(|b| b.bounds.right())
+            .fold(0.0f32, f32::max);
+        assert!(content_bottom <= layout.bottom_y);
+        assert!(content_right <= layout.width);
+        assert_eq!(
+            layout.height,
+            layout.bottom_y + PARTICIPANT_HEIGHT + DiagramStyle::default().margin
+        );
+
+        // The frame encloses the else branch and ends at the `end` slot
+        let frame = layout.boxes[1].bounds;
+        assert_eq!(frame, layout.boxes[3].bounds);
+        assert_eq!(frame.bottom(), layout.boxes[5].slot.start_y);
+    }
+
+    #[test]
+    fn test_frames_scoped_to_participants() {
+        let source = "@start_uml\nparticipant A\nparticipant B\nparticipant C\nparticipant D\nA -> D: wide\nalt outer\nB -> C: x\nalt inner\nB -> C: y\nend\nend\n@end_uml";
+        let mut diagram = Parser::new().parse(source);
+        let layout = diagram.layout(&DiagramStyle::default());
+        let (outer, inner) = (layout.boxes[1].bounds, layout.boxes[3].bounds);
+        let (a, b, c) = (
+            &diagram.participants[0],
+            &diagram.participants[1],
+            &diagram.participants[2],
+        );
+
+        // Neither frame reaches participant A
+        assert!(outer.x > a.x + a.width / 2.0);
+        assert!(inner.x >= b.x - b.width / 2.0 - FRAME_INSET);
+        assert!(inner.right() >= c.x + c.width / 2.0);
+
+        // The inner frame is inset on both sides
+        assert_eq!(inner.x - outer.x, FRAME_INSET);
+        assert_eq!(outer.right() - inner.right(), FRAME_INSET);
+        assert!(outer.y < inner.y && inner.bottom() < outer.bottom());
+    }
+
+    #[test]
+    fn test_unclosed_frame_is_drawn() {
+        let source =
+            "@start_uml\nalt outer\nA -> B: x\nalt inner\nB -> A: y\nend\nA -> B: z\n@end_uml";
+        let svg = render(source, &DiagramStyle::default());
+        assert_eq!(svg.matches(r#"class="alt-box""#).count(), 2);
+        assert_eq!(svg.matches(r#"class="alt-label-box""#).count(), 2);
+    }
+
     #[test]
     fn test_self_message() {
         let source = "@start_uml\nA -> A: self\n@end_uml";
@@ -0,0 +1,441 @@
+//! Build sequence diagrams from tracing / OpenTelemetry span logs.
+//!
+//! Accepted input:
+//! - OTLP-JSON: a `{"resourceSpans": [...]}` document, or one such document per
+//!   line as written by the OpenTelemetry file exporter
+//! - JSON lines with one flat span per line (`span_id`, `parent_span_id`, `name`,
+//!   `service` or `target`, `start_time_unix_nano`, `end_time_unix_nano`)
+//! - JSON lines from the `tracing-subscriber` JSON formatter with span `new` and
+//!   `close` events enabled; line order is used as the timeline
+//!
+//! Services (`service.name`) or targets become participants. When a span starts
+//! under a parent, the parent's participant sends a message named after the span
+//! to the child's participant; when it ends, a dashed return goes back. Returns
+//! are only drawn between different participants.
+
+use crate::json::{self, JsonValue};
+use crate::sequence_diagram::{ArrowStyle, SequenceDiagram};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Error raised while importing a span log
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanImportError {
+    /// 1-based line of the offending record
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SpanImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for SpanImportError {}
+
+/// A span normalised from any of the supported formats
+#[derive(Debug, Clone)]
+struct SpanRecord {
+    id: String,
+    parent: Option<String>,
+    participant: String,
+    name: String,
+    start: u128,
+    end: Option<u128>,
+}
+
+/// Build a sequence diagram from an OTLP-JSON or JSON-lines span log
+///
+/// # Example
+///
+/// ```rust
+/// let log = r#"{"span_id":"1","name":"GET /x","service":"gateway","start":1,"end":9}
+/// {"span_id":"2","parent_span_id":"1","name":"query","service":"db","start":2,"end":5}"#;
+///
+/// let diagram = pill_uml::sequence_from_spans(log).unwrap();
+/// assert_eq!(diagram.participants.len(), 2);
+/// ```
+pub fn sequence_from_spans(input: &str) -> Result<SequenceDiagram, SpanImportError> {
+    let spans = collect_spans(input)?;
+    if spans.is_empty() {
+        return Err(SpanImportError {
+            line: 1,
+            message: "no spans found".to_string(),
+        });
+    }
+    Ok(build_diagram(spans))
+}
+
+fn collect_spans(input: &str) -> Result<Vec<SpanRecord>, SpanImportError> {
+    let mut spans = Vec::new();
+    let mut events = TracingEvents::default();
+
+    // A single (possibly pretty-printed) document
+    if let Ok(doc) = json::parse(input) {
+        match &doc {
+            JsonValue::Array(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    collect_record(item, index, &mut events, &mut spans);
+                }
+            }
+            _ => collect_record(&doc, 0, &mut events, &mut spans),
+        }
+        return Ok(spans);
+    }
+
+    // JSON lines
+    for (index, line) in input.lines().enumerate() {
+        let line_no = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = json::parse(line).map_err(|e| SpanImportError {
+            line: line_no,
+            message: e.to_string(),
+        })?;
+        collect_record(&value, index, &mut events, &mut spans);
+    }
+    Ok(spans)
+}
+
+/// Collect spans from an OTLP request, a `tracing` span event or a flat span
+/// object. `index` is the record's position in the input, used as the time of
+/// `tracing` events, which carry none. Spans without a start time are skipped
+/// rather than mixing positions with real timestamps.
+fn collect_record(
+    value: &JsonValue,
+    index: usize,
+    events: &mut TracingEvents,
+    spans: &mut Vec<SpanRecord>,
+) {
+    if value.get("fields").is_some() {
+        events.record(value, index as u128, spans);
+    } else if let Some(resource_spans) = value.get("resourceSpans").and_then(|v| v.as_array()) {
+        for rs in resource_spans {
+            collect_otlp_resource(rs, spans);
+        }
+    } else if let Some(span) = flat_span(value) {
+        spans.push(span);
+    }
+}
+
+fn collect_otlp_resource(rs: &JsonValue, spans: &mut Vec<SpanRecord>) {
+    let service = rs
+        .get("resource")
+        .and_then(|r| otlp_attribute(r, "service.name"));
+
+    let scopes = rs
+        .get_any(&["scopeSpans", "instrumentationLibrarySpans"])
+        .and_then(|v| v.as_array())
+        .unwrap_or_default();
+    for scope in scopes {
+        let scope_name = scope
+            .get_any(&["scope", "instrumentationLibrary"])
+            .and_then(|s| s.get("name"))
+            .and_then(|n| n.as_str());
+        let participant = service
+            .as_deref()
+            .or(scope_name)
+            .unwrap_or("unknown")
+            .to_string();
+
+        let items = scope
+            .get("spans")
+            .and_then(|v| v.as_array())
+            .unwrap_or_default();
+        for span in items {
+            let trace = span.get("traceId").and_then(|v| v.as_str()).unwrap_or("");
+            let Some(id) = span.get("spanId").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let Some(start) = span.get("startTimeUnixNano").and_then(|v| v.as_u128()) else {
+                continue;
+            };
+            let parent = span
+                .get("parentSpanId")
+                .and_then(|v| v.as_str())
+                .filter(|p| !p.is_empty())
+                .map(|p| format!("{}/{}", trace, p));
+            spans.push(SpanRecord {
+                id: format!("{}/{}", trace, id),
+                parent,
+                participant: participant.clone(),
+                name: string_field(span, &["name"]).unwrap_or_default(),
+                start,
+                end: span.get("endTimeUnixNano").and_then(|v| v.as_u128()),
+            });
+        }
+    }
+}
+
+/// Read a string attribute from an OTLP `attributes` list
+fn otlp_attribute(owner: &JsonValue, key: &str) -> Option<String> {
+    owner
+        .get("attributes")?
+        .as_array()?
+        .iter()
+        .find(|a| a.get("key").and_then(|k| k.as_str()) == Some(key))?
+        .get("value")?
+        .get("stringValue")?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn flat_span(value: &JsonValue) -> Option<SpanRecord> {
+    let id = string_field(value, &["span_id", "spanId", "id"])?;
+    let trace = string_field(value, &["trace_id", "traceId"]).unwrap_or_default();
+    let parent = string_field(value, &["parent_span_id", "parentSpanId", "parent_id"])
+        .filter(|p| !p.is_empty())
+        .map(|p| format!("{}/{}", trace, p));
+    let participant = string_field(
+        value,
+        &["service", "service.name", "service_name", "target"],
+    )
+    .unwrap_or_else(|| "unknown".to_string());
+    let start = value
+        .get_any(&["start_time_unix_nano", "startTimeUnixNano", "start"])
+        .and_then(|v| v.as_u128())?;
+    let end = value
+        .get_any(&["end_time_unix_nano", "endTimeUnixNano", "end"])
+        .and_then(|v| v.as_u128());
+
+    Some(SpanRecord {
+        id: format!("{}/{}", trace, id),
+        parent,
+        participant,
+        name: string_field(value, &["name"]).unwrap_or_default(),
+        start,
+        end,
+    })
+}
+
+/// A string field, or a number rendered as a string (ids are sometimes numeric)
+fn string_field(value: &JsonValue, keys: &[&str]) -> Option<String> {
+    match value.get_any(keys)? {
+        JsonValue::String(s) => Some(s.clone()),
+        JsonValue::Number(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+/// Reconstructs spans from `tracing-subscriber` JSON span events.
+///
+/// These events carry no span ids, so spans are matched by their scope path
+/// (the names of all enclosing spans).
+#[derive(Default)]
+struct TracingEvents {
+    /// Open spans per scope path, innermost last
+    open: HashMap<String, Vec<usize>>,
+}
+
+impl TracingEvents {
+    fn record(&mut self, event: &JsonValue, time: u128, spans: &mut Vec<SpanRecord>) {
+        let kind = event
+            .get("fields")
+            .and_then(|f| f.get("message"))
+            .and_then(|m| m.as_str());
+        let Some(current) = event
+            .get("span")
+            .and_then(|s| s.get("name"))
+            .and_then(|n| n.as_str())
+        else {
+            return;
+        };
+
+        // Scope names from the root down to the current span
+        let mut scope: Vec<&str> = event
+            .get("spans")
+            .and_then(|v| v.as_array())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|s| s.get("name").and_then(|n| n.as_str()))
+            .collect();
+        if scope.last() != Some(&current) {
+            scope.push(current);
+        }
+        let path = scope.join("/");
+        let parent_path = scope[..scope.len() - 1].join("/");
+
+        match kind {
+            Some("new") => {
+                let parent = self
+                    .open
+                    .get(&parent_path)
+                    .and_then(|stack| stack.last())
+                    .map(|&i| spans[i].id.clone());
+                let index = spans.len();
+                spans.push(SpanRecord {
+                    id: format!("tracing/{}", index),
+                    parent,
+                    participant: string_field(event, &["target"])
+                        .unwrap_or_else(|| "unknown".to_string()),
+                    name: current.to_string(),
+                    start: time,
+                    end: None,
+                });
+                self.open.entry(path).or_default().push(index);
+            }
+            Some("close") => {
+                if let Some(index) = self.open.get_mut(&path).and_then(|stack| stack.pop()) {
+                    spans[index].end = Some(time);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn build_diagram(spans: Vec<SpanRecord>) -> SequenceDiagram {
+    let by_id: HashMap<&str, usize> = spans
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.id.as_str(), i))
+        .collect();
+    let parent_of = |i: usize| {
+        spans[i]
+            .parent
+            .as_deref()
+            .and_then(|p| by_id.get(p).copied())
+    };
+
+    // Order starts and ends on one timeline. At equal times ends come first,
+    // except for a zero-length span's own end which must follow its start.
+    let mut events: Vec<(u128, u8, usize, bool)> = Vec::new();
+    for (i, span) in spans.iter().enumerate() {
+        events.push((span.start, 1, i, true));
+        if let Some(end) = span.end {
+            let rank = if end <= span.start { 2 } else { 0 };
+            events.push((end.max(span.start), rank, i, false));
+        }
+    }
+    events.sort_by_key(|&(time, rank, i, _)| (time, rank, i));
+
+    let mut diagram = SequenceDiagram::new();
+    for (_, _, i, is_start) in events {
+        let span = &spans[i];
+        match (parent_of(i), is_start) {
+            (None, true) => diagram.add_participant(&span.participant),
+            (Some(p), true) => diagram.add_message(
+                &spans[p].participant,
+                &span.participant,
+                &span.name,
+                ArrowStyle::Solid,
+            ),
+            (Some(p), false) if spans[p].participant != span.participant => diagram.add_message(
+                &span.participant,
+                &spans[p].participant,
+                "",
+                ArrowStyle::Dashed,
+            ),
+            _ => {}
+        }
+    }
+    diagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence_diagram::Element;
+
+    fn messages(diagram: &SequenceDiagram) -> Vec<(String, String, String)> {
+        diagram
+            .elements
+            .iter()
+            .filter_map(|e| match e {
+                Element::Message(m) => Some((m.from.clone(), m.to.clone(), m.text.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_import_otlp() {
+        let otlp = r#"{"resourceSpans": [
+            {"resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "gateway"}}]},
+             "scopeSpans": [{"spans": [
+                {"traceId": "t", "spanId": "a", "parentSpanId": "", "name": "GET /x",
+                 "startTimeUnixNano": "100", "endTimeUnixNano": "900"}]}]},
+            {"resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "users"}}]},
+             "scopeSpans": [{"spans": [
+                {"traceId": "t", "spanId": "b", "parentSpanId": "a", "name": "lookup",
+                 "startTimeUnixNano": "200", "endTimeUnixNano": "500"}]}]}
+        ]}"#;
+        let diagram = sequence_from_spans(otlp).unwrap();
+        let names: Vec<_> = diagram
+            .participants
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(names, ["gateway", "users"]);
+        assert_eq!(
+            messages(&diagram),
+            [
+                ("gateway".into(), "users".into(), "lookup".into()),
+                ("users".into(), "gateway".into(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_tracing_json_lines() {
+        let log = [
+            r#"{"fields":{"message":"new"},"target":"api","span":{"name":"handle"},"spans":[{"name":"handle"}]}"#,
+            r#"{"fields":{"message":"processing"},"target":"api","span":{"name":"handle"},"spans":[{"name":"handle"}]}"#,
+            r#"{"fields":{"message":"new"},"target":"db","span":{"name":"query"},"spans":[{"name":"handle"},{"name":"query"}]}"#,
+            r#"{"fields":{"message":"close"},"target":"db","span":{"name":"query"},"spans":[{"name":"handle"},{"name":"query"}]}"#,
+            r#"{"fields":{"message":"close"},"target":"api","span":{"name":"handle"},"spans":[{"name":"handle"}]}"#,
+        ]
+        .join("\n");
+        let diagram = sequence_from_spans(&log).unwrap();
+        assert_eq!(
+            messages(&diagram),
+            [
+                ("api".into(), "db".into(), "query".into()),
+                ("db".into(), "api".into(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_flat_nanosecond_spans() {
+        // Ends 1 ns apart, beyond what an `f64` can tell apart at this magnitude
+        let log = [
+            r#"{"span_id":"1","name":"root","service":"api","start_time_unix_nano":1700000000000000000,"end_time_unix_nano":1700000000000000900}"#,
+            r#"{"span_id":"2","parent_span_id":"1","name":"a","service":"db","start_time_unix_nano":1700000000000000100,"end_time_unix_nano":1700000000000000301}"#,
+            r#"{"span_id":"3","parent_span_id":"1","name":"b","service":"cache","start_time_unix_nano":1700000000000000300,"end_time_unix_nano":1700000000000000400}"#,
+            r#"{"span_id":"4","parent_span_id":"1","name":"untimed","service":"db"}"#,
+        ]
+        .join("\n");
+        let diagram = sequence_from_spans(&log).unwrap();
+        assert_eq!(
+            messages(&diagram),
+            [
+                ("api".into(), "db".into(), "a".into()),
+                ("api".into(), "cache".into(), "b".into()),
+                ("db".into(), "api".into(), String::new()),
+                ("cache".into(), "api".into(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_without_spans_fails() {
+        for input in [
+            r#"{"foo": 1}"#,
+            "[]",
+            "{\"span_id\": \"1\", \"name\": \"a\"}",
+        ] {
+            let err = sequence_from_spans(input).unwrap_err();
+            assert_eq!(err.message, "no spans found");
+        }
+    }
+
+    #[test]
+    fn test_import_reports_bad_line() {
+        let log = "{\"span_id\": \"1\", \"name\": \"a\"}\n{\"span_id\": ";
+        let err = sequence_from_spans(log).unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+}